@@ -0,0 +1,33 @@
+extern crate cult;
+
+// Reads 5 seconds of mono input with the blocking read API and prints the
+// level of each 100ms block.
+
+const SAMPLE_RATE: u32 = 44100;
+
+fn main() {
+    let ctx = cult::Context::new("Capture example", None).unwrap();
+
+    println!("context open with {} backend", ctx.backend_id());
+
+    let params = cult::StreamParams::<f32>::new(SAMPLE_RATE, 1, cult::ChannelLayout::Mono);
+    let min_latency = ctx.min_latency(params).expect("could not retrieve minimum latency");
+
+    let mut stm = cult::Stream::<f32>::new_input(
+        &ctx, "Capture",
+        None, params,
+        min_latency, SAMPLE_RATE as usize, Some(Box::new(cult::print_state_change))
+    ).expect("could not create audio stream");
+
+    stm.start().unwrap();
+
+    let mut block = vec![0_f32; SAMPLE_RATE as usize / 10];
+    for _ in 0 .. 50 {
+        let read = stm.read(&mut block).unwrap();
+        let sum: f32 = block[.. read].iter().map(|s| s * s).sum();
+        let rms = (sum / read.max(1) as f32).sqrt();
+        println!("rms: {:.4} (overruns: {})", rms, stm.overruns().unwrap());
+    }
+
+    stm.stop().unwrap();
+}
@@ -0,0 +1,65 @@
+extern crate cult;
+
+use cult::param::AtomicF32;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::{thread, time};
+
+// Sweeps a sine from the control thread through an atomic parameter, and
+// mutes it every other second with commands.
+
+const SAMPLE_RATE: f32 = 44100_f32;
+
+enum Command {
+    Mute(bool),
+}
+
+fn main() {
+    let ctx = cult::Context::new("Commands example", None).unwrap();
+
+    println!("context open with {} backend", ctx.backend_id());
+
+    let frequency = Arc::new(AtomicF32::new(220_f32));
+    let cb_frequency = frequency.clone();
+    let mut phase = 0_f32;
+    let mut muted = false;
+
+    let cb: cult::CommandCallback<f32, Command> =
+        Box::new(move |cmds, _: &[f32], outp: &mut [f32]| {
+        for cmd in cmds.drain() {
+            match cmd {
+                Command::Mute(m) => muted = m,
+            }
+        }
+        let w = std::f32::consts::PI * 2_f32 * cb_frequency.load(Ordering::Relaxed) / SAMPLE_RATE;
+        for s in outp.iter_mut() {
+            *s = if muted { 0_f32 } else { 0.5_f32 * phase.sin() };
+            phase += w;
+            if phase > std::f32::consts::PI * 2_f32 {
+                phase -= std::f32::consts::PI * 2_f32;
+            }
+        }
+        outp.len()
+    });
+
+    let params = cult::StreamParams::<f32>::new(SAMPLE_RATE as u32, 1, cult::ChannelLayout::Mono);
+    let min_latency = ctx.min_latency(params).expect("could not retrieve minimum latency");
+
+    let (stm, mut commands) = cult::Stream::<f32>::new_with_commands(
+        &ctx, "Commands",
+        None, None, None, Some(params),
+        min_latency, 16, cb, Some(Box::new(cult::print_state_change))
+    ).expect("could not create audio stream");
+
+    stm.start().unwrap();
+
+    for step in 0 .. 50 {
+        frequency.store(220_f32 + 10_f32 * step as f32, Ordering::Relaxed);
+        if step % 10 == 0 {
+            let _ = commands.send(Command::Mute(step % 20 != 0));
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+
+    stm.stop().unwrap();
+}
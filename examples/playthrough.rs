@@ -3,7 +3,9 @@ extern crate cult;
 
 use std::{thread, time};
 
-// 5 seconds of play through
+// 5 seconds of play through. With `--drift`, the input and output are opened
+// as two streams bridged by drift compensation, for devices that do not
+// share a clock.
 
 const SAMPLE_RATE: f32 = 44100_f32;
 
@@ -14,24 +16,35 @@ fn main() {
     println!("context open with {} backend", ctx.backend_id());
 
     let cb: cult::DataCallback<f32> = Box::new(move |ib: &[f32], ob: &mut [f32]| {
-        for s in 0 .. ob.len() {
-            ob[s] = ib[s];
-        }
+        ob.copy_from_slice(&ib[.. ob.len()]);
         ob.len()
     });
 
     let params = cult::StreamParams::<f32>::new(SAMPLE_RATE as u32, 1, cult::ChannelLayout::Mono);
     let min_latency = ctx.min_latency(params).expect("could not retrieve minimum latency");
+    let drift = std::env::args().any(|a| a == "--drift");
+    let in_params = if drift {
+        params.with_drift_compensation(cult::resampler::Quality::Sinc)
+    } else {
+        params
+    };
 
     let stm = cult::Stream::<f32>::new(
         &ctx, "Playthrough",
-        None, Some(params), None, Some(params),
+        None, Some(in_params), None, Some(params),
         min_latency, cb, Some(Box::new(cult::print_state_change))
     ).expect("could not create audio stream");
 
     stm.start().unwrap();
 
-    thread::sleep(time::Duration::from_millis(5000));
+    if drift {
+        for _ in 0 .. 5 {
+            thread::sleep(time::Duration::from_millis(1000));
+            println!("drift: {:.1} ppm", stm.drift_ppm().unwrap());
+        }
+    } else {
+        thread::sleep(time::Duration::from_millis(5000));
+    }
 
     stm.stop().unwrap();
 }
@@ -0,0 +1,453 @@
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::{Context as TaskContext, Poll};
+
+use atomic_waker::AtomicWaker;
+use futures_core::Stream as AsyncStream;
+use futures_sink::Sink;
+
+use ring::{ring_buffer, Consumer, Producer};
+use units::Latency;
+use {Context, DataCallback, DevId, Error, Result, Sample, State, StateCallback,
+     Stream, StreamParams};
+
+// Adapters that let async code feed or drain a cubeb stream. The audio thread
+// only touches the ring buffers and calls `AtomicWaker::wake`, which never
+// allocates.
+//
+// Stopping a stream pauses its adapter and starting it again resumes it:
+// flushes resolve while stopped, and captured batches keep coming after a
+// restart. A drained or failed stream ends them for good.
+
+struct Shared {
+    waker: AtomicWaker,
+    // stopped, drained or failed
+    ended: AtomicBool,
+    failed: AtomicBool,
+    drained: AtomicBool,
+    closed: AtomicBool,
+    overruns: AtomicUsize,
+    dropped_frames: AtomicUsize,
+}
+
+impl Shared {
+    fn new() -> Arc<Shared> {
+        Arc::new(Shared {
+            waker: AtomicWaker::new(),
+            ended: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            drained: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            overruns: AtomicUsize::new(0),
+            dropped_frames: AtomicUsize::new(0),
+        })
+    }
+
+    fn tracking_state_cb(shared: &Arc<Shared>, states: StateCallback) -> StateCallback {
+        let shared = shared.clone();
+        let mut states = states;
+        Box::new(move |state: State| {
+            match state {
+                State::Started => shared.ended.store(false, Ordering::Release),
+                State::Stopped => shared.ended.store(true, Ordering::Release),
+                State::Drained => {
+                    shared.drained.store(true, Ordering::Release);
+                    shared.ended.store(true, Ordering::Release);
+                }
+                State::Error => {
+                    shared.failed.store(true, Ordering::Release);
+                    shared.ended.store(true, Ordering::Release);
+                }
+            }
+            shared.waker.wake();
+            states(state);
+        })
+    }
+}
+
+// Every state change reported by cubeb, as an async stream.
+pub struct StateStream {
+    consumer: Consumer<State>,
+    shared: Arc<Shared>,
+}
+
+// Returns a state callback to hand to `Stream::new` and the stream of states
+// it produces. States are dropped if nobody polls for `capacity` changes.
+pub fn state_stream(capacity: usize) -> (StateCallback, StateStream) {
+    let (mut producer, consumer) = ring_buffer(capacity);
+    let shared = Shared::new();
+    let cb_shared = shared.clone();
+    let state_cb: StateCallback = Box::new(move |state: State| {
+        let _ = producer.push(state);
+        cb_shared.waker.wake();
+    });
+    (state_cb, StateStream { consumer, shared })
+}
+
+impl AsyncStream for StateStream {
+    type Item = State;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Option<State>> {
+        let this = self.get_mut();
+        if let Some(state) = this.consumer.pop() {
+            return Poll::Ready(Some(state));
+        }
+        this.shared.waker.register(cx.waker());
+        match this.consumer.pop() {
+            Some(state) => Poll::Ready(Some(state)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+// The queue between async writes and an output data callback, on its own so
+// that it can be driven without a device.
+pub struct OutputQueue<T: Sample> {
+    producer: Producer<T>,
+    channels: usize,
+    pending: Vec<T>,
+    shared: Arc<Shared>,
+}
+
+// Returns the data and state callbacks of an output stream with `channels`
+// channels, buffering `buffer_frames`, and the queue that feeds them. States
+// are passed on to `states`.
+pub fn output_queue<T: Sample>(channels: u32, buffer_frames: usize, states: StateCallback)
+        -> Result<(DataCallback<T>, StateCallback, OutputQueue<T>)> {
+    let channels = channels as usize;
+    if channels == 0 || buffer_frames == 0 {
+        return Err(Error::InvalidParameter);
+    }
+    let (producer, mut consumer) = ring_buffer(buffer_frames * channels);
+    let shared = Shared::new();
+
+    let cb_shared = shared.clone();
+    let data_cb: DataCallback<T> = Box::new(move |_: &[T], obuf: &mut [T]| {
+        let read = consumer.pop_slice(obuf) / channels * channels;
+        cb_shared.waker.wake();
+        if cb_shared.closed.load(Ordering::Acquire) && consumer.is_empty() {
+            // returning a short count makes cubeb drain the stream
+            return read / channels;
+        }
+        for s in obuf[read ..].iter_mut() {
+            *s = T::default();
+        }
+        obuf.len() / channels
+    });
+
+    let state_cb = Shared::tracking_state_cb(&shared, states);
+    Ok((data_cb, state_cb, OutputQueue { producer, channels, pending: Vec::new(), shared }))
+}
+
+impl<T: Sample> OutputQueue<T> {
+    // Queues as many whole frames of `buf` as fit, waiting for room if none do.
+    pub fn poll_write(&mut self, cx: &mut TaskContext, buf: &[T]) -> Poll<Result<usize>> {
+        if self.shared.failed.load(Ordering::Acquire) {
+            return Poll::Ready(Err(Error::Undefined));
+        }
+        let len = buf.len() / self.channels * self.channels;
+        if len == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let room = self.producer.free_len() / self.channels * self.channels;
+        if room == 0 {
+            self.shared.waker.register(cx.waker());
+            if self.producer.free_len() < self.channels {
+                return Poll::Pending;
+            }
+        }
+        let room = self.producer.free_len() / self.channels * self.channels;
+        Poll::Ready(Ok(self.producer.push_slice(&buf[.. len.min(room)])))
+    }
+
+    // Future that resolves once all of `buf` has been queued.
+    pub fn write<'a>(&'a mut self, buf: &'a [T]) -> Write<'a, T> {
+        Write { queue: self, buf, written: 0 }
+    }
+
+    fn poll_pending(&mut self, cx: &mut TaskContext) -> Poll<Result<()>> {
+        while !self.pending.is_empty() {
+            let pending = mem::take(&mut self.pending);
+            let res = self.poll_write(cx, &pending);
+            self.pending = pending;
+            let written = match res {
+                Poll::Ready(Ok(written)) => written,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            self.pending.drain(.. written);
+            if written == 0 {
+                // trailing partial frame, it can never be played
+                self.pending.clear();
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Nothing in the adapters is structurally pinned.
+impl<T: Sample> Unpin for OutputQueue<T> {}
+
+pub struct Write<'a, T: Sample + 'a> {
+    queue: &'a mut OutputQueue<T>,
+    buf: &'a [T],
+    written: usize,
+}
+
+impl<'a, T: Sample> Future for Write<'a, T> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let channels = this.queue.channels;
+        while this.buf.len() - this.written >= channels {
+            match this.queue.poll_write(cx, &this.buf[this.written ..]) {
+                Poll::Ready(Ok(n)) => this.written += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Sample> Sink<Vec<T>> for OutputQueue<T> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<T>) -> Result<()> {
+        let this = self.get_mut();
+        if this.pending.is_empty() {
+            this.pending = item;
+        } else {
+            this.pending.extend_from_slice(&item);
+        }
+        Ok(())
+    }
+
+    // Resolves once everything sent so far has been consumed by the device,
+    // or right away while the stream is stopped.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        if this.producer.is_empty() || this.shared.ended.load(Ordering::Acquire) {
+            return Poll::Ready(Ok(()));
+        }
+        this.shared.waker.register(cx.waker());
+        if this.producer.is_empty() || this.shared.ended.load(Ordering::Acquire) {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    // Lets the stream drain once the queued samples have played.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        this.shared.closed.store(true, Ordering::Release);
+        Pin::new(this).poll_flush(cx)
+    }
+}
+
+// Output stream fed by async writes of interleaved samples.
+pub struct AsyncOutput<T: Sample> {
+    stream: Stream<T>,
+    queue: OutputQueue<T>,
+    states: Option<StateStream>,
+}
+
+impl<T: Sample> AsyncOutput<T> {
+    pub fn new<L: Into<Latency>>(ctx: &Context, stream_name: &str,
+               out_device: Option<&DevId>, out_params: StreamParams<T>,
+               latency: L, buffer_frames: usize) -> Result<AsyncOutput<T>> {
+        let (state_cb, states) = state_stream(16);
+        let (data_cb, state_cb, queue) =
+            output_queue(out_params.channels, buffer_frames, state_cb)?;
+        let stream = Stream::new(ctx, stream_name,
+                                 None, None, out_device, Some(out_params),
+                                 latency, data_cb, Some(state_cb))?;
+        Ok(AsyncOutput { stream, queue, states: Some(states) })
+    }
+
+    pub fn stream(&self) -> &Stream<T> {
+        &self.stream
+    }
+
+    // The state changes of the underlying stream; can only be taken once.
+    pub fn take_states(&mut self) -> Option<StateStream> {
+        self.states.take()
+    }
+
+    pub fn poll_write(&mut self, cx: &mut TaskContext, buf: &[T]) -> Poll<Result<usize>> {
+        self.queue.poll_write(cx, buf)
+    }
+
+    pub fn write<'a>(&'a mut self, buf: &'a [T]) -> Write<'a, T> {
+        self.queue.write(buf)
+    }
+}
+
+impl<T: Sample> Unpin for AsyncOutput<T> {}
+
+impl<T: Sample> Sink<Vec<T>> for AsyncOutput<T> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().queue).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<T>) -> Result<()> {
+        Pin::new(&mut self.get_mut().queue).start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().queue).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().queue).poll_close(cx)
+    }
+}
+
+// The queue between an input data callback and async reads, on its own so
+// that it can be driven without a device.
+pub struct InputQueue<T: Sample> {
+    consumer: Consumer<T>,
+    channels: usize,
+    shared: Arc<Shared>,
+    done: bool,
+}
+
+// Returns the data and state callbacks of an input stream with `channels`
+// channels, buffering `buffer_frames`, and the queue they fill. States are
+// passed on to `states`.
+pub fn input_queue<T: Sample>(channels: u32, buffer_frames: usize, states: StateCallback)
+        -> Result<(DataCallback<T>, StateCallback, InputQueue<T>)> {
+    let channels = channels as usize;
+    if channels == 0 || buffer_frames == 0 {
+        return Err(Error::InvalidParameter);
+    }
+    let (mut producer, consumer) = ring_buffer(buffer_frames * channels);
+    let shared = Shared::new();
+
+    let cb_shared = shared.clone();
+    let data_cb: DataCallback<T> = Box::new(move |ibuf: &[T], _: &mut [T]| {
+        let room = producer.free_len() / channels * channels;
+        let pushed = producer.push_slice(&ibuf[.. ibuf.len().min(room)]);
+        if pushed < ibuf.len() {
+            cb_shared.overruns.fetch_add(1, Ordering::Relaxed);
+            cb_shared.dropped_frames.fetch_add((ibuf.len() - pushed) / channels,
+                                               Ordering::Relaxed);
+        }
+        cb_shared.waker.wake();
+        ibuf.len() / channels
+    });
+
+    let state_cb = Shared::tracking_state_cb(&shared, states);
+    Ok((data_cb, state_cb, InputQueue { consumer, channels, shared, done: false }))
+}
+
+impl<T: Sample> InputQueue<T> {
+    // Number of callbacks that found the queue full because nobody polled,
+    // and the total number of frames lost that way.
+    pub fn overruns(&self) -> usize {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_frames(&self) -> usize {
+        self.shared.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    fn finished(&self) -> bool {
+        self.shared.failed.load(Ordering::Acquire) || self.shared.drained.load(Ordering::Acquire)
+    }
+}
+
+impl<T: Sample> Unpin for InputQueue<T> {}
+
+// Each batch of captured interleaved samples, until the stream drains or
+// fails; a stopped stream yields nothing until started again.
+impl<T: Sample> AsyncStream for InputQueue<T> {
+    type Item = Vec<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Option<Vec<T>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if this.consumer.len() < this.channels {
+            this.shared.waker.register(cx.waker());
+            if this.consumer.len() < this.channels {
+                if this.finished() {
+                    this.done = true;
+                    return Poll::Ready(None);
+                }
+                return Poll::Pending;
+            }
+        }
+        let len = this.consumer.len() / this.channels * this.channels;
+        let mut buf = vec![T::default(); len];
+        this.consumer.pop_slice(&mut buf);
+        Poll::Ready(Some(buf))
+    }
+}
+
+// Input stream yielding each batch of captured interleaved samples.
+pub struct AsyncInput<T: Sample> {
+    stream: Stream<T>,
+    queue: InputQueue<T>,
+    states: Option<StateStream>,
+}
+
+impl<T: Sample> AsyncInput<T> {
+    pub fn new<L: Into<Latency>>(ctx: &Context, stream_name: &str,
+               in_device: Option<&DevId>, in_params: StreamParams<T>,
+               latency: L, buffer_frames: usize) -> Result<AsyncInput<T>> {
+        let (state_cb, states) = state_stream(16);
+        let (data_cb, state_cb, queue) =
+            input_queue(in_params.channels, buffer_frames, state_cb)?;
+        let stream = Stream::new(ctx, stream_name,
+                                 in_device, Some(in_params), None, None,
+                                 latency, data_cb, Some(state_cb))?;
+        Ok(AsyncInput { stream, queue, states: Some(states) })
+    }
+
+    pub fn stream(&self) -> &Stream<T> {
+        &self.stream
+    }
+
+    pub fn take_states(&mut self) -> Option<StateStream> {
+        self.states.take()
+    }
+
+    pub fn overruns(&self) -> usize {
+        self.queue.overruns()
+    }
+
+    pub fn dropped_frames(&self) -> usize {
+        self.queue.dropped_frames()
+    }
+}
+
+impl<T: Sample> Unpin for AsyncInput<T> {}
+
+impl<T: Sample> AsyncStream for AsyncInput<T> {
+    type Item = Vec<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Option<Vec<T>>> {
+        Pin::new(&mut self.get_mut().queue).poll_next(cx)
+    }
+}
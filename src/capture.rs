@@ -0,0 +1,135 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ring::{ring_buffer, Consumer};
+use {DataCallback, Sample, State, StateCallback};
+
+// How long a blocked reader sleeps before re-checking the ring: the audio
+// thread wakes it without taking the lock, so a wakeup can slip in between
+// the check and the wait. Waking is a syscall, so the audio thread only does
+// it when a reader is parked.
+const READ_WAIT: Duration = Duration::from_millis(10);
+
+struct Shared {
+    running: AtomicBool,
+    failed: AtomicBool,
+    overruns: AtomicUsize,
+    dropped_frames: AtomicUsize,
+    // a reader is blocked in `read`
+    waiting: AtomicBool,
+    lock: Mutex<()>,
+    cv: Condvar,
+}
+
+pub struct InputReader<T: Sample> {
+    consumer: Consumer<T>,
+    channels: usize,
+    shared: Arc<Shared>,
+}
+
+// What `Stream::new_input` is made of: the reader, and the callbacks of the
+// stream it reads from.
+pub fn input_reader<T: Sample>(channels: u32, buffer_frames: usize,
+                               state_cb: Option<StateCallback>)
+        -> (InputReader<T>, DataCallback<T>, StateCallback) {
+    let channels = channels as usize;
+    let (mut producer, consumer) = ring_buffer(buffer_frames * channels);
+    let shared = Arc::new(Shared {
+        running: AtomicBool::new(false),
+        failed: AtomicBool::new(false),
+        overruns: AtomicUsize::new(0),
+        dropped_frames: AtomicUsize::new(0),
+        waiting: AtomicBool::new(false),
+        lock: Mutex::new(()),
+        cv: Condvar::new(),
+    });
+
+    let cb_shared = shared.clone();
+    let data_cb: DataCallback<T> = Box::new(move |ibuf: &[T], _: &mut [T]| {
+        let room = producer.free_len() / channels * channels;
+        let pushed = producer.push_slice(&ibuf[.. ibuf.len().min(room)]);
+        if pushed < ibuf.len() {
+            cb_shared.overruns.fetch_add(1, Ordering::Relaxed);
+            cb_shared.dropped_frames.fetch_add((ibuf.len() - pushed) / channels,
+                                               Ordering::Relaxed);
+        }
+        if cb_shared.waiting.load(Ordering::SeqCst) {
+            cb_shared.cv.notify_one();
+        }
+        ibuf.len() / channels
+    });
+
+    let st_shared = shared.clone();
+    let mut state_cb = state_cb;
+    let wrapped_state_cb: StateCallback = Box::new(move |state: State| {
+        match state {
+            State::Started => st_shared.running.store(true, Ordering::Release),
+            State::Stopped | State::Drained => {
+                st_shared.running.store(false, Ordering::Release)
+            }
+            State::Error => {
+                st_shared.failed.store(true, Ordering::Release);
+                st_shared.running.store(false, Ordering::Release);
+            }
+        }
+        st_shared.cv.notify_one();
+        if let Some(ref mut cb) = state_cb {
+            cb(state);
+        }
+    });
+
+    let reader = InputReader {
+        consumer,
+        channels,
+        shared,
+    };
+    (reader, data_cb, wrapped_state_cb)
+}
+
+impl<T: Sample> InputReader<T> {
+    pub fn set_running(&self, running: bool) {
+        self.shared.running.store(running, Ordering::Release);
+        self.shared.cv.notify_one();
+    }
+
+    pub fn failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Acquire)
+    }
+
+    pub fn available_frames(&self) -> usize {
+        self.consumer.len() / self.channels
+    }
+
+    pub fn overruns(&self) -> usize {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_frames(&self) -> usize {
+        self.shared.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    pub fn try_read(&mut self, buf: &mut [T]) -> usize {
+        let len = buf.len() / self.channels * self.channels;
+        self.consumer.pop_slice(&mut buf[.. len])
+    }
+
+    pub fn read(&mut self, buf: &mut [T]) -> usize {
+        let len = buf.len() / self.channels * self.channels;
+        let mut read = 0;
+        loop {
+            read += self.consumer.pop_slice(&mut buf[read .. len]);
+            if read == len || !self.shared.running.load(Ordering::Acquire) {
+                // drain whatever arrived before the stream stopped
+                read += self.consumer.pop_slice(&mut buf[read .. len]);
+                return read;
+            }
+            let guard = self.shared.lock.lock().unwrap();
+            self.shared.waiting.store(true, Ordering::SeqCst);
+            if self.consumer.len() < len - read {
+                let _ = self.shared.cv.wait_timeout(guard, READ_WAIT).unwrap();
+            }
+            self.shared.waiting.store(false, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use {Channel, ChannelLayout, Error, Result, Sample};
+
+// Up and down mixing between channel layouts, with the ITU-R BS.775
+// coefficients: a missing center folds into left and right at -3dB,
+// surrounds fold into the front at -3dB, and mono spreads to left and right
+// at -3dB when there is no center speaker.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Lfe {
+    // The LFE channel is not sent anywhere when the target has none.
+    Drop,
+    // Mixed into the main channels at the given gain when the target has no
+    // LFE channel.
+    MixToMains(f32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MixOptions {
+    pub lfe: Lfe,
+    // Scales each output channel so that the sum of its coefficients does not
+    // exceed 1, trading loudness for never clipping.
+    pub normalize: bool,
+}
+
+impl Default for MixOptions {
+    fn default() -> MixOptions {
+        MixOptions { lfe: Lfe::Drop, normalize: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelMixer {
+    in_channels: usize,
+    out_channels: usize,
+    // out_channels rows of in_channels coefficients
+    matrix: Vec<f32>,
+}
+
+fn position(layout: &[Channel], c: Channel) -> Option<usize> {
+    layout.iter().position(|&x| x == c)
+}
+
+// Adds the contribution of source channel `c` at `gain` to `row`, following
+// the fallbacks when the target layout lacks that speaker.
+fn route(c: Channel, gain: f32, dst: &[Channel], options: &MixOptions, row: &mut [f32]) {
+    use Channel::*;
+    if let Some(i) = position(dst, c) {
+        row[i] += gain;
+        return;
+    }
+    let h = FRAC_1_SQRT_2;
+    match c {
+        Mono => {
+            if position(dst, Center).is_some() {
+                route(Center, gain, dst, options, row);
+            } else {
+                route(Left, gain * h, dst, options, row);
+                route(Right, gain * h, dst, options, row);
+            }
+        }
+        Left | Right => {
+            if position(dst, Mono).is_some() {
+                route(Mono, gain * h, dst, options, row);
+            } else if position(dst, Center).is_some() {
+                route(Center, gain * h, dst, options, row);
+            }
+        }
+        Center => {
+            if position(dst, Left).is_some() {
+                route(Left, gain * h, dst, options, row);
+                route(Right, gain * h, dst, options, row);
+            } else {
+                route(Mono, gain, dst, options, row);
+            }
+        }
+        LFE => {
+            if let Lfe::MixToMains(lfe_gain) = options.lfe {
+                if position(dst, Left).is_some() {
+                    route(Left, gain * lfe_gain * h, dst, options, row);
+                    route(Right, gain * lfe_gain * h, dst, options, row);
+                } else if position(dst, Center).is_some() {
+                    route(Center, gain * lfe_gain, dst, options, row);
+                } else {
+                    route(Mono, gain * lfe_gain, dst, options, row);
+                }
+            }
+        }
+        LeftSurround | RearLeftSurround => {
+            let other = if c == LeftSurround { RearLeftSurround } else { LeftSurround };
+            if position(dst, other).is_some() {
+                route(other, gain, dst, options, row);
+            } else {
+                route(Left, gain * h, dst, options, row);
+            }
+        }
+        RightSurround | RearRightSurround => {
+            let other = if c == RightSurround { RearRightSurround } else { RightSurround };
+            if position(dst, other).is_some() {
+                route(other, gain, dst, options, row);
+            } else {
+                route(Right, gain * h, dst, options, row);
+            }
+        }
+        RearCenter => {
+            if position(dst, LeftSurround).is_some() {
+                route(LeftSurround, gain * h, dst, options, row);
+                route(RightSurround, gain * h, dst, options, row);
+            } else if position(dst, RearLeftSurround).is_some() {
+                route(RearLeftSurround, gain * h, dst, options, row);
+                route(RearRightSurround, gain * h, dst, options, row);
+            } else {
+                route(Left, gain * h * h, dst, options, row);
+                route(Right, gain * h * h, dst, options, row);
+            }
+        }
+    }
+}
+
+impl ChannelMixer {
+    pub fn new(from: ChannelLayout, to: ChannelLayout, options: MixOptions)
+            -> Result<ChannelMixer> {
+        let src = from.channels();
+        let dst = to.channels();
+        if src.is_empty() || dst.is_empty() {
+            return Err(Error::InvalidParameter);
+        }
+        let mut matrix = vec![0_f32; src.len() * dst.len()];
+        let mut column = vec![0_f32; dst.len()];
+        for (i, &c) in src.iter().enumerate() {
+            for v in column.iter_mut() {
+                *v = 0_f32;
+            }
+            route(c, 1_f32, dst, &options, &mut column);
+            for (o, v) in column.iter().enumerate() {
+                matrix[o * src.len() + i] = *v;
+            }
+        }
+        let mut mixer = ChannelMixer {
+            in_channels: src.len(),
+            out_channels: dst.len(),
+            matrix,
+        };
+        if options.normalize {
+            mixer.normalize();
+        }
+        Ok(mixer)
+    }
+
+    // `matrix` has `out_channels` rows of `in_channels` coefficients.
+    pub fn from_matrix(in_channels: usize, out_channels: usize, matrix: Vec<f32>)
+            -> Result<ChannelMixer> {
+        if in_channels == 0 || out_channels == 0 || matrix.len() != in_channels * out_channels {
+            return Err(Error::InvalidParameter);
+        }
+        Ok(ChannelMixer { in_channels, out_channels, matrix })
+    }
+
+    pub fn in_channels(&self) -> usize {
+        self.in_channels
+    }
+
+    pub fn out_channels(&self) -> usize {
+        self.out_channels
+    }
+
+    pub fn matrix(&self) -> &[f32] {
+        &self.matrix
+    }
+
+    pub fn coefficient(&self, output: usize, input: usize) -> f32 {
+        self.matrix[output * self.in_channels + input]
+    }
+
+    pub fn set_coefficient(&mut self, output: usize, input: usize, gain: f32) {
+        self.matrix[output * self.in_channels + input] = gain;
+    }
+
+    // Replaces all coefficients, without reallocating.
+    pub fn set_matrix(&mut self, matrix: &[f32]) -> Result<()> {
+        if matrix.len() != self.matrix.len() {
+            return Err(Error::InvalidParameter);
+        }
+        self.matrix.copy_from_slice(matrix);
+        Ok(())
+    }
+
+    pub fn normalize(&mut self) {
+        for row in self.matrix.chunks_mut(self.in_channels) {
+            let sum: f32 = row.iter().map(|c| c.abs()).sum();
+            if sum > 1_f32 {
+                for c in row.iter_mut() {
+                    *c /= sum;
+                }
+            }
+        }
+    }
+
+    // Mixes as many whole frames as both buffers hold, returns that count.
+    pub fn mix<T: Sample>(&self, input: &[T], output: &mut [T]) -> usize {
+        let frames = (input.len() / self.in_channels).min(output.len() / self.out_channels);
+        let frame_pairs = input.chunks(self.in_channels)
+            .zip(output.chunks_mut(self.out_channels))
+            .take(frames);
+        for (inp, out) in frame_pairs {
+            for (o, row) in out.iter_mut().zip(self.matrix.chunks(self.in_channels)) {
+                let mut acc = 0_f32;
+                for (s, c) in inp.iter().zip(row.iter()) {
+                    acc += s.to_f32() * c;
+                }
+                *o = T::from_f32(acc);
+            }
+        }
+        frames
+    }
+}
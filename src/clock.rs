@@ -0,0 +1,164 @@
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use {Error, Result, Sample, Stream};
+
+// Maps the frames of an output stream to wall-clock time, e.g. to show the
+// video frame matching what is heard. `Stream::position` only moves once per
+// callback, so between two changes the position is extrapolated from the
+// time it was last seen to change, at the stream's rate.
+//
+// Frames are counted at the callback's rate. The audible frame is the
+// position minus the output latency. When the position jumps by more than
+// `JUMP_THRESHOLD` from what was expected, e.g. after a device change, the
+// clock keeps running from where it was instead, so the frames it reports
+// then differ from the stream's by a constant offset.
+
+const JUMP_THRESHOLD: Duration = Duration::from_millis(200);
+// extrapolation stops this long after the position last changed, so that a
+// stopped or starved stream does not look like it keeps playing
+const MAX_EXTRAPOLATION: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct ClockEstimator {
+    rate: f64,
+    // audible frame, in clock frames, at the given instant
+    anchor: Option<(f64, Instant)>,
+    last_position: Option<u64>,
+    last_latency: u32,
+    // clock frames minus stream frames
+    offset: f64,
+    last_reported: f64,
+    jumps: usize,
+}
+
+fn seconds_between(from: Instant, to: Instant) -> f64 {
+    if to >= from {
+        (to - from).as_secs_f64()
+    } else {
+        -(from - to).as_secs_f64()
+    }
+}
+
+impl ClockEstimator {
+    pub fn new(rate: u32) -> ClockEstimator {
+        ClockEstimator {
+            rate: rate as f64,
+            anchor: None,
+            last_position: None,
+            last_latency: 0,
+            offset: 0.0,
+            last_reported: 0.0,
+            jumps: 0,
+        }
+    }
+
+    // Feeds what `Stream::position` and `Stream::latency` returned at `now`.
+    pub fn observe(&mut self, position: u64, latency: u32, now: Instant) {
+        if self.last_position == Some(position) && self.last_latency == latency {
+            return;
+        }
+        self.last_position = Some(position);
+        self.last_latency = latency;
+        let audible = position.saturating_sub(latency as u64) as f64 + self.offset;
+        if self.anchor.is_some() {
+            let expected = self.extrapolate(now);
+            if (audible - expected).abs() > JUMP_THRESHOLD.as_secs_f64() * self.rate {
+                self.offset += expected - audible;
+                self.jumps += 1;
+                self.anchor = Some((expected, now));
+                return;
+            }
+        }
+        self.anchor = Some((audible, now));
+    }
+
+    fn extrapolate(&self, now: Instant) -> f64 {
+        match self.anchor {
+            Some((frame, at)) => {
+                let elapsed = seconds_between(at, now).clamp(0.0, MAX_EXTRAPOLATION.as_secs_f64());
+                frame + elapsed * self.rate
+            }
+            None => 0.0,
+        }
+    }
+
+    // Never smaller than a previous result.
+    pub fn now_playing_frame(&mut self, now: Instant) -> u64 {
+        let frame = self.extrapolate(now).max(self.last_reported);
+        self.last_reported = frame;
+        frame as u64
+    }
+
+    // When `frame` is, was or will be audible, at the current playback speed.
+    pub fn frame_to_instant(&self, frame: u64) -> Option<Instant> {
+        let (anchor_frame, at) = self.anchor?;
+        let seconds = (frame as f64 - anchor_frame) / self.rate;
+        if seconds >= 0.0 {
+            at.checked_add(Duration::from_secs_f64(seconds))
+        } else {
+            at.checked_sub(Duration::from_secs_f64(-seconds))
+        }
+    }
+
+    pub fn instant_to_frame(&self, instant: Instant) -> Option<u64> {
+        let (anchor_frame, at) = self.anchor?;
+        Some((anchor_frame + seconds_between(at, instant) * self.rate).max(0.0) as u64)
+    }
+
+    // Position jumps absorbed so far.
+    pub fn jumps(&self) -> usize {
+        self.jumps
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset as i64
+    }
+}
+
+pub struct StreamClock<'a, T: Sample> {
+    stream: &'a Stream<T>,
+    estimator: RefCell<ClockEstimator>,
+}
+
+impl<'a, T: Sample> StreamClock<'a, T> {
+    pub fn new(stream: &'a Stream<T>) -> StreamClock<'a, T> {
+        StreamClock {
+            stream,
+            estimator: RefCell::new(ClockEstimator::new(stream.rate())),
+        }
+    }
+
+    fn poll(&self, now: Instant) -> Result<()> {
+        let position = self.stream.position()?;
+        let latency = self.stream.latency()?;
+        self.estimator.borrow_mut().observe(position, latency, now);
+        Ok(())
+    }
+
+    pub fn now_playing_frame(&self) -> Result<u64> {
+        let now = Instant::now();
+        self.poll(now)?;
+        Ok(self.estimator.borrow_mut().now_playing_frame(now))
+    }
+
+    // Errs with `Undefined` before the stream reported anything.
+    pub fn frame_to_instant(&self, frame: u64) -> Result<Instant> {
+        self.poll(Instant::now())?;
+        self.estimator.borrow().frame_to_instant(frame).ok_or(Error::Undefined)
+    }
+
+    pub fn instant_to_frame(&self, instant: Instant) -> Result<u64> {
+        self.poll(Instant::now())?;
+        self.estimator.borrow().instant_to_frame(instant).ok_or(Error::Undefined)
+    }
+
+    pub fn jumps(&self) -> usize {
+        self.estimator.borrow().jumps()
+    }
+
+    // Clock frames minus stream frames, after jumps.
+    pub fn offset(&self) -> i64 {
+        self.estimator.borrow().offset()
+    }
+}
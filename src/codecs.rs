@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use symphonia;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{self, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::{Error as CodecError, SeekErrorKind};
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use player::{Decoder, Error, Result};
+use wav;
+use ChannelLayout;
+
+// FLAC, Ogg Vorbis and MP3 files, decoded with symphonia, behind the
+// `codecs` feature.
+
+impl From<CodecError> for Error {
+    fn from(e: CodecError) -> Error {
+        match e {
+            CodecError::IoError(e) => Error::File(wav::Error::Io(e)),
+            e => Error::Decode(e.to_string()),
+        }
+    }
+}
+
+pub struct CodecDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn codecs::Decoder>,
+    track: u32,
+    rate: u32,
+    channels: u32,
+    layout: ChannelLayout,
+    frames: Option<u64>,
+    position: u64,
+    // decoded but not read yet, from `offset` on
+    pending: Vec<f32>,
+    offset: usize,
+    // after a seek, frames of packets from before this one are dropped
+    skip_to: u64,
+    // sought past the end
+    ended: bool,
+}
+
+impl CodecDecoder {
+    // Guesses the format from the contents, and the extension if any.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CodecDecoder> {
+        let path = path.as_ref();
+        let stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        let probed = symphonia::default::get_probe()
+            .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())?;
+        let format = probed.format;
+        let track = format.tracks().iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| Error::Decode("no audio track".to_string()))?;
+        let params = track.codec_params.clone();
+        let decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default())?;
+        let rate = params.sample_rate.unwrap_or(0);
+        let channels = params.channels.map_or(0, |c| c.count() as u32);
+        if rate == 0 || channels == 0 {
+            return Err(Error::Decode("unknown rate or channel count".to_string()));
+        }
+        // symphonia's channel bits are those of WAVE_FORMAT_EXTENSIBLE masks
+        let layout = params.channels.map_or(ChannelLayout::Undefined, |c| {
+            wav::layout_from_mask(c.bits(), channels as u16)
+        });
+        Ok(CodecDecoder {
+            track: track.id,
+            format,
+            decoder,
+            rate,
+            channels,
+            layout,
+            frames: params.n_frames,
+            position: 0,
+            pending: Vec::new(),
+            offset: 0,
+            skip_to: 0,
+            ended: false,
+        })
+    }
+
+    // Decodes the next packet of the track into `pending`, returns false at
+    // the end. Undecodable packets are skipped.
+    fn decode_next(&mut self) -> Result<bool> {
+        let ch = self.channels as usize;
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(CodecError::IoError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(false);
+                }
+                Err(CodecError::ResetRequired) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(CodecError::DecodeError(_)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            self.pending.clear();
+            self.pending.extend_from_slice(buf.samples());
+            // by timestamp, as decoders can output nothing while they prime
+            let early = self.skip_to.saturating_sub(packet.ts()) as usize;
+            self.offset = early.min(self.pending.len() / ch) * ch;
+            return Ok(true);
+        }
+    }
+}
+
+// Readers refuse seeks past the end, or run out of data looking for it.
+fn past_end(e: &CodecError) -> bool {
+    match *e {
+        CodecError::SeekError(SeekErrorKind::OutOfRange) => true,
+        CodecError::IoError(ref e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+impl Decoder for CodecDecoder {
+    fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+        let ch = self.channels as usize;
+        let wanted = out.len() / ch;
+        let mut done = 0;
+        while done < wanted && !self.ended {
+            if self.offset == self.pending.len() && !self.decode_next()? {
+                break;
+            }
+            let available = (self.pending.len() - self.offset) / ch;
+            let frames = available.min(wanted - done);
+            out[done * ch .. (done + frames) * ch]
+                .copy_from_slice(&self.pending[self.offset .. self.offset + frames * ch]);
+            self.offset += frames * ch;
+            done += frames;
+        }
+        self.position += done as u64;
+        Ok(done)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        let to = SeekTo::TimeStamp { ts: frame, track_id: self.track };
+        self.decoder.reset();
+        self.pending.clear();
+        self.offset = 0;
+        let seeked = match self.format.seek(SeekMode::Accurate, to) {
+            Ok(seeked) => seeked,
+            // past the end, the next read ends the stream
+            Err(ref e) if past_end(e) => {
+                self.ended = true;
+                self.position = self.frames.map_or(frame, |frames| frame.min(frames));
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.ended = false;
+        self.skip_to = seeked.required_ts;
+        self.position = seeked.required_ts;
+        Ok(())
+    }
+}
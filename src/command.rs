@@ -0,0 +1,59 @@
+use ring::{ring_buffer, Consumer, Producer};
+
+// Bounded, wait-free channel carrying commands from one control thread to the
+// audio callback. Sending never blocks: when the queue is full the command is
+// handed back to the caller.
+
+pub struct CommandSender<C> {
+    producer: Producer<C>,
+}
+
+pub struct CommandReceiver<C> {
+    consumer: Consumer<C>,
+}
+
+pub fn command_channel<C: Send>(capacity: usize) -> (CommandSender<C>, CommandReceiver<C>) {
+    let (producer, consumer) = ring_buffer(capacity);
+    (CommandSender { producer }, CommandReceiver { consumer })
+}
+
+impl<C> CommandSender<C> {
+    pub fn send(&mut self, command: C) -> Result<(), C> {
+        self.producer.push(command)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.producer.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.producer.capacity()
+    }
+}
+
+impl<C> CommandReceiver<C> {
+    pub fn try_recv(&mut self) -> Option<C> {
+        self.consumer.pop()
+    }
+
+    // Iterates over the commands queued so far. Commands sent while draining
+    // are picked up too, so a flooding sender can keep this going.
+    pub fn drain(&mut self) -> Drain<'_, C> {
+        Drain { receiver: self }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.consumer.len()
+    }
+}
+
+pub struct Drain<'a, C: 'a> {
+    receiver: &'a mut CommandReceiver<C>,
+}
+
+impl<'a, C> Iterator for Drain<'a, C> {
+    type Item = C;
+    fn next(&mut self) -> Option<C> {
+        self.receiver.try_recv()
+    }
+}
@@ -0,0 +1,576 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ring::{ring_buffer, Consumer, Producer};
+use tones::{Tone, ToneSpec, DTMF_COLUMNS, DTMF_KEYS, DTMF_ROWS};
+use units::Frames;
+use {DataCallback, Sample};
+
+// DTMF keys and call progress tones in captured audio, found with Goertzel
+// filters over fixed blocks of the (downmixed) input. DTMF blocks are
+// 12.75ms long, as 102 samples at 8kHz, which is enough to tell the key
+// frequencies apart; call progress blocks are 50ms long so that close pairs
+// like 440 and 480Hz separate.
+//
+// A block holds a key when one frequency of each group stands out, both are
+// above `Options::min_level`, within the twist limits, and carry most of the
+// block's energy. A key is down once seen in enough blocks in a row to last
+// `Options::min_key_duration`, and up once missing as long.
+//
+// Call progress tones are told apart by their frequencies and cadence, from
+// the tables of `Options::country`. Short cadences have to repeat before
+// they are reported, so that e.g. busy and congestion tones that share their
+// first on and off periods are not mixed up. Frequencies closer than the
+// blocks resolve, as Australia's 413, 425 and 438Hz, are matched loosely,
+// and amplitude modulated tones are measured with their sidebands.
+//
+// `Detector::process` runs on the audio thread: it doesn't allocate, and
+// events reach the control thread through a ring buffer.
+
+const EVENTS: usize = 64;
+const MAX_FREQUENCIES: usize = 8;
+const MAX_RUNS: usize = 8;
+const MIN_MATCH_RUNS: usize = 4;
+const MIN_MATCH_SECONDS: u64 = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    // Frames counted from the start of the detection.
+    KeyDown { key: char, frame: u64 },
+    KeyUp { key: char, frame: u64 },
+    // at the start of the first matching period
+    Tone { tone: Tone, frame: u64 },
+    ToneEnded { tone: Tone, frame: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    // Lowest peak level of each DTMF or call progress frequency, in dBFS.
+    pub min_level: f32,
+    // Largest level difference between the high and the low group, in dB,
+    // when the high group is louder (forward) or quieter (reverse).
+    pub forward_twist: f32,
+    pub reverse_twist: f32,
+    // Share of a block's energy the detected frequencies must carry.
+    pub min_energy_ratio: f32,
+    // Shortest key press and gap between presses.
+    pub min_key_duration: Duration,
+    // ISO 3166 code of the call progress tones to look for, if any.
+    pub country: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            min_level: -30.0,
+            forward_twist: 8.0,
+            reverse_twist: 4.0,
+            min_energy_ratio: 0.7,
+            min_key_duration: Duration::from_millis(40),
+            country: None,
+        }
+    }
+}
+
+fn db_to_ratio(db: f32) -> f32 {
+    10_f32.powf(db / 20.0)
+}
+
+#[derive(Copy, Clone)]
+struct Goertzel {
+    coeff: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Goertzel {
+    fn new(frequency: f32, rate: u32) -> Goertzel {
+        let w = 2.0 * ::std::f64::consts::PI * frequency as f64 / rate as f64;
+        Goertzel { coeff: (2.0 * w.cos()) as f32, s1: 0.0, s2: 0.0 }
+    }
+
+    fn push(&mut self, x: f32) {
+        let s = x + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s;
+    }
+
+    // Peak amplitude of the frequency over the last `n` samples, and resets.
+    fn take_amplitude(&mut self, n: usize) -> f32 {
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        2.0 * power.max(0.0).sqrt() / n as f32
+    }
+}
+
+// Goertzel filters run over blocks of `size` samples.
+struct Bank {
+    filters: [Goertzel; MAX_FREQUENCIES],
+    amplitudes: [f32; MAX_FREQUENCIES],
+    count: usize,
+    size: usize,
+    filled: usize,
+    energy: f32,
+}
+
+impl Bank {
+    fn new(frequencies: &[f32], rate: u32, size: usize) -> Bank {
+        let mut filters = [Goertzel::new(0.0, rate); MAX_FREQUENCIES];
+        for (g, &f) in filters.iter_mut().zip(frequencies) {
+            *g = Goertzel::new(f, rate);
+        }
+        Bank {
+            filters,
+            amplitudes: [0.0; MAX_FREQUENCIES],
+            count: frequencies.len(),
+            size: size.max(1),
+            filled: 0,
+            energy: 0.0,
+        }
+    }
+
+    // The mean square of the block once complete, its amplitudes being then
+    // up to date.
+    fn push(&mut self, x: f32) -> Option<f32> {
+        for g in &mut self.filters[.. self.count] {
+            g.push(x);
+        }
+        self.energy += x * x;
+        self.filled += 1;
+        if self.filled < self.size {
+            return None;
+        }
+        for i in 0 .. self.count {
+            self.amplitudes[i] = self.filters[i].take_amplitude(self.size);
+        }
+        let mean_square = self.energy / self.size as f32;
+        self.filled = 0;
+        self.energy = 0.0;
+        Some(mean_square)
+    }
+}
+
+// Index and amplitude of the strongest of `amplitudes`, and whether it is
+// `margin` above all the others.
+fn strongest(amplitudes: &[f32], margin: f32) -> (usize, f32, bool) {
+    let mut best = 0;
+    for (i, &a) in amplitudes.iter().enumerate() {
+        if a > amplitudes[best] {
+            best = i;
+        }
+    }
+    let peak = amplitudes[best];
+    let clear = amplitudes.iter().enumerate().all(|(i, &a)| i == best || a * margin <= peak);
+    (best, peak, clear)
+}
+
+struct ProgressTone {
+    tone: Tone,
+    // bits of `Progress::bank`'s frequencies the tone is made of, and of those
+    // that may show up along: sidebands, and frequencies too close to tell
+    // apart in a block
+    mask: u8,
+    allowed: u8,
+    // on and off frames, starting with on, empty when continuous
+    pattern: [u64; MAX_RUNS],
+    len: usize,
+    // off frames after which the tone has ended
+    end: u64,
+}
+
+impl ProgressTone {
+    fn sounds_like(&self, mask: u8) -> bool {
+        mask & self.mask == self.mask && mask & !self.allowed == 0
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Run {
+    frames: u64,
+    on: bool,
+    mask: u8,
+}
+
+struct Progress {
+    bank: Bank,
+    tones: Vec<ProgressTone>,
+    // completed runs, the latest last
+    runs: [Run; MAX_RUNS],
+    on: bool,
+    start: u64,
+    mask: u8,
+    current: Option<usize>,
+    // on frames after which a continuous tone is reported, longer than any
+    // on period of the cadences
+    continuous: u64,
+    block: u64,
+}
+
+impl Progress {
+    fn new(country: &str, rate: u32) -> Option<Progress> {
+        let kinds = [Tone::Dial, Tone::Ringback, Tone::Busy, Tone::Congestion, Tone::CallWaiting];
+        let block = rate as u64 / 20;
+        let mut specs = Vec::new();
+        for &tone in kinds.iter() {
+            specs.push(ToneSpec::lookup(country, tone)?);
+        }
+
+        let mut frequencies: Vec<f32> = Vec::new();
+        let mut bit = |f: f32| match frequencies.iter().position(|&g| g == f) {
+            Some(i) => 1 << i,
+            None => {
+                frequencies.push(f);
+                1 << (frequencies.len() - 1)
+            }
+        };
+        // carriers, then sidebands
+        let masks: Vec<u8> = specs.iter()
+            .map(|spec| spec.frequencies.iter().fold(0, |mask, &f| mask | bit(f)))
+            .collect();
+        let sidebands: Vec<u8> = specs.iter().map(|spec| match spec.modulation {
+            Some(m) => spec.frequencies.iter().fold(0, |mask, &f| mask | bit(f - m) | bit(f + m)),
+            None => 0,
+        }).collect();
+        if frequencies.len() > MAX_FREQUENCIES {
+            return None;
+        }
+        // closer than one and a half bins leak into each other
+        let resolution = 1.5 * rate as f32 / block as f32;
+        let near = |mask: u8| {
+            (0 .. frequencies.len()).filter(|&i| {
+                (0 .. frequencies.len()).any(|j| {
+                    mask & (1 << j) != 0 && (frequencies[i] - frequencies[j]).abs() < resolution
+                })
+            }).fold(0_u8, |near, i| near | 1 << i)
+        };
+
+        let mut tones = Vec::new();
+        let mut longest_on = rate as u64;
+        for (i, spec) in specs.iter().enumerate() {
+            let mut pattern = [0; MAX_RUNS];
+            let mut end = 2 * block;
+            for (j, &ms) in spec.cadence.iter().enumerate() {
+                pattern[j] = millis(ms, rate);
+                if j % 2 == 0 {
+                    longest_on = longest_on.max(pattern[j]);
+                } else {
+                    end = end.max(pattern[j] + slack(pattern[j], block));
+                }
+            }
+            tones.push(ProgressTone {
+                tone: kinds[i],
+                mask: masks[i],
+                allowed: near(masks[i]) | sidebands[i],
+                pattern,
+                len: spec.cadence.len(),
+                end,
+            });
+        }
+
+        // Short cadences repeat before they are reported, and as long as the
+        // cycle of any other cadence they fit in with the same frequencies.
+        for i in 0 .. tones.len() {
+            let cycle = tones[i].len;
+            if cycle == 0 {
+                continue;
+            }
+            let total: u64 = tones[i].pattern[.. cycle].iter().sum();
+            let (mut runs, mut elapsed) = (cycle, total);
+            while runs < MIN_MATCH_RUNS && elapsed < millis(1000, rate) * MIN_MATCH_SECONDS {
+                runs += cycle;
+                elapsed += total;
+            }
+            for (j, other) in tones.iter().enumerate() {
+                let alike = other.sounds_like(tones[i].mask) && tones[i].sounds_like(other.mask);
+                if j != i && other.len > 0 && alike &&
+                   fits(&tones[i].pattern[.. cycle], &other.pattern[.. other.len], block) {
+                    runs = runs.max(other.len);
+                }
+            }
+            let tone = &mut tones[i];
+            while tone.len < runs && tone.len + cycle <= MAX_RUNS {
+                for j in 0 .. cycle {
+                    tone.pattern[tone.len + j] = tone.pattern[j];
+                }
+                tone.len += cycle;
+            }
+        }
+
+        Some(Progress {
+            bank: Bank::new(&frequencies, rate, block as usize),
+            tones,
+            runs: [Run::default(); MAX_RUNS],
+            on: false,
+            start: 0,
+            mask: 0,
+            current: None,
+            continuous: longest_on + slack(longest_on, block),
+            block,
+        })
+    }
+
+    // A complete block, `end` being the frame after it.
+    fn block(&mut self, mean_square: f32, end: u64, options: &Thresholds,
+             events: &mut Sender) {
+        let all = self.bank.amplitudes;
+        let amplitudes = &all[.. self.bank.count];
+        let (_, peak, _) = strongest(amplitudes, 1.0);
+        let power: f32 = amplitudes.iter().map(|a| 0.5 * a * a).sum();
+        let on = peak >= options.min_amplitude && power >= options.min_ratio * mean_square;
+        let start = end - self.block;
+        if on != self.on {
+            let run = Run { frames: start - self.start, on: self.on, mask: self.mask };
+            for i in 1 .. MAX_RUNS {
+                self.runs[i - 1] = self.runs[i];
+            }
+            self.runs[MAX_RUNS - 1] = run;
+            self.on = on;
+            self.start = start;
+            self.mask = 0;
+            if on {
+                self.match_cadences(start, events);
+            }
+        }
+        if on {
+            for (i, &a) in amplitudes.iter().enumerate() {
+                if a * db_to_ratio(10.0) >= peak {
+                    self.mask |= 1 << i;
+                }
+            }
+            if end - self.start >= self.continuous {
+                let mask = self.mask;
+                let found = self.tones.iter().position(|t| t.len == 0 && t.sounds_like(mask));
+                if let Some(i) = found {
+                    self.report(i, self.start, events);
+                }
+            }
+        } else if let Some(i) = self.current {
+            if end - self.start >= self.tones[i].end {
+                events.send(Event::ToneEnded { tone: self.tones[i].tone, frame: self.start });
+                self.current = None;
+            }
+        }
+    }
+
+    // Tones sharing their frequencies and cadence, as busy and congestion in
+    // some countries, are reported as the first of `Tone`.
+    fn match_cadences(&mut self, now: u64, events: &mut Sender) {
+        let mut found = None;
+        for (i, tone) in self.tones.iter().enumerate() {
+            if tone.len == 0 {
+                continue;
+            }
+            let runs = &self.runs[MAX_RUNS - tone.len ..];
+            let mut frames = [0; MAX_RUNS];
+            for (f, run) in frames.iter_mut().zip(runs) {
+                *f = run.frames;
+            }
+            let alternates = runs.iter().enumerate().all(|(j, run)| {
+                run.on == (j % 2 == 0) && (!run.on || tone.sounds_like(run.mask))
+            });
+            if alternates && fits(&frames[.. tone.len], &tone.pattern[.. tone.len], self.block) {
+                found = Some((i, now - frames.iter().sum::<u64>()));
+                break;
+            }
+        }
+        if let Some((i, start)) = found {
+            self.report(i, start, events);
+        }
+    }
+
+    fn report(&mut self, i: usize, frame: u64, events: &mut Sender) {
+        if self.current == Some(i) {
+            return;
+        }
+        if let Some(previous) = self.current {
+            events.send(Event::ToneEnded { tone: self.tones[previous].tone, frame });
+        }
+        events.send(Event::Tone { tone: self.tones[i].tone, frame });
+        self.current = Some(i);
+    }
+}
+
+fn millis(ms: u32, rate: u32) -> u64 {
+    Frames::from_duration(Duration::from_millis(ms as u64), rate).0
+}
+
+// How far off the measure of a `frames` long period may be, blocks being
+// whole.
+fn slack(frames: u64, block: u64) -> u64 {
+    frames / 8 + block
+}
+
+// Whether the on and off `runs` follow a rotation of `cadence` that starts
+// with an on period.
+fn fits(runs: &[u64], cadence: &[u64], block: u64) -> bool {
+    (0 .. cadence.len()).step_by(2).any(|shift| {
+        runs.iter().enumerate().all(|(j, &frames)| {
+            let expected = cadence[(j + shift) % cadence.len()];
+            let slack = slack(expected, block);
+            frames + slack >= expected && frames <= expected + slack
+        })
+    })
+}
+
+struct Thresholds {
+    min_amplitude: f32,
+    forward_twist: f32,
+    reverse_twist: f32,
+    min_ratio: f32,
+}
+
+struct Sender {
+    producer: Producer<Event>,
+    lost: Arc<AtomicUsize>,
+}
+
+impl Sender {
+    fn send(&mut self, event: Event) {
+        if self.producer.push(event).is_err() {
+            self.lost.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct Detector {
+    channels: usize,
+    frame: u64,
+    thresholds: Thresholds,
+    dtmf: Bank,
+    // blocks a key has to be there, or not, to change state
+    confirm: u32,
+    key: Option<char>,
+    candidate: Option<char>,
+    candidate_blocks: u32,
+    candidate_start: u64,
+    progress: Option<Progress>,
+    events: Sender,
+}
+
+// The control side, receiving what the detector found.
+pub struct Events {
+    consumer: Consumer<Event>,
+    lost: Arc<AtomicUsize>,
+}
+
+impl Events {
+    pub fn try_recv(&mut self) -> Option<Event> {
+        self.consumer.pop()
+    }
+
+    // Events dropped because the control side did not keep up.
+    pub fn lost(&self) -> usize {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+// None when there are no call progress tables for `options.country`.
+pub fn detector(rate: u32, channels: u32, options: &Options) -> Option<(Detector, Events)> {
+    assert!(rate > 0 && channels > 0);
+    let progress = match options.country {
+        Some(ref country) => Some(Progress::new(country, rate)?),
+        None => None,
+    };
+    let mut frequencies = [0_f32; 8];
+    frequencies[.. 4].copy_from_slice(&DTMF_ROWS);
+    frequencies[4 ..].copy_from_slice(&DTMF_COLUMNS);
+    let block = (rate as u64 * 51 / 4000).max(1);
+    let min_key = Frames::from_duration(options.min_key_duration, rate).0;
+    let (producer, consumer) = ring_buffer(EVENTS);
+    let lost = Arc::new(AtomicUsize::new(0));
+    let detector = Detector {
+        channels: channels as usize,
+        frame: 0,
+        thresholds: Thresholds {
+            min_amplitude: db_to_ratio(options.min_level),
+            forward_twist: db_to_ratio(options.forward_twist),
+            reverse_twist: db_to_ratio(options.reverse_twist),
+            min_ratio: options.min_energy_ratio,
+        },
+        dtmf: Bank::new(&frequencies, rate, block as usize),
+        confirm: (min_key / block).saturating_sub(1).max(1) as u32,
+        key: None,
+        candidate: None,
+        candidate_blocks: 0,
+        candidate_start: 0,
+        progress,
+        events: Sender { producer, lost: lost.clone() },
+    };
+    Some((detector, Events { consumer, lost }))
+}
+
+// An input callback feeding `detector`.
+pub fn detecting_callback<T: Sample>(rate: u32, channels: u32,
+                                     options: &Options) -> Option<(DataCallback<T>, Events)> {
+    let (mut detector, events) = detector(rate, channels, options)?;
+    let cb: DataCallback<T> = Box::new(move |input: &[T], _: &mut [T]| {
+        detector.process(input);
+        input.len() / channels as usize
+    });
+    Some((cb, events))
+}
+
+impl Detector {
+    // Interleaved frames, averaged to one channel.
+    pub fn process<T: Sample>(&mut self, input: &[T]) {
+        let scale = 1.0 / self.channels as f32;
+        for frame in input.chunks(self.channels) {
+            let x = frame.iter().map(|s| s.to_f32()).sum::<f32>() * scale;
+            self.frame += 1;
+            if let Some(mean_square) = self.dtmf.push(x) {
+                self.dtmf_block(mean_square);
+            }
+            if let Some(ref mut progress) = self.progress {
+                if let Some(mean_square) = progress.bank.push(x) {
+                    progress.block(mean_square, self.frame, &self.thresholds, &mut self.events);
+                }
+            }
+        }
+    }
+
+    // Frames processed so far.
+    pub fn frames(&self) -> u64 {
+        self.frame
+    }
+
+    fn dtmf_key(&self, mean_square: f32) -> Option<char> {
+        let t = &self.thresholds;
+        let amplitudes = &self.dtmf.amplitudes;
+        // each strongest at least 6dB above the rest of its group
+        let (row, low, low_clear) = strongest(&amplitudes[.. 4], 2.0);
+        let (column, high, high_clear) = strongest(&amplitudes[4 .. 8], 2.0);
+        let valid = low_clear && high_clear
+            && low >= t.min_amplitude && high >= t.min_amplitude
+            && high <= low * t.forward_twist && low <= high * t.reverse_twist
+            && 0.5 * (low * low + high * high) >= t.min_ratio * mean_square;
+        if valid {
+            Some(DTMF_KEYS[row][column])
+        } else {
+            None
+        }
+    }
+
+    fn dtmf_block(&mut self, mean_square: f32) {
+        let key = self.dtmf_key(mean_square);
+        let start = self.frame - self.dtmf.size as u64;
+        if key == self.candidate {
+            self.candidate_blocks += 1;
+        } else {
+            self.candidate = key;
+            self.candidate_blocks = 1;
+            self.candidate_start = start;
+        }
+        if self.candidate_blocks != self.confirm || self.candidate == self.key {
+            return;
+        }
+        if let Some(key) = self.key {
+            self.events.send(Event::KeyUp { key, frame: self.candidate_start });
+        }
+        if let Some(key) = self.candidate {
+            self.events.send(Event::KeyDown { key, frame: self.candidate_start });
+        }
+        self.key = self.candidate;
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use param::AtomicF64;
+use resampler::{Quality, Resampler};
+use ring::{ring_buffer, Consumer, Producer};
+use {DataCallback, Sample};
+
+// Bridge between two audio threads driven by different clocks, e.g. an input
+// and an output device. The writer queues frames as they are captured, the
+// reader resamples them so that the queue stays around its target fill level;
+// the resampling ratio it settles on is the drift between the two clocks.
+//
+// The ratio comes from a PI controller on the smoothed fill level, so that a
+// constant drift is eventually absorbed by the integral term alone. The fill
+// level counts the frames captured since the last write too, using its
+// timestamp, otherwise it would only move in whole periods.
+
+// in seconds
+const FILL_SMOOTHING: f64 = 0.5;
+const PROPORTIONAL_TIME: f64 = 5.0;
+const INTEGRAL_TIME: f64 = 60.0;
+// largest correction applied, as a ratio
+const MAX_CORRECTION: f64 = 1000e-6;
+// largest read handled at once, in frames
+const CHUNK_FRAMES: usize = 1024;
+// longest gap between writes that is extrapolated, in seconds
+const MAX_EXTRAPOLATION: f64 = 0.1;
+
+struct Shared {
+    epoch: Instant,
+    // ns since epoch
+    last_write: AtomicU64,
+    drift: AtomicF64,
+    fill: AtomicUsize,
+    underruns: AtomicUsize,
+    overruns: AtomicUsize,
+}
+
+// Read side of the estimates, usable from any thread.
+#[derive(Clone)]
+pub struct DriftMonitor {
+    shared: Arc<Shared>,
+}
+
+impl DriftMonitor {
+    // Positive when the writer's clock runs faster than the reader's.
+    pub fn ppm(&self) -> f64 {
+        self.shared.drift.load(Ordering::Relaxed) * 1e6
+    }
+
+    // Frames queued at the last read.
+    pub fn fill(&self) -> usize {
+        self.shared.fill.load(Ordering::Relaxed)
+    }
+
+    // Reads that found too few frames and output silence while the queue
+    // filled up again.
+    pub fn underruns(&self) -> usize {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+
+    // Writes that found the queue full and dropped frames.
+    pub fn overruns(&self) -> usize {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+}
+
+pub struct BridgeWriter<T: Sample> {
+    producer: Producer<T>,
+    channels: usize,
+    shared: Arc<Shared>,
+}
+
+impl<T: Sample> BridgeWriter<T> {
+    // Queues as many whole frames as fit, returns their number.
+    pub fn write(&mut self, frames: &[T]) -> usize {
+        self.write_at(frames, Instant::now())
+    }
+
+    // Like `write`, with the time the frames were captured at, for simulated
+    // clocks.
+    pub fn write_at(&mut self, frames: &[T], now: Instant) -> usize {
+        let free = self.producer.free_len() / self.channels * self.channels;
+        let len = frames.len() / self.channels * self.channels;
+        if free < len {
+            self.shared.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        let written = self.producer.push_slice(&frames[.. len.min(free)]) / self.channels;
+        let since_epoch = now.saturating_duration_since(self.shared.epoch).as_nanos() as u64;
+        self.shared.last_write.store(since_epoch, Ordering::Release);
+        written
+    }
+}
+
+pub struct BridgeReader<T: Sample> {
+    consumer: Consumer<T>,
+    channels: usize,
+    rate: f64,
+    target: usize,
+    resampler: Resampler,
+    scratch: Vec<T>,
+    priming: bool,
+    smoothed: f64,
+    integral: f64,
+    shared: Arc<Shared>,
+}
+
+impl<T: Sample> BridgeReader<T> {
+    // Fills `out` with whole frames, silent while the queue is priming.
+    pub fn read(&mut self, out: &mut [T]) {
+        self.read_at(out, Instant::now())
+    }
+
+    // Like `read`, with the time the frames will be played at, for simulated
+    // clocks.
+    pub fn read_at(&mut self, out: &mut [T], now: Instant) {
+        let last_write = self.shared.last_write.load(Ordering::Acquire);
+        let since_write = now.saturating_duration_since(self.shared.epoch).as_nanos() as f64 -
+                          last_write as f64;
+        let mut pending = (since_write / 1e9).clamp(0.0, MAX_EXTRAPOLATION) * self.rate;
+        for chunk in out.chunks_mut(CHUNK_FRAMES * self.channels) {
+            self.read_chunk(chunk, pending);
+            pending = 0.0;
+        }
+    }
+
+    // `pending` is the number of frames captured but not written yet.
+    fn read_chunk(&mut self, out: &mut [T], pending: f64) {
+        let ch = self.channels;
+        let n = out.len() / ch;
+        let fill = self.consumer.len() / ch;
+        self.shared.fill.store(fill, Ordering::Relaxed);
+        if self.priming {
+            if fill < self.target {
+                silence(out);
+                return;
+            }
+            self.priming = false;
+            self.smoothed = fill as f64 + pending;
+        }
+        self.update_ratio(fill as f64 + pending, n);
+
+        let needed = self.resampler.input_needed(n);
+        if fill < needed || needed * ch > self.scratch.len() {
+            self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+            silence(out);
+            self.resampler.reset();
+            self.priming = true;
+            return;
+        }
+        let scratch = &mut self.scratch[.. needed * ch];
+        self.consumer.pop_slice(scratch);
+        let (_, produced) = self.resampler.process(scratch, out);
+        silence(&mut out[produced * ch ..]);
+    }
+
+    fn update_ratio(&mut self, fill: f64, frames: usize) {
+        let dt = frames as f64 / self.rate;
+        let alpha = (dt / FILL_SMOOTHING).min(1.0);
+        self.smoothed += alpha * (fill - self.smoothed);
+        // excess, in seconds of audio
+        let error = (self.smoothed - self.target as f64) / self.rate;
+        let proportional = error / PROPORTIONAL_TIME;
+        self.integral = (self.integral + proportional * dt / INTEGRAL_TIME)
+            .clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        let correction = (proportional + self.integral).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.resampler.set_ratio(1.0 + correction);
+        self.shared.drift.store(self.integral, Ordering::Relaxed);
+    }
+}
+
+fn silence<T: Sample>(out: &mut [T]) {
+    for s in out.iter_mut() {
+        *s = T::default();
+    }
+}
+
+// A bridge for `channels` channels at `rate`, keeping about `target_frames`
+// frames queued.
+pub fn bridge<T: Sample>(channels: usize, rate: u32, target_frames: usize, quality: Quality)
+        -> (BridgeWriter<T>, BridgeReader<T>, DriftMonitor) {
+    assert!(channels > 0 && rate > 0 && target_frames > 0);
+    let shared = Arc::new(Shared {
+        epoch: Instant::now(),
+        last_write: AtomicU64::new(0),
+        drift: AtomicF64::new(0.0),
+        fill: AtomicUsize::new(0),
+        underruns: AtomicUsize::new(0),
+        overruns: AtomicUsize::new(0),
+    });
+    let (producer, consumer) = ring_buffer(4 * (target_frames + CHUNK_FRAMES) * channels);
+    let resampler = Resampler::new(channels, rate, rate, quality);
+    // room for a chunk at the largest ratio, plus the filter
+    let scratch_frames = CHUNK_FRAMES + CHUNK_FRAMES / 100 + 2 * resampler.latency() + 2;
+    let writer = BridgeWriter { producer, channels, shared: shared.clone() };
+    let reader = BridgeReader {
+        consumer,
+        channels,
+        rate: rate as f64,
+        target: target_frames,
+        resampler,
+        scratch: vec![T::default(); scratch_frames * channels],
+        priming: true,
+        smoothed: 0.0,
+        integral: 0.0,
+        shared: shared.clone(),
+    };
+    (writer, reader, DriftMonitor { shared })
+}
+
+// Output callback of a bridged duplex stream: the input comes from `reader`
+// instead of the device.
+pub(crate) fn duplex_callback<T: Sample>(mut reader: BridgeReader<T>,
+                                         out_channels: usize,
+                                         mut data_cb: DataCallback<T>) -> DataCallback<T> {
+    let in_channels = reader.channels;
+    let mut input = vec![T::default(); CHUNK_FRAMES * in_channels];
+    Box::new(move |_: &[T], obuf: &mut [T]| {
+        let frames = obuf.len() / out_channels;
+        let mut done = 0;
+        while done < frames {
+            let n = CHUNK_FRAMES.min(frames - done);
+            let cb_in = &mut input[.. n * in_channels];
+            reader.read(cb_in);
+            let cb_out = &mut obuf[done * out_channels .. (done + n) * out_channels];
+            let returned = data_cb(cb_in, cb_out).min(n);
+            done += returned;
+            if returned < n {
+                break;
+            }
+        }
+        done
+    })
+}
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use ring::{ring_buffer, Consumer, Producer};
+
+// Deferred deallocation for objects the audio callback stops using. The
+// callback moves them into a lock-free queue with a `Retirer`, and the
+// matching `Collector` drops them later on a thread where freeing memory is
+// allowed: a dedicated thread, or the control side of the `Stream` it is
+// attached to.
+
+pub struct Retirer<G> {
+    producer: Producer<G>,
+}
+
+pub struct Collector<G> {
+    consumer: Consumer<G>,
+}
+
+pub fn channel<G: Send>(capacity: usize) -> (Retirer<G>, Collector<G>) {
+    let (producer, consumer) = ring_buffer(capacity);
+    (Retirer { producer }, Collector { consumer })
+}
+
+impl<G> Retirer<G> {
+    // Never frees anything. When the queue is full the object is handed back,
+    // and the caller should hold on to it and retry on the next period.
+    pub fn retire(&mut self, garbage: G) -> Result<(), G> {
+        self.producer.push(garbage)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.producer.len()
+    }
+}
+
+impl<G> Collector<G> {
+    // Drops everything retired so far, returns how many objects were freed.
+    pub fn collect(&mut self) -> usize {
+        let mut count = 0;
+        while let Some(garbage) = self.consumer.pop() {
+            drop(garbage);
+            count += 1;
+        }
+        count
+    }
+
+    pub fn pending(&self) -> usize {
+        self.consumer.len()
+    }
+}
+
+impl<G: Send + 'static> Collector<G> {
+    // Collects every `period` on a background thread, until the returned
+    // handle is dropped.
+    pub fn spawn(self, period: Duration) -> CollectorThread {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let mut collector = self;
+        let handle = thread::Builder::new()
+            .name("cult garbage collector".to_string())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Acquire) {
+                    collector.collect();
+                    thread::park_timeout(period);
+                }
+                collector.collect();
+            })
+            .expect("could not spawn garbage collector thread");
+        CollectorThread { stop, handle: Some(handle) }
+    }
+}
+
+pub struct CollectorThread {
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for CollectorThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+// Type-erased collector, so a `Stream` can own collectors of any garbage type.
+pub trait Collect: Send {
+    fn collect(&mut self) -> usize;
+}
+
+impl<G: Send> Collect for Collector<G> {
+    fn collect(&mut self) -> usize {
+        Collector::collect(self)
+    }
+}
@@ -0,0 +1,281 @@
+use std::f64::consts::PI;
+
+use source::Source;
+use Sample;
+
+// Test and signal tones. Oscillators are band-limited with polynomial
+// corrections around their discontinuities (PolyBLEP, and PolyBLAMP for the
+// triangle's corners), noise comes from a seeded xorshift generator so runs
+// can be repeated. Frequency and amplitude changes glide over `RAMP_SECONDS`
+// with the phase kept continuous, so they don't click.
+
+const RAMP_SECONDS: f64 = 0.01;
+
+pub trait Generator: Send {
+    fn next_sample(&mut self) -> f32;
+
+    // Fills interleaved frames of `channels` channels, e.g. a data callback's
+    // output, with the same signal on every channel.
+    fn fill_frames<T: Sample>(&mut self, out: &mut [T], channels: usize) where Self: Sized {
+        for frame in out.chunks_mut(channels) {
+            let v = T::from_f32(self.next_sample());
+            for s in frame.iter_mut() {
+                *s = v;
+            }
+        }
+    }
+}
+
+// A value moving linearly to its target.
+#[derive(Debug, Copy, Clone)]
+struct Ramp {
+    value: f64,
+    target: f64,
+    step: f64,
+    remaining: u32,
+    frames: u32,
+}
+
+impl Ramp {
+    fn new(value: f64, rate: u32) -> Ramp {
+        let frames = (rate as f64 * RAMP_SECONDS).ceil().max(1.0) as u32;
+        Ramp { value, target: value, step: 0.0, remaining: 0, frames }
+    }
+
+    fn set(&mut self, target: f64) {
+        self.target = target;
+        self.remaining = self.frames;
+        self.step = (target - self.value) / self.frames as f64;
+    }
+
+    fn next(&mut self) -> f64 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.value = if self.remaining == 0 { self.target } else { self.value + self.step };
+        }
+        self.value
+    }
+}
+
+// Residual of a band-limited unit step, `t` samples from it.
+fn blep(t: f64) -> f64 {
+    if t > -1.0 && t < 0.0 {
+        0.5 * (1.0 + t) * (1.0 + t)
+    } else if (0.0 .. 1.0).contains(&t) {
+        -0.5 * (1.0 - t) * (1.0 - t)
+    } else {
+        0.0
+    }
+}
+
+// Residual of a band-limited unit change of slope (per sample), the integral
+// of `blep`.
+fn blamp(t: f64) -> f64 {
+    if t > -1.0 && t < 0.0 {
+        (1.0 + t).powi(3) / 6.0
+    } else if (0.0 .. 1.0).contains(&t) {
+        (1.0 - t).powi(3) / 6.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+}
+
+// Between -amplitude and amplitude. Saw and square waves jump at phase 0,
+// the square back down at half a period; the triangle peaks there.
+pub struct Oscillator {
+    waveform: Waveform,
+    rate: u32,
+    // in periods, in [0, 1)
+    phase: f64,
+    frequency: Ramp,
+    amplitude: Ramp,
+}
+
+impl Oscillator {
+    pub fn new(waveform: Waveform, rate: u32, frequency: f32, amplitude: f32) -> Oscillator {
+        assert!(rate > 0);
+        Oscillator {
+            waveform,
+            rate,
+            phase: 0.0,
+            frequency: Ramp::new(frequency as f64, rate),
+            amplitude: Ramp::new(amplitude as f64, rate),
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency.set(frequency as f64);
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency.target as f32
+    }
+
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        self.amplitude.set(amplitude as f64);
+    }
+
+    pub fn amplitude(&self) -> f32 {
+        self.amplitude.target as f32
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    // In periods, from 0 up to 1 excluded.
+    pub fn phase(&self) -> f64 {
+        self.phase
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+// Samples from `at` to `phase`, wrapped to half a period either side.
+fn distance(phase: f64, at: f64, increment: f64) -> f64 {
+    let mut d = phase - at;
+    if d >= 0.5 {
+        d -= 1.0;
+    } else if d < -0.5 {
+        d += 1.0;
+    }
+    d / increment
+}
+
+impl Generator for Oscillator {
+    fn next_sample(&mut self) -> f32 {
+        // below Nyquist, otherwise the corrections overlap
+        let increment = (self.frequency.next() / self.rate as f64).clamp(0.0, 0.5);
+        let amplitude = self.amplitude.next();
+        let p = self.phase;
+        let mut value = match self.waveform {
+            Waveform::Sine => (2.0 * PI * p).sin(),
+            Waveform::Square => if p < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Saw => 2.0 * p - 1.0,
+            Waveform::Triangle => if p < 0.5 { 4.0 * p - 1.0 } else { 3.0 - 4.0 * p },
+        };
+        if increment > 0.0 {
+            let (up, down) = (distance(p, 0.0, increment), distance(p, 0.5, increment));
+            value += match self.waveform {
+                Waveform::Sine => 0.0,
+                Waveform::Square => 2.0 * (blep(up) - blep(down)),
+                Waveform::Saw => -2.0 * blep(up),
+                Waveform::Triangle => 8.0 * increment * (blamp(up) - blamp(down)),
+            };
+        }
+        self.phase += increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        (amplitude * value) as f32
+    }
+}
+
+impl Source for Oscillator {
+    fn fill(&mut self, out: &mut [f32], channels: usize) -> usize {
+        self.fill_frames(out, channels);
+        out.len() / channels
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Color {
+    // flat spectrum
+    White,
+    // -3dB per octave
+    Pink,
+    // -6dB per octave
+    Brown,
+}
+
+// Noise peaking around -amplitude and amplitude. The same seed gives the
+// same samples.
+pub struct Noise {
+    color: Color,
+    state: u64,
+    amplitude: Ramp,
+    // filter states, Paul Kellett's pink noise filter and a leaky integrator
+    pink: [f64; 7],
+    brown: f64,
+}
+
+impl Noise {
+    pub fn new(color: Color, rate: u32, amplitude: f32, seed: u64) -> Noise {
+        Noise {
+            color,
+            // xorshift never leaves zero
+            state: (seed ^ 0x9e37_79b9_7f4a_7c15).max(1),
+            amplitude: Ramp::new(amplitude as f64, rate),
+            pink: [0.0; 7],
+            brown: 0.0,
+        }
+    }
+
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        self.amplitude.set(amplitude as f64);
+    }
+
+    pub fn amplitude(&self) -> f32 {
+        self.amplitude.target as f32
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    // Uniform in [-1, 1).
+    fn white(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let bits = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        bits as f64 / (1_u64 << 52) as f64 - 1.0
+    }
+}
+
+impl Generator for Noise {
+    fn next_sample(&mut self) -> f32 {
+        let white = self.white();
+        let value = match self.color {
+            Color::White => white,
+            Color::Pink => {
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.1538520;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * 0.11
+            }
+            Color::Brown => {
+                self.brown = (self.brown + 0.02 * white) / 1.02;
+                self.brown * 3.5
+            }
+        };
+        (self.amplitude.next() * value.clamp(-1.0, 1.0)) as f32
+    }
+}
+
+impl Source for Noise {
+    fn fill(&mut self, out: &mut [f32], channels: usize) -> usize {
+        self.fill_frames(out, channels);
+        out.len() / channels
+    }
+}
@@ -4,6 +4,14 @@
 extern crate libc;
 #[macro_use]
 extern crate bitflags;
+#[cfg(feature = "async")]
+extern crate atomic_waker;
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "async")]
+extern crate futures_sink;
+#[cfg(feature = "codecs")]
+extern crate symphonia;
 // #[macro_use]
 // #[cfg(feature = "plugins")]
 // extern crate heapsize;
@@ -16,9 +24,54 @@ use std::slice;
 use std::result;
 use std::marker::PhantomData;
 use std::boxed::Box;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 pub mod ffi;
+pub mod ring;
+pub mod triple_buffer;
+pub mod command;
+pub mod param;
+pub mod gc;
+#[cfg(feature = "rt-check")]
+pub mod rt_check;
+pub mod stats;
+pub mod channel_mixer;
+pub mod resampler;
+pub mod drift;
+pub mod negotiate;
+pub mod units;
+pub mod clock;
+pub mod scheduler;
+pub mod source;
+pub mod generator;
+pub mod tones;
+pub mod detector;
+pub mod meter;
+pub mod loudness;
+pub mod mixer;
+pub mod wav;
+pub mod recorder;
+pub mod player;
+#[cfg(feature = "codecs")]
+pub mod codecs;
+pub mod render;
+mod pipeline;
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod capture;
 use ffi::*;
+use capture::InputReader;
+use command::{command_channel, CommandReceiver, CommandSender};
+use gc::{Collect, Collector};
+use stats::{CallbackStats, StreamStats};
+use channel_mixer::MixOptions;
+use resampler::Quality;
+use drift::DriftMonitor;
+use units::{Frames, Latency};
 
 #[derive(Debug, Copy, Clone)]
 pub enum Error {
@@ -48,9 +101,10 @@ pub type Result<T> = result::Result<T, Error>;
 pub type DataCallback<T> = Box<FnMut(&[T], &mut [T]) -> usize>;
 pub type StateCallback = Box<FnMut(State)>;
 pub type DeviceChangedCallback = Box<FnMut()>;
+pub type CommandCallback<T, C> = Box<dyn FnMut(&mut CommandReceiver<C>, &[T], &mut [T]) -> usize>;
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum SampleFormat {
     Signed16LE  = 0,
@@ -86,10 +140,13 @@ impl Into<cubeb_sample_format> for SampleFormat
     }
 }
 
-pub trait Sample
+pub trait Sample: Copy + Default + Send + 'static
 {
     fn format() -> SampleFormat;
     fn data_cb_ffi() -> cubeb_data_callback;
+    // Conversions to and from [-1.0, 1.0] floats, saturating.
+    fn to_f32(self) -> f32;
+    fn from_f32(v: f32) -> Self;
 }
 
 impl Sample for i16 {
@@ -99,6 +156,12 @@ impl Sample for i16 {
     fn data_cb_ffi() -> cubeb_data_callback {
         data_callback_i16
     }
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768_f32
+    }
+    fn from_f32(v: f32) -> i16 {
+        (v * 32768_f32).round().clamp(-32768_f32, 32767_f32) as i16
+    }
 }
 
 impl Sample for f32 {
@@ -108,6 +171,12 @@ impl Sample for f32 {
     fn data_cb_ffi() -> cubeb_data_callback {
         data_callback_f32
     }
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn from_f32(v: f32) -> f32 {
+        v
+    }
 }
 
 
@@ -119,7 +188,7 @@ pub enum LogLevel {
     Verbose     = 2,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum ChannelLayout {
     Undefined       = 0,
@@ -156,6 +225,57 @@ impl From<cubeb_channel_layout> for ChannelLayout {
     }
 }
 
+// Speaker positions, in the order cubeb interleaves them for each layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    Mono,
+    Left,
+    Right,
+    Center,
+    LFE,
+    RearCenter,
+    LeftSurround,
+    RightSurround,
+    RearLeftSurround,
+    RearRightSurround,
+}
+
+impl ChannelLayout {
+    pub fn channels(&self) -> &'static [Channel] {
+        use Channel::*;
+        match *self {
+            ChannelLayout::Undefined    => &[],
+            ChannelLayout::DualMono     => &[Left, Right],
+            ChannelLayout::DualMono_LFE => &[Left, Right, LFE],
+            ChannelLayout::Mono         => &[Mono],
+            ChannelLayout::Mono_LFE     => &[Mono, LFE],
+            ChannelLayout::Stereo       => &[Left, Right],
+            ChannelLayout::Stereo_LFE   => &[Left, Right, LFE],
+            ChannelLayout::F3           => &[Left, Right, Center],
+            ChannelLayout::F3_LFE       => &[Left, Right, Center, LFE],
+            ChannelLayout::F2_1         => &[Left, Right, RearCenter],
+            ChannelLayout::F2_1_LFE     => &[Left, Right, LFE, RearCenter],
+            ChannelLayout::F3_1         => &[Left, Right, Center, RearCenter],
+            ChannelLayout::F3_1_LFE     => &[Left, Right, Center, LFE, RearCenter],
+            ChannelLayout::F2_2         => &[Left, Right, LeftSurround, RightSurround],
+            ChannelLayout::F2_2_LFE     => &[Left, Right, LFE, LeftSurround, RightSurround],
+            ChannelLayout::F3_2         => &[Left, Right, Center, LeftSurround, RightSurround],
+            ChannelLayout::F3_2_LFE     => &[Left, Right, Center, LFE,
+                                             LeftSurround, RightSurround],
+            ChannelLayout::F3_R3_LFE    => &[Left, Right, Center, LFE, RearCenter,
+                                             LeftSurround, RightSurround],
+            ChannelLayout::F3_4_LFE     => &[Left, Right, Center, LFE,
+                                             RearLeftSurround, RearRightSurround,
+                                             LeftSurround, RightSurround],
+            ChannelLayout::Max          => &[],
+        }
+    }
+
+    pub fn channel_count(&self) -> u32 {
+        self.channels().len() as u32
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
 pub enum State {
@@ -316,8 +436,10 @@ impl Context {
         }
     }
 
+    // In frames at the rate of `params`.
     pub fn min_latency<T: Sample>(&self, params: StreamParams<T>) -> Result<u32> {
-        let mut latency = 0;
+        let mut latency: u32 = 0;
+        let (rate, device_rate) = (params.rate(), params.device_rate());
         let params = params.into();
         let res = unsafe {
             cubeb_get_min_latency(self.native,
@@ -325,11 +447,16 @@ impl Context {
                         &mut latency as *mut _)
         };
         match res {
-            CUBEB_OK => { Ok(latency) },
+            CUBEB_OK => { Ok(Frames(latency as u64).at_rate(device_rate, rate).0 as u32) },
             _ => { Err( Error::from(res) ) }
         }
     }
 
+    pub fn min_latency_duration<T: Sample>(&self, params: StreamParams<T>) -> Result<Duration> {
+        let rate = params.rate();
+        self.min_latency(params).map(|l| Frames::from(l).to_duration(rate))
+    }
+
     pub fn preferred_sample_rate(&self) -> Result<u32> {
         let mut rate = 0;
         let res = unsafe {
@@ -491,29 +618,113 @@ impl<'a> Drop for DeviceCollection<'a> {
     }
 }
 
+// The rate, channels and layout are the ones the data callback sees. The
+// `with_device_*` options open the device with different ones, cult
+// converting in between.
 #[derive(Debug, Copy, Clone)]
 pub struct StreamParams<T: Sample> {
     rate: u32,
     channels: u32,
     layout: ChannelLayout,
+    device_layout: Option<ChannelLayout>,
+    mix: MixOptions,
+    physical_channels: Option<u32>,
+    device_rate: Option<u32>,
+    quality: Quality,
+    drift: Option<Quality>,
     phantom: PhantomData<T>,
 }
 
 impl<T: Sample> StreamParams<T> {
     pub fn new (rate: u32, channels: u32, layout: ChannelLayout) -> StreamParams<T> {
         StreamParams {
-            rate: rate, channels: channels, layout: layout, phantom: PhantomData
+            rate: rate, channels: channels, layout: layout,
+            device_layout: None, mix: MixOptions::default(),
+            physical_channels: None,
+            device_rate: None, quality: Quality::Sinc,
+            drift: None,
+            phantom: PhantomData
+        }
+    }
+
+    // Opens the device with `layout`, up or down mixing from the callback's
+    // layout, e.g. to `Context::preferred_channel_layout`.
+    pub fn with_device_layout(mut self, layout: ChannelLayout) -> StreamParams<T> {
+        self.device_layout = Some(layout);
+        self
+    }
+
+    pub fn with_mix_options(mut self, options: MixOptions) -> StreamParams<T> {
+        self.mix = options;
+        self
+    }
+
+    // Opens the device with `channels` channels, the callback's channels (or
+    // the device layout's, when mixing) being routed to some of them. See
+    // `Stream::set_output_routing`; by default logical channel `i` goes to
+    // device channel `i`.
+    pub fn with_device_channels(mut self, channels: u32) -> StreamParams<T> {
+        self.physical_channels = Some(channels);
+        self
+    }
+
+    // Opens the device at `rate`, e.g. `Context::preferred_sample_rate`, and
+    // resamples to and from the callback's rate with `quality`. Positions and
+    // latencies are then counted at the callback's rate.
+    pub fn with_device_rate(mut self, rate: u32, quality: Quality) -> StreamParams<T> {
+        self.device_rate = Some(rate);
+        self.quality = quality;
+        self
+    }
+
+    // For the input of a duplex stream whose input and output devices do not
+    // share a clock. The input is then opened as a stream of its own, and
+    // resampled with `quality` to follow the output's clock, see
+    // `Stream::drift_ppm`. Ignored on output parameters.
+    pub fn with_drift_compensation(mut self, quality: Quality) -> StreamParams<T> {
+        self.drift = Some(quality);
+        self
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn device_rate(&self) -> u32 {
+        self.device_rate.unwrap_or(self.rate)
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    pub fn device_layout(&self) -> ChannelLayout {
+        match self.physical_channels {
+            Some(c) if c != self.logical_channels() => ChannelLayout::Undefined,
+            _ => self.device_layout.unwrap_or(self.layout),
         }
     }
+
+    pub fn device_channels(&self) -> u32 {
+        self.physical_channels.unwrap_or_else(|| self.logical_channels())
+    }
+
+    fn logical_channels(&self) -> u32 {
+        self.device_layout.map_or(self.channels, |l| l.channel_count())
+    }
 }
 
 impl<T: Sample> Into<cubeb_stream_params> for StreamParams<T> {
     fn into(self) -> cubeb_stream_params {
         cubeb_stream_params {
             format: T::format().into(),
-            rate: self.rate,
-            channels: self.channels,
-            layout: self.layout.into(),
+            rate: self.device_rate(),
+            channels: self.device_channels(),
+            layout: self.device_layout().into(),
         }
     }
 }
@@ -522,34 +733,101 @@ impl<T: Sample> Into<cubeb_stream_params> for StreamParams<T> {
 pub struct Stream<T: Sample> {
     native: *const cubeb_stream,
     data: Box<StreamData<T>>,
+    input: Option<InputReader<T>>,
+    collectors: Mutex<Vec<Box<dyn Collect>>>,
+    controls: pipeline::Controls,
+    // input half of a drift compensated duplex stream, and its estimates
+    linked_input: Option<Box<Stream<T>>>,
+    drift: Option<DriftMonitor>,
+    // as seen by the callback, of the output if any
+    rate: u32,
+    channels: u32,
 }
 
-struct StreamData<T: Sample> {
+// What the data callback trampolines get as their user pointer.
+pub(crate) struct StreamData<T: Sample> {
     data_cb: DataCallback<T>,
     state_cb: Option<StateCallback>,
     in_channels: u32,
     out_channels: u32,
+    pub(crate) stats: CallbackStats,
+    #[cfg(feature = "rt-check")]
+    pub(crate) rt_violations: rt_check::Violations,
+    // set once the callback panicked, the stream failing from then on
+    panicked: AtomicBool,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl<T: Sample> StreamData<T> {
+    // `data_cb` is already wrapped in the pipeline of the parameters.
+    pub(crate) fn new(data_cb: DataCallback<T>, state_cb: Option<StateCallback>,
+                      in_params: Option<&StreamParams<T>>,
+                      out_params: Option<&StreamParams<T>>) -> Box<StreamData<T>> {
+        Box::new(StreamData {
+            data_cb,
+            state_cb,
+            in_channels: in_params.map_or(0, |p| p.device_channels()),
+            out_channels: out_params.map_or(0, |p| p.device_channels()),
+            stats: CallbackStats::new(out_params.or(in_params).map_or(0, |p| p.device_rate()),
+                                      in_params.is_some(), out_params.is_some()),
+            #[cfg(feature = "rt-check")]
+            rt_violations: rt_check::Violations::default(),
+            panicked: AtomicBool::new(false),
+            panic: Mutex::new(None),
+        })
+    }
+
+    // Re-raises on the calling thread a panic caught in the data callback,
+    // once.
+    pub(crate) fn rethrow_panic(&self) {
+        if self.panicked.load(Ordering::Acquire) {
+            let payload = self.panic.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some(payload) = payload {
+                panic::resume_unwind(payload);
+            }
+        }
+    }
+
+    // Runs the data callback through the trampoline cubeb calls, with
+    // buffers of `frames` frames on the device side.
+    pub(crate) fn call(&mut self, ibuf: &[T], obuf: &mut [T], frames: usize) -> c_long {
+        debug_assert!(ibuf.len() >= frames * self.in_channels as usize &&
+                      obuf.len() >= frames * self.out_channels as usize);
+        (T::data_cb_ffi())(ptr::null(), self as *mut StreamData<T> as *mut c_void,
+                           ibuf.as_ptr() as *const c_void, obuf.as_mut_ptr() as *mut c_void,
+                           frames as c_long)
+    }
 }
 
 impl<T: Sample> Stream<T> {
-    pub fn new(ctx: &Context, stream_name: &str,
+    // `latency` is in frames at the callback's rate, or a `Duration`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<L: Into<Latency>>(ctx: &Context, stream_name: &str,
                in_device: Option<&DevId>, in_params: Option<StreamParams<T>>,
                out_device: Option<&DevId>, out_params: Option<StreamParams<T>>,
-               latency_frames: u32, data_cb: DataCallback<T>,
+               latency: L, data_cb: DataCallback<T>,
                state_cb: Option<StateCallback>) -> Result<Stream<T>> {
+        let main_params = out_params.or(in_params);
+        let rate = main_params.map_or(0, |p| p.rate);
+        let latency_frames = latency.into().to_frames(rate).0.min(u32::MAX as u64) as u32;
+        if let (Some(ip), Some(op)) = (in_params, out_params) {
+            if let Some(quality) = ip.drift {
+                return Stream::new_bridged(ctx, stream_name, in_device, ip, out_device, op,
+                                           latency_frames, quality, data_cb, state_cb);
+            }
+        }
         let mut stm = ptr::null();
 
         let nat_state_cb: Option<cubeb_state_callback> = match state_cb.as_ref() {
             Some(_) => Some(state_callback_cb),
             None => Some(state_callback_noop),
         };
-        let mut data = Box::new(StreamData {
-            data_cb: data_cb,
-            state_cb: state_cb,
-            in_channels: in_params.as_ref().map_or(0, |p| p.channels),
-            out_channels: out_params.as_ref().map_or(0, |p| p.channels),
-        });
+        let (data_cb, controls) =
+            pipeline::build(data_cb, in_params.as_ref(), out_params.as_ref())?;
+        let mut data = StreamData::new(data_cb, state_cb, in_params.as_ref(), out_params.as_ref());
 
+        let device_latency = Frames::from(latency_frames)
+            .at_rate(rate, main_params.map_or(0, |p| p.device_rate())).0 as u32;
         let stream_name = CString::new(stream_name).unwrap();
         let in_params: Option<cubeb_stream_params> = in_params.map(|p| p.into());
         let out_params: Option<cubeb_stream_params> = out_params.map(|p| p.into());
@@ -558,7 +836,7 @@ impl<T: Sample> Stream<T> {
             cubeb_stream_init(ctx.native, &mut stm, stream_name.as_ptr(),
                 transmute(in_device), transmute(in_params.as_ref()),
                 transmute(out_device), transmute(out_params.as_ref()),
-                latency_frames, Some(T::data_cb_ffi()), nat_state_cb,
+                device_latency, Some(T::data_cb_ffi()), nat_state_cb,
                 &mut *data as *mut StreamData<T> as *mut c_void
             )
         };
@@ -566,32 +844,203 @@ impl<T: Sample> Stream<T> {
             CUBEB_OK => Ok(Stream {
                 native: stm,
                 data: data,
+                input: None,
+                collectors: Mutex::new(Vec::new()),
+                controls,
+                linked_input: None,
+                drift: None,
+                rate,
+                channels: main_params.map_or(0, |p| p.channels),
             }),
             _ => Err( Error::from(res) )
         }
     }
 
+    // Two streams, the input one feeding the output one's callback through a
+    // drift compensating bridge. State changes are those of the output.
+    #[allow(clippy::too_many_arguments)]
+    fn new_bridged(ctx: &Context, stream_name: &str,
+                   in_device: Option<&DevId>, in_params: StreamParams<T>,
+                   out_device: Option<&DevId>, out_params: StreamParams<T>,
+                   latency_frames: u32, quality: Quality, data_cb: DataCallback<T>,
+                   state_cb: Option<StateCallback>) -> Result<Stream<T>> {
+        if in_params.rate != out_params.rate || in_params.channels == 0 ||
+           out_params.channels == 0 {
+            return Err(Error::InvalidParameter);
+        }
+        let in_channels = in_params.channels as usize;
+        // with periods of up to `latency_frames` on each side, the queue must
+        // hold more than two of them not to run dry as the devices' phases
+        // slide past each other
+        let target = (3 * latency_frames as usize).max(in_params.rate as usize / 100);
+        let (mut writer, reader, monitor) =
+            drift::bridge(in_channels, in_params.rate, target, quality);
+
+        let in_cb: DataCallback<T> = Box::new(move |ibuf: &[T], _: &mut [T]| {
+            writer.write(ibuf);
+            ibuf.len() / in_channels
+        });
+        let mut in_params = in_params;
+        in_params.drift = None;
+        let input = Stream::new(ctx, stream_name, in_device, Some(in_params), None, None,
+                                latency_frames, in_cb, None)?;
+
+        let out_cb = drift::duplex_callback(reader, out_params.channels as usize, data_cb);
+        let mut output = Stream::new(ctx, stream_name, None, None, out_device, Some(out_params),
+                                     latency_frames, out_cb, state_cb)?;
+        output.linked_input = Some(Box::new(input));
+        output.drift = Some(monitor);
+        Ok(output)
+    }
+
+    // Like `new`, but the callback also gets the receiving end of a command
+    // queue of `command_capacity` entries, whose sender is returned with the
+    // stream.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_commands<C: Send + 'static, L: Into<Latency>>(
+               ctx: &Context, stream_name: &str,
+               in_device: Option<&DevId>, in_params: Option<StreamParams<T>>,
+               out_device: Option<&DevId>, out_params: Option<StreamParams<T>>,
+               latency: L, command_capacity: usize,
+               data_cb: CommandCallback<T, C>,
+               state_cb: Option<StateCallback>) -> Result<(Stream<T>, CommandSender<C>)> {
+        if command_capacity == 0 {
+            return Err(Error::InvalidParameter);
+        }
+        let (sender, mut receiver) = command_channel(command_capacity);
+        let mut data_cb = data_cb;
+        let cb: DataCallback<T> = Box::new(move |ibuf: &[T], obuf: &mut [T]| {
+            data_cb(&mut receiver, ibuf, obuf)
+        });
+        let stm = Stream::new(ctx, stream_name,
+                              in_device, in_params, out_device, out_params,
+                              latency, cb, state_cb)?;
+        Ok((stm, sender))
+    }
+
+    // Input-only stream whose callback fills a ring buffer of
+    // `buffer_frames` frames, drained with `read` and `try_read`.
+    pub fn new_input<L: Into<Latency>>(ctx: &Context, stream_name: &str,
+                     in_device: Option<&DevId>, in_params: StreamParams<T>,
+                     latency: L, buffer_frames: usize,
+                     state_cb: Option<StateCallback>) -> Result<Stream<T>> {
+        if in_params.channels == 0 || buffer_frames == 0 {
+            return Err(Error::InvalidParameter);
+        }
+        let (reader, data_cb, state_cb) =
+            capture::input_reader(in_params.channels, buffer_frames, state_cb);
+        let mut stm = Stream::new(ctx, stream_name,
+                                  in_device, Some(in_params), None, None,
+                                  latency, data_cb, Some(state_cb))?;
+        stm.input = Some(reader);
+        Ok(stm)
+    }
+
     pub fn start(&self) -> Result<()> {
+        self.data.rethrow_panic();
+        self.collect_garbage();
+        if let Some(ref linked) = self.linked_input {
+            linked.start()?;
+        }
+        if let Some(ref input) = self.input {
+            input.set_running(true);
+        }
         let res = unsafe {
             cubeb_stream_start(self.native)
         };
         match res {
             CUBEB_OK => Ok(()),
-            _ => Err(Error::from(res)),
+            _ => {
+                if let Some(ref input) = self.input {
+                    input.set_running(false);
+                }
+                if let Some(ref linked) = self.linked_input {
+                    let _ = linked.stop();
+                }
+                Err(Error::from(res))
+            }
         }
     }
 
     pub fn stop(&self) -> Result<()> {
+        self.data.rethrow_panic();
+        self.collect_garbage();
         let res = unsafe {
             cubeb_stream_stop(self.native)
         };
+        if let Some(ref input) = self.input {
+            input.set_running(false);
+        }
+        if let Some(ref linked) = self.linked_input {
+            linked.stop()?;
+        }
         match res {
             CUBEB_OK => Ok(()),
             _ => Err(Error::from(res)),
         }
     }
 
+    // Estimated drift between the input and output clocks of a stream opened
+    // with `StreamParams::with_drift_compensation`, positive when the input
+    // runs faster.
+    pub fn drift_ppm(&self) -> Result<f64> {
+        self.drift.as_ref().map(|d| d.ppm()).ok_or(Error::InvalidParameter)
+    }
+
+    pub fn drift_monitor(&self) -> Option<DriftMonitor> {
+        self.drift.clone()
+    }
+
+    // Blocks until `buf` is filled with whole frames or the stream stops.
+    // Returns the number of samples written to `buf`.
+    pub fn read(&mut self, buf: &mut [T]) -> Result<usize> {
+        match self.input {
+            Some(ref mut input) => {
+                let read = input.read(buf);
+                if read == 0 && input.failed() {
+                    return Err(Error::Undefined);
+                }
+                Ok(read)
+            }
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    // Copies out whatever whole frames are already buffered, without blocking.
+    pub fn try_read(&mut self, buf: &mut [T]) -> Result<usize> {
+        match self.input {
+            Some(ref mut input) => Ok(input.try_read(buf)),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    pub fn available_frames(&self) -> Result<usize> {
+        self.input.as_ref().map(|i| i.available_frames()).ok_or(Error::InvalidParameter)
+    }
+
+    // Number of callbacks that found the ring buffer full because the reader
+    // fell behind, and the total number of frames lost that way.
+    pub fn overruns(&self) -> Result<usize> {
+        self.input.as_ref().map(|i| i.overruns()).ok_or(Error::InvalidParameter)
+    }
+
+    pub fn dropped_frames(&self) -> Result<usize> {
+        self.input.as_ref().map(|i| i.dropped_frames()).ok_or(Error::InvalidParameter)
+    }
+
+    // Frees garbage retired by the callback through a collector attached with
+    // `attach_collector`. Also done by every other control-side method.
+    pub fn collect_garbage(&self) -> usize {
+        let mut collectors = self.collectors.lock().unwrap();
+        collectors.iter_mut().map(|c| c.collect()).sum()
+    }
+
+    pub fn attach_collector<G: Send + 'static>(&self, collector: Collector<G>) {
+        self.collectors.lock().unwrap().push(Box::new(collector));
+    }
+
     pub fn reset_default_device(&self) -> Result<()> {
+        self.collect_garbage();
         let res = unsafe {
             cubeb_stream_reset_default_device(self.native)
         };
@@ -601,29 +1050,128 @@ impl<T: Sample> Stream<T> {
         }
     }
 
+    // In frames at the callback's rate.
     pub fn position(&self) -> Result<u64> {
+        self.collect_garbage();
         let mut val = 0;
         let res = unsafe {
             cubeb_stream_get_position(self.native, &mut val)
         };
         match res {
-            CUBEB_OK => Ok(val),
+            CUBEB_OK => Ok(match self.controls.rate {
+                Some(ref rate) => rate.to_callback_frames(val),
+                None => val,
+            }),
             _ => Err(Error::from(res)),
         }
     }
 
+    // In frames at the callback's rate, including the frames held by the
+    // resampler when the device runs at another rate.
     pub fn latency(&self) -> Result<u32> {
+        self.collect_garbage();
         let mut val = 0;
         let res = unsafe {
             cubeb_stream_get_latency(self.native, &mut val)
         };
         match res {
-            CUBEB_OK => Ok(val),
+            CUBEB_OK => Ok(match self.controls.rate {
+                Some(ref rate) => rate.callback_latency(val, self.data.out_channels > 0),
+                None => val,
+            }),
             _ => Err(Error::from(res)),
         }
     }
 
+    pub fn position_duration(&self) -> Result<Duration> {
+        self.position().map(|p| Frames(p).to_duration(self.rate))
+    }
+
+    pub fn latency_duration(&self) -> Result<Duration> {
+        self.latency().map(|l| Frames::from(l).to_duration(self.rate))
+    }
+
+    // Rate and channel count of the callback's output, or input for
+    // input-only streams.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    // Timing of the data callback since the stream was created. This, `start`
+    // and `stop` re-raise a panic of the data callback, which fails the
+    // stream instead of unwinding into cubeb.
+    pub fn stats(&self) -> StreamStats {
+        self.data.rethrow_panic();
+        #[allow(unused_mut)]
+        let mut stats = self.data.stats.snapshot();
+        #[cfg(feature = "rt-check")]
+        {
+            stats.rt_violations = self.data.rt_violations.count();
+        }
+        stats
+    }
+
+    // Heap operations caught in the data callback so far.
+    #[cfg(feature = "rt-check")]
+    pub fn rt_violations(&self) -> usize {
+        self.data.rethrow_panic();
+        self.data.rt_violations.count()
+    }
+
+    // Replaces the mixing coefficients of a stream opened with
+    // `StreamParams::with_device_layout`, `device channels` rows of
+    // `callback channels` coefficients for the output.
+    pub fn set_output_mix_matrix(&self, matrix: &[f32]) -> Result<()> {
+        self.collect_garbage();
+        match self.controls.output_mix {
+            Some(ref mix) => mix.set_matrix(matrix),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    // `callback channels` rows of `device channels` coefficients.
+    pub fn set_input_mix_matrix(&self, matrix: &[f32]) -> Result<()> {
+        self.collect_garbage();
+        match self.controls.input_mix {
+            Some(ref mix) => mix.set_matrix(matrix),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    // Sets the device channel of each logical channel of a stream opened with
+    // `StreamParams::with_device_channels`. `None` leaves a logical channel
+    // unrouted; device channels that nothing is routed to are silent.
+    pub fn set_output_routing(&self, map: &[Option<u32>]) -> Result<()> {
+        self.collect_garbage();
+        match self.controls.output_route {
+            Some(ref route) => route.set_map(map),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    // Sets the device channel each logical input channel is read from.
+    pub fn set_input_routing(&self, map: &[Option<u32>]) -> Result<()> {
+        self.collect_garbage();
+        match self.controls.input_route {
+            Some(ref route) => route.set_map(map),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    pub fn output_routing(&self) -> Option<Vec<Option<u32>>> {
+        self.controls.output_route.as_ref().map(|r| r.map())
+    }
+
+    pub fn input_routing(&self) -> Option<Vec<Option<u32>>> {
+        self.controls.input_route.as_ref().map(|r| r.map())
+    }
+
     pub fn set_volume(&self, volume: f32) -> Result<()> {
+        self.collect_garbage();
         let res = unsafe {
             cubeb_stream_set_volume(self.native, volume)
         };
@@ -634,6 +1182,7 @@ impl<T: Sample> Stream<T> {
     }
 
     pub fn set_panning(&self, panning: f32) -> Result<()> {
+        self.collect_garbage();
         let res = unsafe {
             cubeb_stream_set_panning(self.native, panning)
         };
@@ -647,6 +1196,7 @@ impl<T: Sample> Stream<T> {
 impl<T: Sample> Drop for Stream<T> {
     fn drop(&mut self) {
         unsafe { cubeb_stream_destroy(self.native); }
+        self.collect_garbage();
     }
 }
 
@@ -662,22 +1212,50 @@ pub fn print_state_change(state: State) {
 }
 
 
+// Unwinding into cubeb would abort, so a panic of the callback, or one raised
+// by `rt_check` with `Policy::Panic`, fails the stream and is kept for the
+// control side to re-raise.
+unsafe fn run_data_callback<T: Sample>(user: *mut c_void,
+                                       in_buf: *const c_void,
+                                       out_buf: *mut c_void,
+                                       nframes: c_long) -> c_long {
+    let data = &mut *(user as *mut StreamData<T>);
+    if data.panicked.load(Ordering::Relaxed) {
+        return CUBEB_ERROR as c_long;
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let start = Instant::now();
+        #[cfg(feature = "rt-check")]
+        let _guard = rt_check::enter(&data.rt_violations);
+        let ibuf: &[T] = match data.in_channels {
+            0 => &[],
+            ch => slice::from_raw_parts(in_buf as *const T, nframes as usize * ch as usize),
+        };
+        let obuf: &mut [T] = match data.out_channels {
+            0 => &mut [],
+            ch => slice::from_raw_parts_mut(out_buf as *mut T, nframes as usize * ch as usize),
+        };
+
+        let returned = (data.data_cb)(ibuf, obuf);
+        data.stats.record(start, Instant::now(), nframes as u64, returned as u64);
+        returned as c_long
+    }));
+    match result {
+        Ok(returned) => returned,
+        Err(payload) => {
+            *data.panic.lock().unwrap_or_else(|e| e.into_inner()) = Some(payload);
+            data.panicked.store(true, Ordering::Release);
+            CUBEB_ERROR as c_long
+        }
+    }
+}
+
 extern fn data_callback_i16(_stm: *const cubeb_stream,
                             user: *mut c_void,
                             in_buf: *const c_void,
                             out_buf: *mut c_void,
                             nframes: c_long) -> c_long {
-    unsafe {
-        let data: &mut StreamData<i16> = transmute(user);
-        let ibuf: &[i16] = slice::from_raw_parts(
-            in_buf as *const i16, nframes as usize * data.in_channels as usize
-        );
-        let obuf: &mut [i16] = slice::from_raw_parts_mut(
-            out_buf as *mut i16, nframes as usize * data.out_channels as usize
-        );
-
-        (data.data_cb)(ibuf, obuf) as c_long
-    }
+    unsafe { run_data_callback::<i16>(user, in_buf, out_buf, nframes) }
 }
 
 extern fn data_callback_f32(_stm: *const cubeb_stream,
@@ -685,17 +1263,7 @@ extern fn data_callback_f32(_stm: *const cubeb_stream,
                             in_buf: *const c_void,
                             out_buf: *mut c_void,
                             nframes: c_long) -> c_long {
-    unsafe {
-        let data: &mut StreamData<f32> = transmute(user);
-        let ibuf: &[f32] = slice::from_raw_parts(
-            in_buf as *const f32, nframes as usize * data.in_channels as usize
-        );
-        let obuf: &mut [f32] = slice::from_raw_parts_mut(
-            out_buf as *mut f32, nframes as usize * data.out_channels as usize
-        );
-
-        (data.data_cb)(ibuf, obuf) as c_long
-    }
+    unsafe { run_data_callback::<f32>(user, in_buf, out_buf, nframes) }
 }
 
 extern fn state_callback_noop(_stm: *const cubeb_stream,
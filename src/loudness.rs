@@ -0,0 +1,343 @@
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use meter::{metered_with, Side};
+use triple_buffer::{triple_buffer, Reader, Writer};
+use wav::{self, WavReader};
+use {Channel, ChannelLayout, DataCallback, Sample};
+
+// Loudness after ITU-R BS.1770-4 and EBU R128: channels are K-weighted,
+// summed by power with the weights of their position, and measured over
+// 400ms (momentary) and 3s (short-term) windows sliding by 100ms. The
+// integrated loudness gates the 400ms blocks at -70 LUFS and then 10 LU
+// below their mean; the loudness range (EBU Tech 3342) is the spread between
+// the 10th and 95th percentiles of the short-term values, gated at -70 LUFS
+// and 20 LU below their mean.
+//
+// Gated values are kept in histograms of 0.1 LU bins, with the exact power
+// of the blocks in each, so that measuring for hours takes no more memory
+// and never allocates on the audio thread.
+
+const SUB_BLOCKS_MOMENTARY: usize = 4;
+const SUB_BLOCKS_SHORT_TERM: usize = 30;
+const ABSOLUTE_GATE: f64 = -70.0;
+const BINS_PER_LU: f64 = 10.0;
+const BINS: usize = 1000;
+
+// In LUFS, or LU for the range; -inf when not measured yet.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Loudness {
+    pub momentary: f64,
+    pub short_term: f64,
+    pub integrated: f64,
+    pub range: f64,
+    pub max_momentary: f64,
+    pub max_short_term: f64,
+}
+
+impl Default for Loudness {
+    fn default() -> Loudness {
+        Loudness {
+            momentary: f64::NEG_INFINITY,
+            short_term: f64::NEG_INFINITY,
+            integrated: f64::NEG_INFINITY,
+            range: 0.0,
+            max_momentary: f64::NEG_INFINITY,
+            max_short_term: f64::NEG_INFINITY,
+        }
+    }
+}
+
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+// Weight of each channel of `layout`: surrounds count for 1.41, the LFE not
+// at all. Channels of undefined layouts all weigh 1.
+pub fn channel_weights(layout: ChannelLayout, channels: usize) -> Vec<f64> {
+    let positions = layout.channels();
+    if positions.len() != channels {
+        return vec![1.0; channels];
+    }
+    positions.iter().map(|&c| match c {
+        Channel::LFE => 0.0,
+        Channel::LeftSurround | Channel::RightSurround => 1.41,
+        _ => 1.0,
+    }).collect()
+}
+
+#[derive(Copy, Clone, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+// The two stages of the K-weighting filter, a high shelf modelling the head
+// and a high pass, at `rate`.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let fs = rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / fs).tan();
+    let vh = 10_f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+// Counts and summed powers of gated blocks by loudness.
+struct Histogram {
+    counts: Vec<u64>,
+    powers: Vec<f64>,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram { counts: vec![0; BINS], powers: vec![0.0; BINS] }
+    }
+
+    fn bin(lufs: f64) -> usize {
+        (((lufs - ABSOLUTE_GATE) * BINS_PER_LU) as usize).min(BINS - 1)
+    }
+
+    fn bin_lufs(bin: usize) -> f64 {
+        ABSOLUTE_GATE + (bin as f64 + 0.5) / BINS_PER_LU
+    }
+
+    fn add(&mut self, power: f64) {
+        let lufs = to_lufs(power);
+        if lufs >= ABSOLUTE_GATE {
+            let bin = Histogram::bin(lufs);
+            self.counts[bin] += 1;
+            self.powers[bin] += power;
+        }
+    }
+
+    fn clear(&mut self) {
+        for (c, p) in self.counts.iter_mut().zip(self.powers.iter_mut()) {
+            *c = 0;
+            *p = 0.0;
+        }
+    }
+
+    // First bin at or above `relative` LU from the mean, if any block passed
+    // the absolute gate.
+    fn relative_gate(&self, relative: f64) -> Option<usize> {
+        let count: u64 = self.counts.iter().sum();
+        if count == 0 {
+            return None;
+        }
+        let mean = self.powers.iter().sum::<f64>() / count as f64;
+        Some(Histogram::bin((to_lufs(mean) + relative).max(ABSOLUTE_GATE)))
+    }
+
+    fn gated_mean(&self, relative: f64) -> f64 {
+        let gate = match self.relative_gate(relative) {
+            Some(gate) => gate,
+            None => return f64::NEG_INFINITY,
+        };
+        let count: u64 = self.counts[gate ..].iter().sum();
+        if count == 0 {
+            return f64::NEG_INFINITY;
+        }
+        to_lufs(self.powers[gate ..].iter().sum::<f64>() / count as f64)
+    }
+
+    fn range(&self) -> f64 {
+        let gate = match self.relative_gate(-20.0) {
+            Some(gate) => gate,
+            None => return 0.0,
+        };
+        let count: u64 = self.counts[gate ..].iter().sum();
+        if count == 0 {
+            return 0.0;
+        }
+        let percentile = |p: f64| {
+            let target = (p * (count - 1) as f64).round() as u64;
+            let mut seen = 0;
+            for (i, &c) in self.counts[gate ..].iter().enumerate() {
+                seen += c;
+                if seen > target {
+                    return Histogram::bin_lufs(gate + i);
+                }
+            }
+            Histogram::bin_lufs(BINS - 1)
+        };
+        percentile(0.95) - percentile(0.10)
+    }
+}
+
+// The audio thread side, also usable on its own to measure offline.
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    // weighted power of the current 100ms sub-block so far
+    energy: f64,
+    filled: usize,
+    sub_block: usize,
+    // powers of the last sub-blocks, `next` being the oldest
+    sub_blocks: [f64; SUB_BLOCKS_SHORT_TERM],
+    next: usize,
+    seen: usize,
+    blocks: Histogram,
+    short_terms: Histogram,
+    loudness: Loudness,
+    writer: Writer<Loudness>,
+    reset: Arc<AtomicBool>,
+}
+
+// The UI side.
+pub struct LoudnessReader {
+    reader: Reader<Loudness>,
+    reset: Arc<AtomicBool>,
+}
+
+impl LoudnessReader {
+    // As of the last 100ms.
+    pub fn loudness(&mut self) -> Loudness {
+        *self.reader.read()
+    }
+
+    // Starts measuring anew.
+    pub fn reset(&self) {
+        self.reset.store(true, Ordering::Release);
+    }
+}
+
+pub fn loudness_meter(rate: u32, channels: u32,
+                      layout: ChannelLayout) -> (LoudnessMeter, LoudnessReader) {
+    assert!(rate >= 10 && channels > 0);
+    let channels = channels as usize;
+    let (writer, reader) = triple_buffer(Loudness::default());
+    let reset = Arc::new(AtomicBool::new(false));
+    let meter = LoudnessMeter {
+        channels,
+        weights: channel_weights(layout, channels),
+        filters: vec![k_weighting(rate); channels],
+        energy: 0.0,
+        filled: 0,
+        sub_block: rate as usize / 10,
+        sub_blocks: [0.0; SUB_BLOCKS_SHORT_TERM],
+        next: 0,
+        seen: 0,
+        blocks: Histogram::new(),
+        short_terms: Histogram::new(),
+        loudness: Loudness::default(),
+        writer,
+        reset: reset.clone(),
+    };
+    (meter, LoudnessReader { reader, reset })
+}
+
+impl LoudnessMeter {
+    // Interleaved frames.
+    pub fn process<T: Sample>(&mut self, frames: &[T]) {
+        if self.reset.swap(false, Ordering::Acquire) {
+            self.reset();
+        }
+        for frame in frames.chunks(self.channels) {
+            for (i, s) in frame.iter().enumerate() {
+                let [ref mut shelf, ref mut high_pass] = self.filters[i];
+                let y = high_pass.process(shelf.process(s.to_f32() as f64));
+                self.energy += self.weights[i] * y * y;
+            }
+            self.filled += 1;
+            if self.filled == self.sub_block {
+                self.end_sub_block();
+            }
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        self.sub_blocks[self.next] = self.energy / self.sub_block as f64;
+        self.next = (self.next + 1) % SUB_BLOCKS_SHORT_TERM;
+        self.seen += 1;
+        self.energy = 0.0;
+        self.filled = 0;
+
+        let mean = |blocks: &[f64; SUB_BLOCKS_SHORT_TERM], next: usize, count: usize| {
+            (1 ..= count).map(|i| blocks[(next + SUB_BLOCKS_SHORT_TERM - i) % blocks.len()])
+                .sum::<f64>() / count as f64
+        };
+        let l = &mut self.loudness;
+        if self.seen >= SUB_BLOCKS_MOMENTARY {
+            let power = mean(&self.sub_blocks, self.next, SUB_BLOCKS_MOMENTARY);
+            self.blocks.add(power);
+            l.momentary = to_lufs(power);
+            l.max_momentary = l.max_momentary.max(l.momentary);
+            l.integrated = self.blocks.gated_mean(-10.0);
+        }
+        if self.seen >= SUB_BLOCKS_SHORT_TERM {
+            let power = mean(&self.sub_blocks, self.next, SUB_BLOCKS_SHORT_TERM);
+            self.short_terms.add(power);
+            l.short_term = to_lufs(power);
+            l.max_short_term = l.max_short_term.max(l.short_term);
+            l.range = self.short_terms.range();
+        }
+        self.writer.write(*l);
+    }
+
+    pub fn loudness(&self) -> Loudness {
+        self.loudness
+    }
+
+    // Starts measuring anew, from the next sub-block.
+    pub fn reset(&mut self) {
+        self.seen = 0;
+        self.blocks.clear();
+        self.short_terms.clear();
+        self.loudness = Loudness::default();
+    }
+}
+
+// Wraps the data callback of a stream, measuring its `channels` channel
+// input or what it returned of its output.
+pub fn metered<T: Sample>(data_cb: DataCallback<T>, side: Side, rate: u32, channels: u32,
+                          layout: ChannelLayout) -> (DataCallback<T>, LoudnessReader) {
+    let (mut meter, reader) = loudness_meter(rate, channels, layout);
+    (metered_with(data_cb, side, channels, move |buf: &[T]| meter.process(buf)), reader)
+}
+
+// Measures a whole WAV file, weighting its channels after its channel mask.
+pub fn measure_file<P: AsRef<Path>>(path: P) -> wav::Result<Loudness> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let (mut meter, _) = loudness_meter(spec.rate, spec.channels as u32, spec.layout);
+    let mut buf = vec![0_f32; 4096 * spec.channels as usize];
+    loop {
+        let frames = reader.read(&mut buf)?;
+        if frames == 0 {
+            break;
+        }
+        meter.process(&buf[.. frames * spec.channels as usize]);
+    }
+    Ok(meter.loudness())
+}
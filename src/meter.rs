@@ -0,0 +1,226 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use triple_buffer::{triple_buffer, Reader, Writer};
+use {DataCallback, Sample};
+
+// Per channel levels of the audio going through a data callback, for level
+// meters. The audio thread updates them every callback and publishes them
+// through a triple buffer; the UI reads the latest ones whenever it redraws.
+//
+// Peaks fall at `Options::peak_decay` once past, like a peak programme meter,
+// and are also held at their highest until `MeterReader::reset_holds`. The
+// RMS level follows the mean square through one pole filters, with their own
+// time constants when it rises and falls. True peaks are those of the signal
+// upsampled 4 times (ITU-R BS.1770 annex 2), catching the overs between
+// samples that a DAC will produce.
+
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Options {
+    // RMS time constants, rising and falling
+    pub rms_attack: Duration,
+    pub rms_release: Duration,
+    // in dB per second
+    pub peak_decay: f32,
+    pub true_peak: bool,
+}
+
+impl Default for Options {
+    // A 300ms RMS like VU meters, peaks falling 20dB in 1.7s as IEC type I
+    // peak meters.
+    fn default() -> Options {
+        Options {
+            rms_attack: Duration::from_millis(300),
+            rms_release: Duration::from_millis(300),
+            peak_decay: 20.0 / 1.7,
+            true_peak: true,
+        }
+    }
+}
+
+// Linear amplitudes, 1.0 being full scale.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Levels {
+    pub peak: f32,
+    pub peak_hold: f32,
+    pub rms: f32,
+    // zero unless `Options::true_peak`
+    pub true_peak: f32,
+    pub true_peak_hold: f32,
+}
+
+pub fn to_dbfs(level: f32) -> f32 {
+    20.0 * level.log10()
+}
+
+// 4 phase windowed sinc interpolator, phase 0 being the input delayed by
+// half the filter.
+fn interpolator() -> [[f32; TAPS_PER_PHASE]; OVERSAMPLING] {
+    let len = OVERSAMPLING * TAPS_PER_PHASE;
+    let mut phases = [[0_f32; TAPS_PER_PHASE]; OVERSAMPLING];
+    for (p, phase) in phases.iter_mut().enumerate() {
+        let mut sum = 0.0;
+        for (k, tap) in phase.iter_mut().enumerate() {
+            let m = (OVERSAMPLING * k + p) as f64;
+            let t = (m - (len / 2) as f64) / OVERSAMPLING as f64;
+            let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+            // Blackman, over len + 1 taps so that it is symmetric
+            let x = 2.0 * PI * m / len as f64;
+            let window = 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos();
+            *tap = (sinc * window) as f32;
+            sum += sinc * window;
+        }
+        for tap in phase.iter_mut() {
+            *tap /= sum as f32;
+        }
+    }
+    phases
+}
+
+#[derive(Copy, Clone, Default)]
+struct Channel {
+    levels: Levels,
+    mean_square: f32,
+    // the last inputs, `position` being the oldest
+    history: [f32; TAPS_PER_PHASE],
+    position: usize,
+}
+
+// The audio thread side.
+pub struct Meter {
+    channels: usize,
+    state: Vec<Channel>,
+    attack: f32,
+    release: f32,
+    decay: f32,
+    phases: Option<[[f32; TAPS_PER_PHASE]; OVERSAMPLING]>,
+    writer: Writer<Vec<Levels>>,
+    reset: Arc<AtomicBool>,
+}
+
+// The UI side.
+pub struct MeterReader {
+    reader: Reader<Vec<Levels>>,
+    reset: Arc<AtomicBool>,
+}
+
+impl MeterReader {
+    // One per channel, as of the last callback.
+    pub fn levels(&mut self) -> &[Levels] {
+        self.reader.read()
+    }
+
+    pub fn reset_holds(&self) {
+        self.reset.store(true, Ordering::Release);
+    }
+}
+
+fn coefficient(time: Duration, rate: u32) -> f32 {
+    let samples = time.as_secs_f64() * rate as f64;
+    if samples <= 0.0 {
+        1.0
+    } else {
+        (1.0 - (-1.0 / samples).exp()) as f32
+    }
+}
+
+pub fn meter(rate: u32, channels: u32, options: &Options) -> (Meter, MeterReader) {
+    assert!(rate > 0 && channels > 0);
+    let channels = channels as usize;
+    let (writer, reader) = triple_buffer(vec![Levels::default(); channels]);
+    let reset = Arc::new(AtomicBool::new(false));
+    let meter = Meter {
+        channels,
+        state: vec![Channel::default(); channels],
+        attack: coefficient(options.rms_attack, rate),
+        release: coefficient(options.rms_release, rate),
+        decay: 10_f32.powf(-options.peak_decay / (20.0 * rate as f32)),
+        phases: if options.true_peak { Some(interpolator()) } else { None },
+        writer,
+        reset: reset.clone(),
+    };
+    (meter, MeterReader { reader, reset })
+}
+
+impl Meter {
+    // Interleaved frames, then publishes the levels.
+    pub fn process<T: Sample>(&mut self, frames: &[T]) {
+        if self.reset.swap(false, Ordering::Acquire) {
+            for c in &mut self.state {
+                c.levels.peak_hold = 0.0;
+                c.levels.true_peak_hold = 0.0;
+            }
+        }
+        for (i, c) in self.state.iter_mut().enumerate() {
+            let (attack, release, decay) = (self.attack, self.release, self.decay);
+            let l = &mut c.levels;
+            for s in frames.iter().skip(i).step_by(self.channels) {
+                let x = s.to_f32();
+                let square = x * x;
+                let k = if square > c.mean_square { attack } else { release };
+                c.mean_square += k * (square - c.mean_square);
+                l.peak = x.abs().max(l.peak * decay);
+                l.peak_hold = l.peak_hold.max(l.peak);
+                if let Some(ref phases) = self.phases {
+                    c.history[c.position] = x;
+                    c.position = (c.position + 1) % TAPS_PER_PHASE;
+                    let mut peak = l.true_peak * decay;
+                    for phase in phases.iter() {
+                        // taps from the newest input to the oldest
+                        let mut y = 0.0;
+                        for (k, h) in phase.iter().enumerate() {
+                            let j = (c.position + TAPS_PER_PHASE - 1 - k) % TAPS_PER_PHASE;
+                            y += h * c.history[j];
+                        }
+                        peak = peak.max(y.abs());
+                    }
+                    l.true_peak = peak;
+                    l.true_peak_hold = l.true_peak_hold.max(peak);
+                }
+            }
+            l.rms = c.mean_square.sqrt();
+        }
+        let published = self.writer.back();
+        for (p, c) in published.iter_mut().zip(&self.state) {
+            *p = c.levels;
+        }
+        self.writer.publish();
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Side {
+    Input,
+    Output,
+}
+
+// Wraps the data callback of a stream, handing `process` its `channels`
+// channel input or what it returned of its output.
+pub fn metered_with<T: Sample, F: FnMut(&[T]) + 'static>(mut data_cb: DataCallback<T>, side: Side,
+                                                         channels: u32, mut process: F)
+        -> DataCallback<T> {
+    let ch = channels as usize;
+    Box::new(move |ibuf: &[T], obuf: &mut [T]| {
+        if side == Side::Input {
+            process(ibuf);
+        }
+        let returned = data_cb(ibuf, obuf);
+        if side == Side::Output {
+            process(&obuf[.. returned.min(obuf.len() / ch) * ch]);
+        }
+        returned
+    })
+}
+
+// Wraps the data callback of a stream, metering its `channels` channel input
+// or what it returned of its output.
+pub fn metered<T: Sample>(data_cb: DataCallback<T>, side: Side, rate: u32, channels: u32,
+                          options: &Options) -> (DataCallback<T>, MeterReader) {
+    let (mut meter, reader) = meter(rate, channels, options);
+    (metered_with(data_cb, side, channels, move |buf: &[T]| meter.process(buf)), reader)
+}
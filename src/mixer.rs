@@ -0,0 +1,377 @@
+use std::f32::consts::FRAC_PI_4;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use command::{command_channel, CommandReceiver, CommandSender};
+use gc::{self, Collector, CollectorThread, Retirer};
+use param::AtomicF32;
+use source::Source;
+use units::Latency;
+use {Context, DataCallback, DevId, Error, Result, Stream, StreamParams};
+
+// Mixes any number of sources into a single output stream, instead of a
+// stream per sound. Sources are handed to the callback through a command
+// queue, and freed by a collector thread once they end or are removed, so
+// the audio thread never locks nor frees.
+//
+// At most `max_voices` sources play at once. A source added past that limit
+// steals the voice of lowest priority, the oldest one among equals, unless
+// every playing source has a higher priority than the new one, which is then
+// dropped. A stolen or removed voice fades out over `FADE_FRAMES`.
+
+const CHUNK_FRAMES: usize = 256;
+const FADE_FRAMES: usize = 64;
+const COLLECT_PERIOD: Duration = Duration::from_millis(50);
+
+struct Controls {
+    gain: AtomicF32,
+    pan: AtomicF32,
+    muted: AtomicBool,
+    stopped: AtomicBool,
+    stolen: AtomicBool,
+    finished: AtomicBool,
+}
+
+// Dropping a handle leaves its source playing.
+pub struct SourceHandle {
+    controls: Arc<Controls>,
+}
+
+impl SourceHandle {
+    // Linear, 1.0 by default.
+    pub fn set_gain(&self, gain: f32) {
+        self.controls.gain.store(gain.max(0.0), Ordering::Relaxed);
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.controls.gain.load(Ordering::Relaxed)
+    }
+
+    // From -1.0, left only, to 1.0, right only. Only moves the first two
+    // channels of the output.
+    pub fn set_pan(&self, pan: f32) {
+        self.controls.pan.store(pan.clamp(-1.0, 1.0), Ordering::Relaxed);
+    }
+
+    pub fn pan(&self) -> f32 {
+        self.controls.pan.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.controls.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.controls.muted.load(Ordering::Relaxed)
+    }
+
+    // Fades the source out and removes it from the mixer.
+    pub fn stop(&self) {
+        self.controls.stopped.store(true, Ordering::Relaxed);
+    }
+
+    // Played to the end, stopped, stolen or dropped, and no longer mixed.
+    pub fn is_finished(&self) -> bool {
+        self.controls.finished.load(Ordering::Acquire)
+    }
+
+    // Lost its voice to another source, or never got one.
+    pub fn was_stolen(&self) -> bool {
+        self.controls.stolen.load(Ordering::Acquire)
+    }
+}
+
+struct Voice {
+    source: Box<dyn Source>,
+    controls: Arc<Controls>,
+    priority: u8,
+    id: u64,
+    // gains applied at the end of the last period, ramped from to avoid clicks
+    left: f32,
+    right: f32,
+    fading: bool,
+    ended: bool,
+}
+
+// A voice no longer mixed, waiting to be freed.
+pub struct Retired(Box<Voice>);
+
+struct Shared {
+    sender: Mutex<CommandSender<Box<Voice>>>,
+    next_id: AtomicU64,
+    playing: AtomicUsize,
+    steals: AtomicUsize,
+}
+
+// Adds sources from any thread. Cheap to clone.
+#[derive(Clone)]
+pub struct MixerHandle {
+    shared: Arc<Shared>,
+}
+
+impl MixerHandle {
+    pub fn add<S: Source + 'static>(&self, source: S) -> Result<SourceHandle> {
+        self.add_with_priority(source, 0)
+    }
+
+    // Errs with `Undefined` when too many sources are waiting to be picked up
+    // by the callback.
+    pub fn add_with_priority<S: Source + 'static>(&self, source: S, priority: u8)
+            -> Result<SourceHandle> {
+        let controls = Arc::new(Controls {
+            gain: AtomicF32::new(1.0),
+            pan: AtomicF32::new(0.0),
+            muted: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            stolen: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+        });
+        let voice = Box::new(Voice {
+            source: Box::new(source),
+            controls: controls.clone(),
+            priority,
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            left: 0.0,
+            right: 0.0,
+            fading: false,
+            ended: false,
+        });
+        match self.shared.sender.lock().unwrap().send(voice) {
+            Ok(()) => Ok(SourceHandle { controls }),
+            Err(_) => Err(Error::Undefined),
+        }
+    }
+
+    // Voices in use at the last period, fading ones included.
+    pub fn playing(&self) -> usize {
+        self.shared.playing.load(Ordering::Relaxed)
+    }
+
+    // Voices stolen so far, and sources dropped for lack of a voice.
+    pub fn steals(&self) -> usize {
+        self.shared.steals.load(Ordering::Relaxed)
+    }
+}
+
+// Gains of the first two channels for a pan position, unity at the center.
+fn pan_gains(gain: f32, pan: f32) -> (f32, f32) {
+    let angle = (pan + 1.0) * FRAC_PI_4;
+    let left = (angle.cos() * 2_f32.sqrt()).min(1.0);
+    let right = (angle.sin() * 2_f32.sqrt()).min(1.0);
+    (gain * left, gain * right)
+}
+
+// No panning on a single channel: the voice plays at its gain wherever it is
+// panned.
+fn gains(channels: usize, gain: f32, pan: f32) -> (f32, f32) {
+    if channels == 1 {
+        (gain, gain)
+    } else {
+        pan_gains(gain, pan)
+    }
+}
+
+struct Mix {
+    receiver: CommandReceiver<Box<Voice>>,
+    retirer: Retirer<Retired>,
+    // boxed so that retiring moves a pointer
+    #[allow(clippy::vec_box)]
+    voices: Vec<Box<Voice>>,
+    max_voices: usize,
+    // `voices` never grows past this, so that it never allocates
+    slots: usize,
+    channels: usize,
+    scratch: Vec<f32>,
+    shared: Arc<Shared>,
+}
+
+impl Mix {
+    fn steal(&mut self, priority: u8) -> bool {
+        let victim = self.voices.iter_mut()
+            .filter(|v| !v.fading && v.priority <= priority)
+            .min_by_key(|v| (v.priority, v.id));
+        match victim {
+            Some(voice) => {
+                voice.fading = true;
+                voice.controls.stolen.store(true, Ordering::Release);
+                self.shared.steals.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn accept(&mut self) {
+        // fading voices keep their slot for a period, hence the doubled room
+        while self.voices.len() < self.slots {
+            let mut voice = match self.receiver.try_recv() {
+                Some(voice) => voice,
+                None => break,
+            };
+            let active = self.voices.iter().filter(|v| !v.fading).count();
+            if active >= self.max_voices && !self.steal(voice.priority) {
+                voice.controls.stolen.store(true, Ordering::Release);
+                voice.ended = true;
+                self.shared.steals.fetch_add(1, Ordering::Relaxed);
+            } else if !voice.controls.muted.load(Ordering::Relaxed) {
+                // starts at full gain, so that attacks are kept
+                let controls = &voice.controls;
+                let (left, right) = gains(self.channels,
+                                          controls.gain.load(Ordering::Relaxed),
+                                          controls.pan.load(Ordering::Relaxed));
+                voice.left = left;
+                voice.right = right;
+            }
+            self.voices.push(voice);
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        let ch = self.channels;
+        let frames = out.len() / ch;
+        for voice in self.voices.iter_mut() {
+            if voice.ended {
+                continue;
+            }
+            let controls = &voice.controls;
+            if controls.stopped.load(Ordering::Relaxed) {
+                voice.fading = true;
+            }
+            let (left, right) = if voice.fading || controls.muted.load(Ordering::Relaxed) {
+                (0.0, 0.0)
+            } else {
+                gains(ch, controls.gain.load(Ordering::Relaxed),
+                      controls.pan.load(Ordering::Relaxed))
+            };
+            let ramp = if voice.fading { FADE_FRAMES.min(frames) } else { frames };
+            let scratch = &mut self.scratch[.. frames * ch];
+            let filled = voice.source.fill(scratch, ch).min(frames);
+            let played = if voice.fading { filled.min(ramp) } else { filled };
+            for (i, (o, s)) in out.chunks_mut(ch).zip(scratch.chunks(ch)).take(played).enumerate() {
+                let t = ((i + 1) as f32 / ramp as f32).min(1.0);
+                let l = voice.left + (left - voice.left) * t;
+                let r = voice.right + (right - voice.right) * t;
+                if ch == 1 {
+                    o[0] += s[0] * l;
+                    continue;
+                }
+                o[0] += s[0] * l;
+                o[1] += s[1] * r;
+                let centre = 0.5 * (l + r);
+                for (o, s) in o[2..].iter_mut().zip(s[2..].iter()) {
+                    *o += s * centre;
+                }
+            }
+            voice.left = left;
+            voice.right = right;
+            if filled < frames || voice.fading {
+                voice.ended = true;
+            }
+        }
+    }
+
+    fn retire(&mut self) {
+        let mut i = 0;
+        while i < self.voices.len() {
+            if !self.voices[i].ended {
+                i += 1;
+                continue;
+            }
+            let voice = self.voices.swap_remove(i);
+            voice.controls.finished.store(true, Ordering::Release);
+            if let Err(Retired(voice)) = self.retirer.retire(Retired(voice)) {
+                // the collector is behind, try again next period
+                self.voices.push(voice);
+                break;
+            }
+        }
+        self.shared.playing.store(self.voices.len(), Ordering::Relaxed);
+    }
+
+    fn process(&mut self, out: &mut [f32]) {
+        for v in out.iter_mut() {
+            *v = 0.0;
+        }
+        self.accept();
+        for chunk in out.chunks_mut(CHUNK_FRAMES * self.channels) {
+            self.render(chunk);
+        }
+        self.retire();
+    }
+}
+
+// The output callback of a `channels` channel mixer, without a stream, e.g.
+// to render offline. `capacity` bounds the sources waiting to start.
+pub fn mixer_callback(channels: u32, max_voices: usize, capacity: usize)
+        -> (DataCallback<f32>, MixerHandle, Collector<Retired>) {
+    assert!(channels > 0 && max_voices > 0 && capacity > 0);
+    let channels = channels as usize;
+    let (sender, receiver) = command_channel(capacity);
+    let (retirer, collector) = gc::channel(capacity + 2 * max_voices);
+    let shared = Arc::new(Shared {
+        sender: Mutex::new(sender),
+        next_id: AtomicU64::new(0),
+        playing: AtomicUsize::new(0),
+        steals: AtomicUsize::new(0),
+    });
+    let mut mix = Mix {
+        receiver,
+        retirer,
+        voices: Vec::with_capacity(2 * max_voices),
+        max_voices,
+        slots: 2 * max_voices,
+        channels,
+        scratch: vec![0.0; CHUNK_FRAMES * channels],
+        shared: shared.clone(),
+    };
+    let cb: DataCallback<f32> = Box::new(move |_: &[f32], obuf: &mut [f32]| {
+        mix.process(obuf);
+        obuf.len() / channels
+    });
+    (cb, MixerHandle { shared }, collector)
+}
+
+pub struct Mixer {
+    stream: Stream<f32>,
+    handle: MixerHandle,
+    _collector: CollectorThread,
+}
+
+impl Mixer {
+    pub fn new<L: Into<Latency>>(ctx: &Context, stream_name: &str, device: Option<&DevId>,
+                                 params: StreamParams<f32>, latency: L, max_voices: usize)
+            -> Result<Mixer> {
+        let (cb, handle, collector) = mixer_callback(params.channels(), max_voices,
+                                                     4 * max_voices);
+        let stream = Stream::new(ctx, stream_name, None, None, device, Some(params),
+                                 latency, cb, None)?;
+        Ok(Mixer { stream, handle, _collector: collector.spawn(COLLECT_PERIOD) })
+    }
+
+    pub fn start(&self) -> Result<()> {
+        self.stream.start()
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.stream.stop()
+    }
+
+    pub fn add<S: Source + 'static>(&self, source: S) -> Result<SourceHandle> {
+        self.handle.add(source)
+    }
+
+    pub fn add_with_priority<S: Source + 'static>(&self, source: S, priority: u8)
+            -> Result<SourceHandle> {
+        self.handle.add_with_priority(source, priority)
+    }
+
+    // For adding sources from other threads.
+    pub fn handle(&self) -> MixerHandle {
+        self.handle.clone()
+    }
+
+    pub fn stream(&self) -> &Stream<f32> {
+        &self.stream
+    }
+}
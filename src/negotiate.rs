@@ -0,0 +1,173 @@
+use resampler::Quality;
+use units::Frames;
+use {ChannelLayout, DeviceFmt, DeviceInfo, Error, Result, Sample, SampleFormat, StreamParams};
+use {DEVICE_FMT_F32BE, DEVICE_FMT_F32LE, DEVICE_FMT_S16BE, DEVICE_FMT_S16LE};
+
+// Picks device-side stream parameters from what a device reports it can do,
+// keeping the callback side as asked for and filling in the `with_device_*`
+// options of `StreamParams` for whatever does not match.
+
+// What a `DeviceInfo` reports, detached from the device collection so that it
+// can be kept or built by hand. Zero maximums mean the backend did not say.
+#[derive(Debug, Copy, Clone)]
+pub struct Capabilities {
+    pub formats: DeviceFmt,
+    pub default_format: DeviceFmt,
+    pub min_rate: u32,
+    pub max_rate: u32,
+    pub default_rate: u32,
+    pub max_channels: u32,
+    pub latency_lo: u32,
+    pub latency_hi: u32,
+}
+
+impl<'a, 'b> From<&'b DeviceInfo<'a>> for Capabilities {
+    fn from(info: &'b DeviceInfo<'a>) -> Capabilities {
+        Capabilities {
+            formats: info.format(),
+            default_format: info.default_format(),
+            min_rate: info.min_rate(),
+            max_rate: info.max_rate(),
+            default_rate: info.default_rate(),
+            max_channels: info.max_channels(),
+            latency_lo: info.latency_lo(),
+            latency_hi: info.latency_hi(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RateConstraint {
+    // The device has to run at the callback's rate.
+    Exact,
+    // The device runs at the supported rate closest to the callback's, cult
+    // resampling in between.
+    Closest,
+    // As `Closest`, but going to the device's default rate instead when it
+    // reports a usable one.
+    DeviceDefault,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Constraints {
+    pub rate: RateConstraint,
+    // resampler used when the device rate differs
+    pub quality: Quality,
+    // wanted latency in frames at the callback's rate, 0 for the lowest the
+    // device allows
+    pub latency_frames: u32,
+}
+
+impl Default for Constraints {
+    fn default() -> Constraints {
+        Constraints { rate: RateConstraint::Closest, quality: Quality::Sinc, latency_frames: 0 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Conversion {
+    // The device does not list the callback's sample format. Not something
+    // cult inserts: the backend converts to the device's default one.
+    BackendFormat { callback: SampleFormat, device: DeviceFmt },
+    Rate { callback: u32, device: u32 },
+    // Mixed to a layout of `device` channels when the callback's layout is
+    // known, routed to the first `device` channels otherwise.
+    Channels { callback: u32, device: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct Negotiated<T: Sample> {
+    // ready for `Stream::new`
+    pub params: StreamParams<T>,
+    // in frames at the callback's rate, as `Stream::new` takes it
+    pub latency_frames: u32,
+    pub conversions: Vec<Conversion>,
+}
+
+fn device_format(format: SampleFormat) -> DeviceFmt {
+    match format {
+        SampleFormat::Signed16LE => DEVICE_FMT_S16LE,
+        SampleFormat::Signed16BE => DEVICE_FMT_S16BE,
+        SampleFormat::Float32LE => DEVICE_FMT_F32LE,
+        SampleFormat::Float32BE => DEVICE_FMT_F32BE,
+    }
+}
+
+// The usual speaker arrangement for a channel count.
+fn layout_for(channels: u32) -> Option<ChannelLayout> {
+    match channels {
+        1 => Some(ChannelLayout::Mono),
+        2 => Some(ChannelLayout::Stereo),
+        3 => Some(ChannelLayout::F3),
+        4 => Some(ChannelLayout::F2_2),
+        5 => Some(ChannelLayout::F3_2),
+        6 => Some(ChannelLayout::F3_2_LFE),
+        7 => Some(ChannelLayout::F3_R3_LFE),
+        8 => Some(ChannelLayout::F3_4_LFE),
+        _ => None,
+    }
+}
+
+// Fails with `NotSupported` when `RateConstraint::Exact` cannot be met.
+pub fn negotiate<T: Sample>(desired: StreamParams<T>, constraints: &Constraints,
+                            device: &Capabilities) -> Result<Negotiated<T>> {
+    if desired.rate() == 0 || desired.channels() == 0 {
+        return Err(Error::InvalidParameter);
+    }
+    let mut params = StreamParams::<T>::new(desired.rate(), desired.channels(), desired.layout())
+        .with_mix_options(desired.mix);
+    let mut conversions = Vec::new();
+
+    let format = device_format(T::format());
+    if !device.formats.is_empty() && !device.formats.contains(format) {
+        conversions.push(Conversion::BackendFormat {
+            callback: T::format(),
+            device: device.default_format,
+        });
+    }
+
+    let rate = desired.rate();
+    let max_rate = if device.max_rate == 0 { u32::MAX } else { device.max_rate };
+    let min_rate = device.min_rate.min(max_rate);
+    let in_range = |r: u32| r != 0 && r >= min_rate && r <= max_rate;
+    let device_rate = if in_range(rate) {
+        rate
+    } else if constraints.rate == RateConstraint::DeviceDefault &&
+              in_range(device.default_rate) {
+        device.default_rate
+    } else {
+        rate.clamp(min_rate, max_rate)
+    };
+    if device_rate != rate {
+        if constraints.rate == RateConstraint::Exact {
+            return Err(Error::NotSupported);
+        }
+        params = params.with_device_rate(device_rate, constraints.quality);
+        conversions.push(Conversion::Rate { callback: rate, device: device_rate });
+    }
+
+    let channels = desired.channels();
+    if device.max_channels != 0 && channels > device.max_channels {
+        let device_channels = device.max_channels;
+        let mixable = !desired.layout().channels().is_empty() &&
+                      desired.layout().channel_count() == channels;
+        params = match layout_for(device_channels) {
+            Some(layout) if mixable => params.with_device_layout(layout),
+            _ => params.with_device_channels(device_channels),
+        };
+        conversions.push(Conversion::Channels { callback: channels, device: device_channels });
+    }
+
+    let wanted = constraints.latency_frames as u64 * device_rate as u64 / rate as u64;
+    let hi = if device.latency_hi == 0 { u32::MAX } else { device.latency_hi };
+    let device_latency = (wanted.min(u32::MAX as u64) as u32).clamp(device.latency_lo.min(hi), hi);
+    let latency_frames = Frames::from(device_latency).at_rate(device_rate, rate).0 as u32;
+
+    Ok(Negotiated { params, latency_frames, conversions })
+}
+
+// `negotiate` against a device of a `DeviceCollection`.
+pub fn negotiate_device<T: Sample>(desired: StreamParams<T>, constraints: &Constraints,
+                                   device: &DeviceInfo) -> Result<Negotiated<T>> {
+    negotiate(desired, constraints, &Capabilities::from(device))
+}
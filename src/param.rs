@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+// Floating point cells for continuous parameters (gain, frequency, ...) that
+// the control side writes and the audio callback reads every period.
+
+#[derive(Debug, Default)]
+pub struct AtomicF32 {
+    bits: AtomicU32,
+}
+
+impl AtomicF32 {
+    pub fn new(value: f32) -> AtomicF32 {
+        AtomicF32 { bits: AtomicU32::new(value.to_bits()) }
+    }
+
+    pub fn load(&self, order: Ordering) -> f32 {
+        f32::from_bits(self.bits.load(order))
+    }
+
+    pub fn store(&self, value: f32, order: Ordering) {
+        self.bits.store(value.to_bits(), order)
+    }
+
+    pub fn swap(&self, value: f32, order: Ordering) -> f32 {
+        f32::from_bits(self.bits.swap(value.to_bits(), order))
+    }
+
+    pub fn into_inner(self) -> f32 {
+        f32::from_bits(self.bits.into_inner())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AtomicF64 {
+    bits: AtomicU64,
+}
+
+impl AtomicF64 {
+    pub fn new(value: f64) -> AtomicF64 {
+        AtomicF64 { bits: AtomicU64::new(value.to_bits()) }
+    }
+
+    pub fn load(&self, order: Ordering) -> f64 {
+        f64::from_bits(self.bits.load(order))
+    }
+
+    pub fn store(&self, value: f64, order: Ordering) {
+        self.bits.store(value.to_bits(), order)
+    }
+
+    pub fn swap(&self, value: f64, order: Ordering) -> f64 {
+        f64::from_bits(self.bits.swap(value.to_bits(), order))
+    }
+
+    pub fn into_inner(self) -> f64 {
+        f64::from_bits(self.bits.into_inner())
+    }
+}
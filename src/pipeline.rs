@@ -0,0 +1,616 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use channel_mixer::ChannelMixer;
+use resampler::Resampler;
+use triple_buffer::{triple_buffer, Reader, Writer};
+use {DataCallback, Error, Result, Sample, StreamParams};
+
+// Conversions inserted between the device and the data callback when the
+// `StreamParams` ask for a device side that differs from the callback side.
+// The callback keeps seeing buffers in its own format, and is called in
+// chunks of at most `CHUNK_FRAMES` frames so scratch buffers never grow.
+//
+// When the rates differ the callback no longer runs in lockstep with the
+// device: resampled frames go through FIFOs, and the callback is called for
+// as many frames as the resampler needs. Resampling happens at the callback's
+// channel count, before mixing on the way out and after it on the way in.
+
+const CHUNK_FRAMES: usize = 1024;
+// in callback frames
+const FIFO_FRAMES: usize = 4 * CHUNK_FRAMES;
+
+// Mixing coefficients that the control side can replace while the stream
+// runs. Whole matrices go through a triple buffer, and the audio thread
+// copies the latest one into its mixer at the next callback.
+pub struct MixControl {
+    writer: Mutex<Writer<Vec<f32>>>,
+    len: usize,
+}
+
+impl MixControl {
+    fn new(mixer: &ChannelMixer) -> (MixControl, Reader<Vec<f32>>) {
+        let (writer, reader) = triple_buffer(mixer.matrix().to_vec());
+        (MixControl { writer: Mutex::new(writer), len: mixer.matrix().len() }, reader)
+    }
+
+    pub fn set_matrix(&self, matrix: &[f32]) -> Result<()> {
+        if matrix.len() != self.len {
+            return Err(Error::InvalidParameter);
+        }
+        let mut writer = self.writer.lock().unwrap();
+        writer.back().copy_from_slice(matrix);
+        writer.publish();
+        Ok(())
+    }
+}
+
+// Logical to physical channel map that the control side can change while the
+// stream runs. Entry `i` is the device channel of logical channel `i`, or
+// `UNROUTED`. Whole maps go through a triple buffer, so that a period is
+// never routed with half of the old map and half of the new one.
+pub struct RouteControl {
+    // with the map last set, for `map`
+    writer: Mutex<(Writer<Vec<u32>>, Vec<u32>)>,
+    logical: usize,
+    physical: usize,
+}
+
+const UNROUTED: u32 = u32::MAX;
+
+impl RouteControl {
+    fn new(logical: usize, physical: usize) -> (RouteControl, Route) {
+        let map: Vec<u32> = (0 .. logical).map(|i| {
+            if i < physical { i as u32 } else { UNROUTED }
+        }).collect();
+        let (writer, maps) = triple_buffer(map.clone());
+        let control = RouteControl {
+            writer: Mutex::new((writer, map.clone())),
+            logical,
+            physical,
+        };
+        (control, Route { map, maps, physical })
+    }
+
+    pub fn set_map(&self, map: &[Option<u32>]) -> Result<()> {
+        if map.len() != self.logical ||
+           map.iter().any(|c| c.is_some_and(|c| c as usize >= self.physical)) {
+            return Err(Error::InvalidParameter);
+        }
+        let mut guard = self.writer.lock().unwrap();
+        let (ref mut writer, ref mut current) = *guard;
+        for ((b, c), &m) in writer.back().iter_mut().zip(current.iter_mut()).zip(map.iter()) {
+            *b = m.unwrap_or(UNROUTED);
+            *c = *b;
+        }
+        writer.publish();
+        Ok(())
+    }
+
+    pub fn map(&self) -> Vec<Option<u32>> {
+        self.writer.lock().unwrap().1.iter().map(|&c| match c {
+            UNROUTED => None,
+            c => Some(c),
+        }).collect()
+    }
+}
+
+// The audio thread's side of a `RouteControl`.
+struct Route {
+    map: Vec<u32>,
+    maps: Reader<Vec<u32>>,
+    physical: usize,
+}
+
+impl Route {
+    fn update(&mut self) {
+        if self.maps.has_update() {
+            self.map.copy_from_slice(self.maps.read());
+        }
+    }
+
+    // Physical channels nobody routes to are silent.
+    fn route_out<T: Sample>(&self, logical: &[T], physical: &mut [T]) {
+        let frames = logical.chunks(self.map.len()).zip(physical.chunks_mut(self.physical));
+        for (l, p) in frames {
+            for s in p.iter_mut() {
+                *s = T::default();
+            }
+            for (s, &c) in l.iter().zip(self.map.iter()) {
+                if c != UNROUTED {
+                    let c = c as usize;
+                    p[c] = T::from_f32(p[c].to_f32() + s.to_f32());
+                }
+            }
+        }
+    }
+
+    fn route_in<T: Sample>(&self, physical: &[T], logical: &mut [T]) {
+        let frames = physical.chunks(self.physical).zip(logical.chunks_mut(self.map.len()));
+        for (p, l) in frames {
+            for (s, &c) in l.iter_mut().zip(self.map.iter()) {
+                *s = if c == UNROUTED { T::default() } else { p[c as usize] };
+            }
+        }
+    }
+}
+
+// Rates of a resampled stream, and how many callback frames sit in the
+// conversion at the end of the last callback, for latency accounting.
+pub struct RateControl {
+    callback_rate: u32,
+    device_rate: u32,
+    input_delay: AtomicU32,
+    output_delay: AtomicU32,
+}
+
+impl RateControl {
+    pub fn to_callback_frames(&self, device_frames: u64) -> u64 {
+        device_frames * self.callback_rate as u64 / self.device_rate as u64
+    }
+
+    pub fn input_delay(&self) -> u32 {
+        self.input_delay.load(Ordering::Relaxed)
+    }
+
+    pub fn output_delay(&self) -> u32 {
+        self.output_delay.load(Ordering::Relaxed)
+    }
+
+    // A latency reported by the device, at its rate, in callback frames plus
+    // what the conversion of the output, or input, holds.
+    pub fn callback_latency(&self, device_frames: u32, output: bool) -> u32 {
+        let delay = if output { self.output_delay() } else { self.input_delay() };
+        self.to_callback_frames(device_frames as u64) as u32 + delay
+    }
+}
+
+#[derive(Default)]
+pub struct Controls {
+    pub input_mix: Option<Arc<MixControl>>,
+    pub output_mix: Option<Arc<MixControl>>,
+    pub input_route: Option<Arc<RouteControl>>,
+    pub output_route: Option<Arc<RouteControl>>,
+    pub rate: Option<Arc<RateControl>>,
+}
+
+struct Mix {
+    mixer: ChannelMixer,
+    control: Arc<MixControl>,
+    matrices: Reader<Vec<f32>>,
+}
+
+// The conversions of one direction, applied a chunk at a time. Channel counts
+// go callback -> logical (after mixing) -> device (after routing).
+struct Stages<T: Sample> {
+    mix: Option<Mix>,
+    route: Option<(Arc<RouteControl>, Route)>,
+    callback_channels: usize,
+    logical_channels: usize,
+    device_channels: usize,
+    // logical frames, when both mixing and routing
+    between: Vec<T>,
+}
+
+impl<T: Sample> Stages<T> {
+    fn new(params: &StreamParams<T>, to_device: bool) -> Result<Option<Stages<T>>> {
+        let callback_channels = params.channels as usize;
+        let logical_channels = params.logical_channels() as usize;
+        let device_channels = params.device_channels() as usize;
+        let mix = match params.device_layout {
+            Some(layout) => {
+                let mixer = if to_device {
+                    ChannelMixer::new(params.layout, layout, params.mix)?
+                } else {
+                    ChannelMixer::new(layout, params.layout, params.mix)?
+                };
+                let cb_side = if to_device { mixer.in_channels() } else { mixer.out_channels() };
+                if cb_side != callback_channels {
+                    return Err(Error::InvalidParameter);
+                }
+                let (control, matrices) = MixControl::new(&mixer);
+                Some(Mix { mixer, control: Arc::new(control), matrices })
+            }
+            None => None,
+        };
+        let route = match params.physical_channels {
+            Some(physical) => {
+                if physical == 0 {
+                    return Err(Error::InvalidParameter);
+                }
+                let (control, route) = RouteControl::new(logical_channels, physical as usize);
+                Some((Arc::new(control), route))
+            }
+            None => None,
+        };
+        if mix.is_none() && route.is_none() {
+            return Ok(None);
+        }
+        let between = if mix.is_some() && route.is_some() {
+            vec![T::default(); CHUNK_FRAMES * logical_channels]
+        } else {
+            Vec::new()
+        };
+        Ok(Some(Stages {
+            mix,
+            route,
+            callback_channels,
+            logical_channels,
+            device_channels,
+            between,
+        }))
+    }
+
+    fn update(&mut self) {
+        if let Some(ref mut mix) = self.mix {
+            if mix.matrices.has_update() {
+                // same length, this does not fail
+                let _ = mix.mixer.set_matrix(mix.matrices.read());
+            }
+        }
+        if let Some((_, ref mut route)) = self.route {
+            route.update();
+        }
+    }
+
+    fn write_device(&mut self, callback: &[T], device: &mut [T]) {
+        match (self.mix.as_ref(), self.route.as_ref().map(|r| &r.1)) {
+            (Some(mix), Some(route)) => {
+                let frames = callback.len() / self.callback_channels;
+                let between = &mut self.between[.. frames * self.logical_channels];
+                mix.mixer.mix(callback, between);
+                route.route_out(between, device);
+            }
+            (Some(mix), None) => { mix.mixer.mix(callback, device); }
+            (None, Some(route)) => route.route_out(callback, device),
+            (None, None) => {}
+        }
+    }
+
+    fn read_device(&mut self, device: &[T], callback: &mut [T]) {
+        match (self.mix.as_ref(), self.route.as_ref().map(|r| &r.1)) {
+            (Some(mix), Some(route)) => {
+                let frames = device.len() / self.device_channels;
+                let between = &mut self.between[.. frames * self.logical_channels];
+                route.route_in(device, between);
+                mix.mixer.mix(between, callback);
+            }
+            (Some(mix), None) => { mix.mixer.mix(device, callback); }
+            (None, Some(route)) => route.route_in(device, callback),
+            (None, None) => {}
+        }
+    }
+}
+
+// Interleaved frames queued on the audio thread, allocated up front.
+struct Fifo<T: Sample> {
+    buf: Vec<T>,
+    channels: usize,
+    frames: usize,
+}
+
+impl<T: Sample> Fifo<T> {
+    fn new(channels: usize, capacity: usize) -> Fifo<T> {
+        Fifo { buf: vec![T::default(); channels * capacity], channels, frames: 0 }
+    }
+
+    fn frames(&self) -> usize {
+        self.frames
+    }
+
+    fn data(&self) -> &[T] {
+        &self.buf[.. self.frames * self.channels]
+    }
+
+    fn free(&mut self) -> &mut [T] {
+        &mut self.buf[self.frames * self.channels ..]
+    }
+
+    fn commit(&mut self, frames: usize) {
+        self.frames += frames;
+    }
+
+    // Returns the number of frames that fit.
+    fn push(&mut self, data: &[T]) -> usize {
+        let ch = self.channels;
+        let n = (data.len() / ch).min(self.free().len() / ch);
+        self.free()[.. n * ch].copy_from_slice(&data[.. n * ch]);
+        self.frames += n;
+        n
+    }
+
+    fn push_silence(&mut self, frames: usize) {
+        let ch = self.channels;
+        let n = frames.min(self.free().len() / ch);
+        for s in self.free()[.. n * ch].iter_mut() {
+            *s = T::default();
+        }
+        self.frames += n;
+    }
+
+    fn consume(&mut self, frames: usize) {
+        let ch = self.channels;
+        self.buf.copy_within(frames * ch .. self.frames * ch, 0);
+        self.frames -= frames;
+    }
+}
+
+struct Resampling<T: Sample> {
+    // device rate to callback rate
+    input: Option<Resampler>,
+    // callback rate to device rate
+    output: Option<Resampler>,
+    in_fifo: Fifo<T>,
+    out_fifo: Fifo<T>,
+    // callback channels at the device rate
+    dev_in: Vec<T>,
+    dev_out: Vec<T>,
+    cb_in: Vec<T>,
+    cb_out: Vec<T>,
+    // the callback returned short, the resampler is being flushed
+    ended: bool,
+    flushed: bool,
+    control: Arc<RateControl>,
+}
+
+struct Pipeline<T: Sample> {
+    data_cb: DataCallback<T>,
+    input: Option<Stages<T>>,
+    output: Option<Stages<T>>,
+    resampling: Option<Resampling<T>>,
+    in_scratch: Vec<T>,
+    out_scratch: Vec<T>,
+    in_channels: usize,
+    out_channels: usize,
+    dev_in_channels: usize,
+    dev_out_channels: usize,
+}
+
+impl<T: Sample> Pipeline<T> {
+    fn process(&mut self, ibuf: &[T], obuf: &mut [T]) -> usize {
+        if let Some(ref mut stages) = self.input {
+            stages.update();
+        }
+        if let Some(ref mut stages) = self.output {
+            stages.update();
+        }
+        let frames = ibuf.len().checked_div(self.dev_in_channels)
+            .unwrap_or_else(|| obuf.len() / self.dev_out_channels);
+        if self.resampling.is_some() {
+            return self.process_resampled(ibuf, obuf, frames);
+        }
+
+        let mut done = 0;
+        while done < frames {
+            let n = CHUNK_FRAMES.min(frames - done);
+            let dev_in = &ibuf[done * self.dev_in_channels ..
+                               (done + n) * self.dev_in_channels];
+            let dev_out = &mut obuf[done * self.dev_out_channels ..
+                                    (done + n) * self.dev_out_channels];
+
+            let cb_in: &[T] = match self.input {
+                Some(ref mut stages) => {
+                    let cb_in = &mut self.in_scratch[.. n * stages.callback_channels];
+                    stages.read_device(dev_in, cb_in);
+                    cb_in
+                }
+                None => dev_in,
+            };
+            let returned = match self.output {
+                Some(ref mut stages) => {
+                    let cb_out = &mut self.out_scratch[.. n * stages.callback_channels];
+                    let returned = (self.data_cb)(cb_in, cb_out).min(n);
+                    stages.write_device(&cb_out[.. returned * stages.callback_channels],
+                                     &mut dev_out[.. returned * self.dev_out_channels]);
+                    returned
+                }
+                None => (self.data_cb)(cb_in, dev_out).min(n),
+            };
+            done += returned;
+            if returned < n {
+                break;
+            }
+        }
+        done
+    }
+
+    fn process_resampled(&mut self, ibuf: &[T], obuf: &mut [T], frames: usize) -> usize {
+        let rs = self.resampling.as_mut().unwrap();
+        let (in_ch, out_ch) = (self.in_channels, self.out_channels);
+
+        // device input, converted to the callback's channels and rate, is
+        // queued for the callback; what does not fit is dropped
+        if let Some(ref mut resampler) = rs.input {
+            let mut done = 0;
+            while done < frames {
+                let n = CHUNK_FRAMES.min(frames - done);
+                let dev_in = &ibuf[done * self.dev_in_channels ..
+                                   (done + n) * self.dev_in_channels];
+                let src: &[T] = match self.input {
+                    Some(ref mut stages) => {
+                        let src = &mut rs.dev_in[.. n * in_ch];
+                        stages.read_device(dev_in, src);
+                        src
+                    }
+                    None => dev_in,
+                };
+                let mut used = 0;
+                while used < n {
+                    let (c, p) = resampler.process(&src[used * in_ch ..], rs.in_fifo.free());
+                    rs.in_fifo.commit(p);
+                    used += c;
+                    if c == 0 && p == 0 {
+                        break;
+                    }
+                }
+                done += n;
+            }
+            let buffered = resampler.buffered() * rs.control.callback_rate as f64 /
+                           rs.control.device_rate as f64;
+            rs.control.input_delay.store(rs.in_fifo.frames() as u32 + buffered as u32,
+                                         Ordering::Relaxed);
+        }
+
+        let resampler = match rs.output {
+            Some(ref mut resampler) => resampler,
+            None => {
+                // input only, hand over everything that was converted
+                while rs.in_fifo.frames() > 0 {
+                    let k = CHUNK_FRAMES.min(rs.in_fifo.frames());
+                    let returned = (self.data_cb)(&rs.in_fifo.data()[.. k * in_ch], &mut []);
+                    rs.in_fifo.consume(k);
+                    if returned < k {
+                        return 0;
+                    }
+                }
+                rs.control.input_delay.store(0, Ordering::Relaxed);
+                return frames;
+            }
+        };
+
+        let mut done = 0;
+        while done < frames {
+            let n = CHUNK_FRAMES.min(frames - done);
+            let mut made = 0;
+            while made < n {
+                let (c, p) = resampler.process(rs.out_fifo.data(),
+                                               &mut rs.dev_out[made * out_ch .. n * out_ch]);
+                rs.out_fifo.consume(c);
+                made += p;
+                if made == n {
+                    break;
+                }
+                if rs.ended {
+                    if rs.flushed {
+                        break;
+                    }
+                    // push the frames still inside the filter out
+                    rs.out_fifo.push_silence(resampler.latency());
+                    rs.flushed = true;
+                    continue;
+                }
+                // the FIFO is empty, ask the callback for what is missing
+                let k = resampler.input_needed(n - made).clamp(1, CHUNK_FRAMES);
+                let cb_in: &[T] = if rs.input.is_some() {
+                    let avail = rs.in_fifo.frames().min(k);
+                    let cb_in = &mut rs.cb_in[.. k * in_ch];
+                    cb_in[.. avail * in_ch].copy_from_slice(&rs.in_fifo.data()[.. avail * in_ch]);
+                    for s in cb_in[avail * in_ch ..].iter_mut() {
+                        *s = T::default();
+                    }
+                    rs.in_fifo.consume(avail);
+                    cb_in
+                } else {
+                    &[]
+                };
+                let cb_out = &mut rs.cb_out[.. k * out_ch];
+                let returned = (self.data_cb)(cb_in, cb_out).min(k);
+                rs.out_fifo.push(&cb_out[.. returned * out_ch]);
+                if returned < k {
+                    rs.ended = true;
+                }
+            }
+
+            let dev_out = &mut obuf[done * self.dev_out_channels ..
+                                    (done + made) * self.dev_out_channels];
+            match self.output {
+                Some(ref mut stages) => stages.write_device(&rs.dev_out[.. made * out_ch], dev_out),
+                None => dev_out.copy_from_slice(&rs.dev_out[.. made * out_ch]),
+            }
+            done += made;
+            if made < n {
+                break;
+            }
+        }
+        let delay = rs.out_fifo.frames() as f64 + resampler.buffered();
+        rs.control.output_delay.store(delay as u32, Ordering::Relaxed);
+        done
+    }
+}
+
+// Wraps `data_cb` with the conversions needed by `in_params` and
+// `out_params`, or returns it untouched when there are none.
+pub fn build<T: Sample>(data_cb: DataCallback<T>,
+                        in_params: Option<&StreamParams<T>>,
+                        out_params: Option<&StreamParams<T>>)
+        -> Result<(DataCallback<T>, Controls)> {
+    let input = match in_params {
+        Some(p) => Stages::new(p, false)?,
+        None => None,
+    };
+    let output = match out_params {
+        Some(p) => Stages::new(p, true)?,
+        None => None,
+    };
+    // a duplex stream has a single rate on each side
+    let rates = match (in_params, out_params) {
+        (Some(i), Some(o)) => {
+            if i.rate != o.rate || i.device_rate() != o.device_rate() {
+                return Err(Error::InvalidParameter);
+            }
+            Some((o.rate, o.device_rate(), o.quality))
+        }
+        (Some(p), None) | (None, Some(p)) => Some((p.rate, p.device_rate(), p.quality)),
+        (None, None) => None,
+    };
+    let resampling = match rates {
+        Some((rate, device_rate, quality)) if rate != device_rate => {
+            if rate == 0 || device_rate == 0 {
+                return Err(Error::InvalidParameter);
+            }
+            let in_ch = in_params.map_or(0, |p| p.channels as usize);
+            let out_ch = out_params.map_or(0, |p| p.channels as usize);
+            if in_params.is_some() && in_ch == 0 || out_params.is_some() && out_ch == 0 {
+                return Err(Error::InvalidParameter);
+            }
+            Some(Resampling {
+                input: in_params.map(|_| Resampler::new(in_ch, device_rate, rate, quality)),
+                output: out_params.map(|_| Resampler::new(out_ch, rate, device_rate, quality)),
+                in_fifo: Fifo::new(in_ch, FIFO_FRAMES),
+                out_fifo: Fifo::new(out_ch, FIFO_FRAMES),
+                dev_in: vec![T::default(); CHUNK_FRAMES * in_ch],
+                dev_out: vec![T::default(); CHUNK_FRAMES * out_ch],
+                cb_in: vec![T::default(); CHUNK_FRAMES * in_ch],
+                cb_out: vec![T::default(); CHUNK_FRAMES * out_ch],
+                ended: false,
+                flushed: false,
+                control: Arc::new(RateControl {
+                    callback_rate: rate,
+                    device_rate,
+                    input_delay: AtomicU32::new(0),
+                    output_delay: AtomicU32::new(0),
+                }),
+            })
+        }
+        _ => None,
+    };
+    if input.is_none() && output.is_none() && resampling.is_none() {
+        return Ok((data_cb, Controls::default()));
+    }
+
+    let controls = Controls {
+        input_mix: input.as_ref().and_then(|s| s.mix.as_ref()).map(|m| m.control.clone()),
+        output_mix: output.as_ref().and_then(|s| s.mix.as_ref()).map(|m| m.control.clone()),
+        input_route: input.as_ref().and_then(|s| s.route.as_ref()).map(|r| r.0.clone()),
+        output_route: output.as_ref().and_then(|s| s.route.as_ref()).map(|r| r.0.clone()),
+        rate: resampling.as_ref().map(|r| r.control.clone()),
+    };
+    let mut pipeline = Pipeline {
+        data_cb,
+        in_scratch: vec![T::default(); CHUNK_FRAMES *
+                         input.as_ref().map_or(0, |s| s.callback_channels)],
+        out_scratch: vec![T::default(); CHUNK_FRAMES *
+                          output.as_ref().map_or(0, |s| s.callback_channels)],
+        in_channels: in_params.map_or(0, |p| p.channels as usize),
+        out_channels: out_params.map_or(0, |p| p.channels as usize),
+        dev_in_channels: in_params.map_or(0, |p| p.device_channels() as usize),
+        dev_out_channels: out_params.map_or(0, |p| p.device_channels() as usize),
+        input,
+        output,
+        resampling,
+    };
+    let cb: DataCallback<T> = Box::new(move |ibuf: &[T], obuf: &mut [T]| {
+        pipeline.process(ibuf, obuf)
+    });
+    Ok((cb, controls))
+}
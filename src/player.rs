@@ -0,0 +1,986 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::mem;
+use std::path::Path;
+use std::result;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use ring::{ring_buffer, Consumer, Producer};
+use channel_mixer::{ChannelMixer, MixOptions};
+#[cfg(feature = "codecs")]
+use codecs::CodecDecoder;
+use resampler::{Quality, Resampler};
+use triple_buffer::{triple_buffer, Reader, Writer};
+use units::{Frames, Latency};
+use wav::{self, Encoding, WavReader};
+use {ChannelLayout, Context, DataCallback, DevId, Sample, State, StateCallback, Stream,
+     StreamParams};
+
+// Plays files through an output stream. A read-ahead thread decodes into a
+// ring that the callback drains, and goes on with the next queued file, or
+// back to the loop start, without a gap. When the last file runs out the
+// callback returns short, and the stream ends with `State::Drained`.
+//
+// Seeking is a handshake between the two threads: the reader moves to the
+// new position and stops pushing, then the callback empties the ring and
+// lets it resume. Each file position change travels through the ring of
+// markers, so that the callback knows which frame of which file it plays,
+// and keeps the last few so that what is heard a latency later is known too.
+// Seeks and loops apply to the file the callback plays, the reader going
+// back to it when it already moved on to the next queued one.
+
+const READ_PERIOD: Duration = Duration::from_millis(10);
+const CHUNK_FRAMES: usize = 4096;
+const MARKERS: usize = 64;
+const SEGMENTS: usize = 16;
+
+#[derive(Debug)]
+pub enum Error {
+    Stream(::Error),
+    File(wav::Error),
+    // From the compressed formats of the `codecs` feature.
+    Decode(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl From<::Error> for Error {
+    fn from(e: ::Error) -> Error {
+        Error::Stream(e)
+    }
+}
+
+impl From<wav::Error> for Error {
+    fn from(e: wav::Error) -> Error {
+        Error::File(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::File(wav::Error::Io(e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Stream(e) => write!(f, "stream error: {:?}", e),
+            Error::File(ref e) => e.fmt(f),
+            Error::Decode(ref what) => write!(f, "decoding error: {}", what),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::File(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// A file, or anything else, decoded to interleaved floats.
+pub trait Decoder: Send {
+    fn rate(&self) -> u32;
+    fn channels(&self) -> u32;
+    fn layout(&self) -> ChannelLayout {
+        ChannelLayout::Undefined
+    }
+    // Total frames, when known.
+    fn frames(&self) -> Option<u64>;
+    // Next frame read.
+    fn position(&self) -> u64;
+    // Returns the number of frames read, 0 at the end.
+    fn read(&mut self, out: &mut [f32]) -> Result<usize>;
+    fn seek(&mut self, frame: u64) -> Result<()>;
+}
+
+impl<R: Read + Seek + Send> Decoder for WavReader<R> {
+    fn rate(&self) -> u32 {
+        self.spec().rate
+    }
+
+    fn channels(&self) -> u32 {
+        self.spec().channels as u32
+    }
+
+    fn layout(&self) -> ChannelLayout {
+        self.spec().layout
+    }
+
+    fn frames(&self) -> Option<u64> {
+        Some(WavReader::frames(self))
+    }
+
+    fn position(&self) -> u64 {
+        WavReader::position(self)
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+        Ok(WavReader::read(self, out)?)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        Ok(WavReader::seek(self, frame)?)
+    }
+}
+
+// Headerless interleaved little-endian samples, as `recorder` writes them.
+pub struct RawDecoder {
+    reader: BufReader<File>,
+    rate: u32,
+    channels: u32,
+    encoding: Encoding,
+    frames: u64,
+    position: u64,
+    bytes: Vec<u8>,
+}
+
+impl RawDecoder {
+    pub fn open<P: AsRef<Path>>(path: P, rate: u32, channels: u32, encoding: Encoding)
+            -> Result<RawDecoder> {
+        if rate == 0 || channels == 0 {
+            return Err(Error::Stream(::Error::InvalidParameter));
+        }
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let block = channels as u64 * encoding.bytes_per_sample() as u64;
+        Ok(RawDecoder {
+            reader: BufReader::new(file),
+            rate,
+            channels,
+            encoding,
+            frames: len / block,
+            position: 0,
+            bytes: Vec::new(),
+        })
+    }
+}
+
+impl Decoder for RawDecoder {
+    fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    fn frames(&self) -> Option<u64> {
+        Some(self.frames)
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+        let ch = self.channels as usize;
+        let frames = ((out.len() / ch) as u64).min(self.frames - self.position) as usize;
+        let size = self.encoding.bytes_per_sample();
+        self.bytes.resize(frames * ch * size, 0);
+        self.reader.read_exact(&mut self.bytes)?;
+        for (o, b) in out.iter_mut().zip(self.bytes.chunks(size)) {
+            *o = self.encoding.decode(b);
+        }
+        self.position += frames as u64;
+        Ok(frames)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        let frame = frame.min(self.frames);
+        let block = self.channels as u64 * self.encoding.bytes_per_sample() as u64;
+        self.reader.seek(SeekFrom::Start(frame * block))?;
+        self.position = frame;
+        Ok(())
+    }
+}
+
+// Picks a decoder from the file's contents: WAV, or with the `codecs`
+// feature FLAC, Ogg Vorbis and MP3.
+pub fn open_decoder<P: AsRef<Path>>(path: P) -> Result<Box<dyn Decoder>> {
+    match WavReader::open(path.as_ref()) {
+        Ok(reader) => Ok(Box::new(reader)),
+        #[cfg(feature = "codecs")]
+        Err(wav::Error::Format(_)) => Ok(Box::new(CodecDecoder::open(path)?)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Adapts a decoder to another rate and channel count. Known layouts are
+// mixed; otherwise mono is copied to every channel and other channels are
+// matched by index.
+pub struct Converter {
+    decoder: Box<dyn Decoder>,
+    rate: u32,
+    channels: usize,
+    layout: ChannelLayout,
+    mixer: Option<ChannelMixer>,
+    resampler: Option<Resampler>,
+    decoded: Vec<f32>,
+    // mixed to the output channels, resampled from `offset` on
+    mixed: Vec<f32>,
+    offset: usize,
+    flushed: bool,
+    position: u64,
+}
+
+fn channel_mixer(from: ChannelLayout, from_channels: u32, to: ChannelLayout, to_channels: u32)
+        -> Option<ChannelMixer> {
+    if from == to && from_channels == to_channels {
+        return None;
+    }
+    if from.channel_count() == from_channels && to.channel_count() == to_channels {
+        if let Ok(mixer) = ChannelMixer::new(from, to, MixOptions::default()) {
+            return Some(mixer);
+        }
+    }
+    if from_channels == to_channels {
+        return None;
+    }
+    let (inputs, outputs) = (from_channels as usize, to_channels as usize);
+    let mut matrix = vec![0_f32; inputs * outputs];
+    for o in 0 .. outputs {
+        if inputs == 1 {
+            matrix[o] = 1.0;
+        } else if o < inputs {
+            matrix[o * inputs + o] = 1.0;
+        }
+    }
+    ChannelMixer::from_matrix(inputs, outputs, matrix).ok()
+}
+
+impl Converter {
+    pub fn new(decoder: Box<dyn Decoder>, rate: u32, channels: u32, layout: ChannelLayout,
+               quality: Quality) -> Result<Converter> {
+        if rate == 0 || channels == 0 {
+            return Err(Error::Stream(::Error::InvalidParameter));
+        }
+        let mixer = channel_mixer(decoder.layout(), decoder.channels(), layout, channels);
+        let resampler = if decoder.rate() != rate {
+            Some(Resampler::new(channels as usize, decoder.rate(), rate, quality))
+        } else {
+            None
+        };
+        Ok(Converter {
+            decoder,
+            rate,
+            channels: channels as usize,
+            layout,
+            mixer,
+            resampler,
+            decoded: Vec::new(),
+            mixed: Vec::new(),
+            offset: 0,
+            flushed: false,
+            position: 0,
+        })
+    }
+
+    // Refills `mixed` from the decoder, then with the filter's tail at the
+    // end. Returns false when there is nothing left.
+    fn refill(&mut self) -> Result<bool> {
+        let in_ch = self.decoder.channels() as usize;
+        self.decoded.resize(CHUNK_FRAMES * in_ch, 0.0);
+        let read = self.decoder.read(&mut self.decoded)?;
+        self.offset = 0;
+        if read == 0 {
+            let tail = match self.resampler {
+                Some(ref resampler) if !self.flushed => resampler.latency(),
+                _ => 0,
+            };
+            self.flushed = true;
+            self.mixed.clear();
+            self.mixed.resize(tail * self.channels, 0.0);
+            return Ok(tail > 0);
+        }
+        match self.mixer {
+            Some(ref mixer) => {
+                self.mixed.resize(read * self.channels, 0.0);
+                mixer.mix(&self.decoded[.. read * in_ch], &mut self.mixed);
+            }
+            None => {
+                self.mixed.clear();
+                self.mixed.extend_from_slice(&self.decoded[.. read * in_ch]);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Decoder for Converter {
+    fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn channels(&self) -> u32 {
+        self.channels as u32
+    }
+
+    fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    fn frames(&self) -> Option<u64> {
+        let (from, to) = (self.decoder.rate(), self.rate);
+        self.decoder.frames().map(|frames| Frames(frames).at_rate(from, to).0)
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> Result<usize> {
+        let ch = self.channels;
+        let wanted = out.len() / ch;
+        let mut done = 0;
+        while done < wanted {
+            // what the resampler holds goes out before anything is decoded
+            let input = &self.mixed[self.offset ..];
+            let output = &mut out[done * ch .. wanted * ch];
+            let (consumed, produced) = match self.resampler {
+                Some(ref mut resampler) => resampler.process(input, output),
+                None => {
+                    let frames = (input.len() / ch).min(output.len() / ch);
+                    output[.. frames * ch].copy_from_slice(&input[.. frames * ch]);
+                    (frames, frames)
+                }
+            };
+            self.offset += consumed * ch;
+            done += produced;
+            if done < wanted && self.offset == self.mixed.len() && !self.refill()? {
+                break;
+            }
+        }
+        self.position += done as u64;
+        Ok(done)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        let inner = Frames(frame).at_rate(self.rate, self.decoder.rate()).0;
+        self.decoder.seek(inner)?;
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.reset();
+        }
+        self.mixed.clear();
+        self.offset = 0;
+        self.flushed = false;
+        self.position = frame;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Options {
+    // Decoded ahead of the callback.
+    pub buffer: Duration,
+    pub latency: Latency,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            buffer: Duration::from_millis(500),
+            latency: Latency::Duration(Duration::from_millis(20)),
+        }
+    }
+}
+
+// From this frame of the ring on, frames come from `frame` of file `file`.
+#[derive(Copy, Clone)]
+struct Marker {
+    at: u64,
+    frame: u64,
+    file: usize,
+}
+
+// From frame `at` of what the callback took from the ring on, frames came
+// from `frame` of file `file`.
+#[derive(Copy, Clone, Default)]
+struct Segment {
+    at: u64,
+    frame: u64,
+    file: usize,
+}
+
+// The last `SEGMENTS` segments the callback played.
+#[derive(Copy, Clone)]
+struct Timeline {
+    played: u64,
+    segments: [Segment; SEGMENTS],
+    next: usize,
+}
+
+impl Timeline {
+    fn new() -> Timeline {
+        Timeline { played: 0, segments: [Segment::default(); SEGMENTS], next: 1 }
+    }
+
+    fn start(&mut self, frame: u64, file: usize) {
+        self.segments[self.next % SEGMENTS] = Segment { at: self.played, frame, file };
+        self.next += 1;
+    }
+
+    // File and frame played `latency` frames before the last one, or the
+    // start of the oldest segment kept.
+    fn before(&self, latency: u64) -> (usize, u64) {
+        let heard = self.played.saturating_sub(latency);
+        let kept = self.next.min(SEGMENTS);
+        let mut segment = self.segments[(self.next - 1) % SEGMENTS];
+        for back in 1 ..= kept {
+            segment = self.segments[(self.next - back) % SEGMENTS];
+            if segment.at <= heard {
+                break;
+            }
+        }
+        (segment.file, segment.frame + heard.saturating_sub(segment.at))
+    }
+}
+
+struct Shared {
+    // seek generations: asked for, fed by the reader, played by the callback
+    requested: AtomicU64,
+    ready: AtomicU64,
+    flushed: AtomicU64,
+    seek_to: AtomicU64,
+    seek_file: AtomicUsize,
+    // generation + 1 of the data that ends with what is in the ring
+    ended: AtomicU64,
+    paused: AtomicBool,
+    stop: AtomicBool,
+    drained: AtomicBool,
+    failed: AtomicBool,
+    position: AtomicU64,
+    file: AtomicUsize,
+    underruns: AtomicUsize,
+    // file, start and end
+    loop_points: Mutex<Option<(usize, u64, u64)>>,
+    queue: Mutex<VecDeque<Box<dyn Decoder>>>,
+    error: Mutex<Option<Error>>,
+}
+
+struct ReadAhead<T: Sample> {
+    decoder: Box<dyn Decoder>,
+    // the files before `file` that the callback may still play
+    previous: VecDeque<Box<dyn Decoder>>,
+    producer: Producer<T>,
+    markers: Producer<Marker>,
+    shared: Arc<Shared>,
+    channels: usize,
+    generation: u64,
+    pushed: u64,
+    file: usize,
+    ended: bool,
+    // the first marker of a generation waits for the callback's flush
+    marked: bool,
+}
+
+impl<T: Sample> ReadAhead<T> {
+    fn mark(&mut self, frame: u64) {
+        let marker = Marker { at: self.pushed, frame, file: self.file };
+        while self.markers.push(marker).is_err() {
+            if self.shared.stop.load(Ordering::Acquire) {
+                return;
+            }
+            thread::park_timeout(READ_PERIOD);
+        }
+    }
+
+    // Start and end of the loop of the file being read.
+    fn loop_points(&self) -> Option<(u64, u64)> {
+        match *self.shared.loop_points.lock().unwrap() {
+            Some((file, start, end)) if file == self.file && start < end => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    // Moves on at the end of the file or loop, returns false when done.
+    fn next(&mut self) -> Result<bool> {
+        if let Some((start, _)) = self.loop_points() {
+            self.decoder.seek(start)?;
+            self.mark(start);
+            return Ok(true);
+        }
+        let next = self.shared.queue.lock().unwrap().pop_front();
+        match next {
+            Some(decoder) => {
+                self.previous.push_back(mem::replace(&mut self.decoder, decoder));
+                self.file += 1;
+                // files before the one the callback plays are gone for good
+                let playing = self.shared.file.load(Ordering::Relaxed);
+                while !self.previous.is_empty() && self.file - self.previous.len() < playing {
+                    self.previous.pop_front();
+                }
+                self.mark(0);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Goes back to `file` when it was read ahead of, re-queueing the files
+    // after it from their start, or on to it from the queue.
+    fn switch_to(&mut self, file: usize) -> Result<()> {
+        while self.file > file {
+            let previous = match self.previous.pop_back() {
+                Some(previous) => previous,
+                None => break,
+            };
+            let mut next = mem::replace(&mut self.decoder, previous);
+            next.seek(0)?;
+            self.shared.queue.lock().unwrap().push_front(next);
+            self.file -= 1;
+        }
+        while self.file < file {
+            let next = match self.shared.queue.lock().unwrap().pop_front() {
+                Some(next) => next,
+                None => break,
+            };
+            self.previous.push_back(mem::replace(&mut self.decoder, next));
+            self.file += 1;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        let ch = self.channels;
+        let mut buf = vec![0_f32; CHUNK_FRAMES * ch];
+        let mut samples = vec![T::default(); CHUNK_FRAMES * ch];
+        while !self.shared.stop.load(Ordering::Acquire) {
+            let requested = self.shared.requested.load(Ordering::Acquire);
+            if requested != self.generation {
+                self.switch_to(self.shared.seek_file.load(Ordering::Relaxed))?;
+                self.decoder.seek(self.shared.seek_to.load(Ordering::Relaxed))?;
+                self.generation = requested;
+                self.pushed = 0;
+                self.ended = false;
+                self.marked = false;
+                self.shared.ready.store(requested, Ordering::Release);
+            }
+            if self.shared.flushed.load(Ordering::Acquire) != self.generation {
+                thread::park_timeout(READ_PERIOD);
+                continue;
+            }
+            if !self.marked {
+                let position = self.decoder.position();
+                self.mark(position);
+                self.marked = true;
+            }
+            let room = self.producer.free_len() / ch;
+            if self.ended || room == 0 {
+                thread::park_timeout(READ_PERIOD);
+                continue;
+            }
+            let mut frames = room.min(CHUNK_FRAMES);
+            if let Some((_, end)) = self.loop_points() {
+                frames = frames.min(end.saturating_sub(self.decoder.position()) as usize);
+            }
+            let read = if frames > 0 { self.decoder.read(&mut buf[.. frames * ch])? } else { 0 };
+            if read == 0 {
+                if !self.next()? {
+                    self.ended = true;
+                    self.shared.ended.store(self.generation + 1, Ordering::Release);
+                }
+                continue;
+            }
+            for (s, &v) in samples.iter_mut().zip(buf[.. read * ch].iter()) {
+                *s = T::from_f32(v);
+            }
+            self.producer.push_slice(&samples[.. read * ch]);
+            self.pushed += read as u64;
+        }
+        Ok(())
+    }
+}
+
+struct Playout<T: Sample> {
+    consumer: Consumer<T>,
+    markers: Consumer<Marker>,
+    shared: Arc<Shared>,
+    channels: usize,
+    generation: u64,
+    played: u64,
+    pending: Option<Marker>,
+    // frame of the ring, and of the file, the last marker started at
+    base: (u64, u64),
+    file: usize,
+    timeline: Timeline,
+    timelines: Writer<Timeline>,
+}
+
+impl<T: Sample> Playout<T> {
+    fn apply_markers(&mut self) {
+        loop {
+            if self.pending.is_none() {
+                self.pending = self.markers.pop();
+            }
+            match self.pending {
+                Some(marker) if marker.at <= self.played => {
+                    self.base = (marker.at, marker.frame);
+                    self.file = marker.file;
+                    self.pending = None;
+                    self.timeline.start(marker.frame, marker.file);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn process(&mut self, obuf: &mut [T]) -> usize {
+        let ch = self.channels;
+        let frames = obuf.len() / ch;
+        let ready = self.shared.ready.load(Ordering::Acquire);
+        if ready != self.generation {
+            // the reader stopped pushing until the flush is acknowledged
+            let len = self.consumer.len();
+            self.consumer.discard(len);
+            let len = self.markers.len();
+            self.markers.discard(len);
+            self.pending = None;
+            self.generation = ready;
+            self.played = 0;
+            self.base = (0, self.shared.seek_to.load(Ordering::Relaxed));
+            self.file = self.shared.seek_file.load(Ordering::Relaxed);
+            self.shared.file.store(self.file, Ordering::Relaxed);
+            self.timeline.start(self.base.1, self.file);
+            self.shared.flushed.store(ready, Ordering::Release);
+        }
+        let ended = self.shared.ended.load(Ordering::Acquire) == self.generation + 1;
+        if self.shared.paused.load(Ordering::Relaxed) {
+            for s in obuf.iter_mut() {
+                *s = T::default();
+            }
+            return frames;
+        }
+
+        let mut done = 0;
+        while done < frames {
+            self.apply_markers();
+            let limit = match self.pending {
+                Some(marker) => ((marker.at - self.played) as usize).min(frames - done),
+                None => frames - done,
+            };
+            let got = self.consumer.pop_slice(&mut obuf[done * ch .. (done + limit) * ch]) / ch;
+            self.played += got as u64;
+            self.timeline.played += got as u64;
+            done += got;
+            if got < limit {
+                break;
+            }
+        }
+        self.apply_markers();
+        self.shared.position.store(self.base.1 + self.played - self.base.0, Ordering::Relaxed);
+        self.shared.file.store(self.file, Ordering::Relaxed);
+        self.timelines.write(self.timeline);
+
+        if done < frames {
+            for s in obuf[done * ch ..].iter_mut() {
+                *s = T::default();
+            }
+            if ended {
+                // a short return drains the stream
+                return done;
+            }
+            self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        frames
+    }
+}
+
+// Controls a playback and owns its read-ahead thread, whatever drives the
+// callback.
+pub struct Playback {
+    shared: Arc<Shared>,
+    timeline: Mutex<Reader<Timeline>>,
+    reader: Option<thread::JoinHandle<()>>,
+    rate: u32,
+    channels: u32,
+    layout: ChannelLayout,
+}
+
+impl Playback {
+    fn wake(&self) {
+        if let Some(ref reader) = self.reader {
+            reader.thread().unpark();
+        }
+    }
+
+    // In frames of the file the callback plays, even when a queued file
+    // already started decoding.
+    pub fn seek(&self, frame: u64) {
+        self.shared.seek_to.store(frame, Ordering::Relaxed);
+        self.shared.seek_file.store(self.current_file(), Ordering::Relaxed);
+        self.shared.requested.fetch_add(1, Ordering::Release);
+        self.wake();
+    }
+
+    pub fn seek_duration(&self, position: Duration) {
+        self.seek(Frames::from_duration(position, self.rate).0)
+    }
+
+    // Plays frames `start` to `end` of the file the callback plays over and
+    // over once reached, until cleared with `None`; the queued files play
+    // after that. No loop happens when the file was already read ahead past
+    // `end`, e.g. to the next file.
+    pub fn set_loop(&self, points: Option<(u64, u64)>) {
+        let file = self.current_file();
+        *self.shared.loop_points.lock().unwrap() = points.map(|(start, end)| (file, start, end));
+        self.wake();
+    }
+
+    // Plays `decoder` right after the current file, or after the previously
+    // queued ones, converted to the rate and channels of the first one.
+    pub fn queue(&self, decoder: Box<dyn Decoder>) -> Result<()> {
+        let decoder = if decoder.rate() != self.rate || decoder.channels() != self.channels {
+            Box::new(Converter::new(decoder, self.rate, self.channels, self.layout,
+                                    Quality::Sinc)?)
+        } else {
+            decoder
+        };
+        self.shared.queue.lock().unwrap().push_back(decoder);
+        self.wake();
+        Ok(())
+    }
+
+    // Outputs silence, keeping the position, until `resume`.
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    // Frame of the current file after the last one the callback rendered.
+    pub fn position_frames(&self) -> u64 {
+        self.shared.position.load(Ordering::Relaxed)
+    }
+
+    // Index of the file the callback renders, 0 for the first one.
+    pub fn current_file(&self) -> usize {
+        self.shared.file.load(Ordering::Relaxed)
+    }
+
+    // File and frame heard `latency` frames after the callback rendered its
+    // last one, which may be before a file change, loop or seek.
+    pub fn heard_position(&self, latency: u64) -> (usize, u64) {
+        self.timeline.lock().unwrap().read().before(latency)
+    }
+
+    // Callbacks that found the ring empty before the end.
+    pub fn underruns(&self) -> usize {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.shared.drained.load(Ordering::Acquire)
+    }
+
+    // The stream reported `State::Error`.
+    pub fn failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Acquire)
+    }
+
+    // The error that stopped the read-ahead thread, if any.
+    pub fn take_error(&self) -> Option<Error> {
+        self.shared.error.lock().unwrap().take()
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(reader) = self.reader.take() {
+            reader.thread().unpark();
+            let _ = reader.join();
+        }
+    }
+}
+
+// The callbacks of an output stream playing `decoder`, at its rate and
+// channel count.
+pub fn playback<T: Sample>(decoder: Box<dyn Decoder>, options: &Options)
+        -> Result<(DataCallback<T>, StateCallback, Playback)> {
+    let (rate, channels, layout) = (decoder.rate(), decoder.channels(), decoder.layout());
+    if rate == 0 || channels == 0 {
+        return Err(Error::Stream(::Error::InvalidParameter));
+    }
+    let ch = channels as usize;
+    let buffer_frames = Frames::from_duration(options.buffer, rate).0.max(CHUNK_FRAMES as u64);
+    let (producer, consumer) = ring_buffer(buffer_frames as usize * ch);
+    let (marker_producer, marker_consumer) = ring_buffer(MARKERS);
+    let shared = Arc::new(Shared {
+        requested: AtomicU64::new(0),
+        ready: AtomicU64::new(0),
+        flushed: AtomicU64::new(0),
+        seek_to: AtomicU64::new(0),
+        seek_file: AtomicUsize::new(0),
+        ended: AtomicU64::new(0),
+        paused: AtomicBool::new(false),
+        stop: AtomicBool::new(false),
+        drained: AtomicBool::new(false),
+        failed: AtomicBool::new(false),
+        position: AtomicU64::new(0),
+        file: AtomicUsize::new(0),
+        underruns: AtomicUsize::new(0),
+        loop_points: Mutex::new(None),
+        queue: Mutex::new(VecDeque::new()),
+        error: Mutex::new(None),
+    });
+
+    let mut read_ahead = ReadAhead {
+        decoder,
+        previous: VecDeque::new(),
+        producer,
+        markers: marker_producer,
+        shared: shared.clone(),
+        channels: ch,
+        generation: 0,
+        pushed: 0,
+        file: 0,
+        ended: false,
+        marked: false,
+    };
+    let reader = thread::Builder::new()
+        .name("cult player".to_string())
+        .spawn(move || {
+            if let Err(e) = read_ahead.run() {
+                *read_ahead.shared.error.lock().unwrap() = Some(e);
+                // what was decoded still plays, then the stream drains
+                let generation = read_ahead.generation;
+                read_ahead.shared.ended.store(generation + 1, Ordering::Release);
+            }
+        })?;
+
+    let (timelines, timeline) = triple_buffer(Timeline::new());
+    let mut playout = Playout {
+        consumer,
+        markers: marker_consumer,
+        shared: shared.clone(),
+        channels: ch,
+        generation: 0,
+        played: 0,
+        pending: None,
+        base: (0, 0),
+        file: 0,
+        timeline: Timeline::new(),
+        timelines,
+    };
+    let data_cb: DataCallback<T> = Box::new(move |_: &[T], obuf: &mut [T]| {
+        playout.process(obuf)
+    });
+
+    let st_shared = shared.clone();
+    let state_cb: StateCallback = Box::new(move |state: State| {
+        match state {
+            State::Drained => st_shared.drained.store(true, Ordering::Release),
+            State::Error => st_shared.failed.store(true, Ordering::Release),
+            State::Started | State::Stopped => {}
+        }
+    });
+
+    let playback = Playback {
+        shared,
+        timeline: Mutex::new(timeline),
+        reader: Some(reader),
+        rate,
+        channels,
+        layout,
+    };
+    Ok((data_cb, state_cb, playback))
+}
+
+pub struct Player<T: Sample> {
+    stream: Stream<T>,
+    playback: Playback,
+}
+
+impl<T: Sample> Player<T> {
+    // Opens a file as `open_decoder` does, and a stream at its rate and
+    // channel count.
+    pub fn open<P: AsRef<Path>>(ctx: &Context, device: Option<&DevId>, path: P)
+            -> Result<Player<T>> {
+        Player::with_decoder(ctx, device, open_decoder(path)?, &Options::default())
+    }
+
+    // Converts the file to the rate, channels and layout of `params`.
+    pub fn open_with_params<P: AsRef<Path>>(ctx: &Context, device: Option<&DevId>, path: P,
+                                            params: StreamParams<T>, options: &Options)
+            -> Result<Player<T>> {
+        let decoder = open_decoder(path)?;
+        let decoder = Converter::new(decoder, params.rate(), params.channels(), params.layout(),
+                                     Quality::Sinc)?;
+        Player::with_params(ctx, device, Box::new(decoder), params, options)
+    }
+
+    // The stream runs at the decoder's rate and channel count.
+    pub fn with_decoder(ctx: &Context, device: Option<&DevId>, decoder: Box<dyn Decoder>,
+                        options: &Options) -> Result<Player<T>> {
+        let params = StreamParams::<T>::new(decoder.rate(), decoder.channels(), decoder.layout());
+        Player::with_params(ctx, device, decoder, params, options)
+    }
+
+    fn with_params(ctx: &Context, device: Option<&DevId>, decoder: Box<dyn Decoder>,
+                   params: StreamParams<T>, options: &Options) -> Result<Player<T>> {
+        let (data_cb, state_cb, playback) = playback(decoder, options)?;
+        let stream = Stream::new(ctx, "cult player", None, None, device, Some(params),
+                                 options.latency, data_cb, Some(state_cb))?;
+        Ok(Player { stream, playback })
+    }
+
+    pub fn play(&self) -> Result<()> {
+        self.playback.resume();
+        Ok(self.stream.start()?)
+    }
+
+    pub fn pause(&self) {
+        self.playback.pause()
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        Ok(self.stream.stop()?)
+    }
+
+    pub fn seek(&self, position: Duration) {
+        self.playback.seek_duration(position)
+    }
+
+    pub fn queue<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.playback.queue(open_decoder(path)?)
+    }
+
+    // What is heard now, in time of the file it is from.
+    pub fn position(&self) -> Result<Duration> {
+        let latency = self.stream.latency()? as u64;
+        let (_, frame) = self.playback.heard_position(latency);
+        Ok(Frames(frame).to_duration(self.playback.rate()))
+    }
+
+    pub fn playback(&self) -> &Playback {
+        &self.playback
+    }
+
+    pub fn stream(&self) -> &Stream<T> {
+        &self.stream
+    }
+}
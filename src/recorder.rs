@@ -0,0 +1,394 @@
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ring::{ring_buffer, Consumer};
+use units::{Frames, Latency};
+use wav::{self, Encoding, Spec, WavWriter};
+use {ChannelLayout, Context, DataCallback, DevId, Sample, State, StateCallback, Stream,
+     StreamParams};
+
+// Records an input stream to a file. The callback pushes the input into a
+// ring, and a writer thread empties it to disk every `WRITE_PERIOD`; frames
+// that do not fit because the disk stalled are dropped and counted.
+//
+// The file is finalized when the recording is stopped, reaches its maximum
+// duration, which ends the stream, or when the stream fails. WAV headers are
+// also patched every `PATCH_PERIOD`, so that little is lost if the process
+// dies.
+
+const WRITE_PERIOD: Duration = Duration::from_millis(20);
+const PATCH_PERIOD: Duration = Duration::from_secs(1);
+const CHUNK_FRAMES: usize = 4096;
+
+#[derive(Debug)]
+pub enum Error {
+    Stream(::Error),
+    File(wav::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl From<::Error> for Error {
+    fn from(e: ::Error) -> Error {
+        Error::Stream(e)
+    }
+}
+
+impl From<wav::Error> for Error {
+    fn from(e: wav::Error) -> Error {
+        Error::File(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::File(wav::Error::Io(e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Stream(e) => write!(f, "stream error: {:?}", e),
+            Error::File(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::File(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FileFormat {
+    Wav,
+    // Headerless interleaved little-endian samples.
+    Raw,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Options {
+    pub format: FileFormat,
+    pub encoding: Encoding,
+    pub max_duration: Option<Duration>,
+    // How long the disk may stall before frames are dropped.
+    pub buffer: Duration,
+    pub latency: Latency,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            format: FileFormat::Wav,
+            encoding: Encoding::Pcm16,
+            max_duration: None,
+            buffer: Duration::from_secs(2),
+            latency: Latency::Duration(Duration::from_millis(20)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Summary {
+    // In the file.
+    pub frames: u64,
+    pub dropped_frames: u64,
+}
+
+struct Shared {
+    paused: AtomicBool,
+    // the stream ended or failed, or the recording was stopped
+    stopping: AtomicBool,
+    failed: AtomicBool,
+    finished: AtomicBool,
+    recorded: AtomicU64,
+    written: AtomicU64,
+    dropped_frames: AtomicU64,
+    overruns: AtomicUsize,
+}
+
+enum Sink {
+    Wav(WavWriter<BufWriter<File>>),
+    Raw(BufWriter<File>, Encoding, Vec<u8>),
+}
+
+impl Sink {
+    fn write<T: Sample>(&mut self, samples: &[T]) -> Result<()> {
+        match *self {
+            Sink::Wav(ref mut writer) => writer.write(samples)?,
+            Sink::Raw(ref mut writer, encoding, ref mut bytes) => {
+                bytes.clear();
+                for &s in samples {
+                    encoding.encode(s.to_f32(), bytes);
+                }
+                writer.write_all(bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn patch(&mut self) -> Result<()> {
+        match *self {
+            Sink::Wav(ref mut writer) => writer.flush()?,
+            Sink::Raw(ref mut writer, _, _) => writer.flush()?,
+        }
+        Ok(())
+    }
+
+    fn finalize(self) -> Result<()> {
+        match self {
+            Sink::Wav(writer) => {
+                writer.finalize()?;
+            }
+            Sink::Raw(mut writer, _, _) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+fn write_loop<T: Sample>(mut consumer: Consumer<T>, mut sink: Sink, channels: usize,
+                         shared: &Shared) -> Result<u64> {
+    let mut buf = vec![T::default(); CHUNK_FRAMES * channels];
+    let mut last_patch = Instant::now();
+    loop {
+        // whatever was pushed before the flag was raised is written below
+        let stopping = shared.stopping.load(Ordering::Acquire);
+        loop {
+            let popped = consumer.pop_slice(&mut buf);
+            if popped == 0 {
+                break;
+            }
+            sink.write(&buf[.. popped])?;
+            shared.written.fetch_add((popped / channels) as u64, Ordering::Relaxed);
+        }
+        if stopping {
+            break;
+        }
+        if last_patch.elapsed() >= PATCH_PERIOD {
+            sink.patch()?;
+            last_patch = Instant::now();
+        }
+        thread::sleep(WRITE_PERIOD);
+    }
+    sink.finalize()?;
+    Ok(shared.written.load(Ordering::Relaxed))
+}
+
+// Controls a recording and owns its writer thread, whatever drives the
+// callback.
+pub struct Recording {
+    shared: Arc<Shared>,
+    writer: Option<thread::JoinHandle<Result<u64>>>,
+    rate: u32,
+}
+
+impl Recording {
+    // Input is discarded, not counted as dropped, until `resume`.
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    // Taken from the stream, dropped frames included.
+    pub fn frames_recorded(&self) -> u64 {
+        self.shared.recorded.load(Ordering::Relaxed)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Frames(self.frames_recorded()).to_duration(self.rate)
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.shared.written.load(Ordering::Relaxed)
+    }
+
+    // Frames lost because the writer fell behind, and the callbacks that lost
+    // some.
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    pub fn overruns(&self) -> usize {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+
+    // The file was finalized, or writing it failed.
+    pub fn is_finished(&self) -> bool {
+        self.shared.finished.load(Ordering::Acquire)
+    }
+
+    // The stream reported `State::Error`.
+    pub fn failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Acquire)
+    }
+
+    // Writes what is left, finalizes the file and waits for the writer.
+    pub fn finish(mut self) -> Result<Summary> {
+        self.shared.stopping.store(true, Ordering::Release);
+        let frames = match self.writer.take().map(|w| w.join()) {
+            Some(Ok(result)) => result?,
+            _ => return Err(Error::Stream(::Error::Undefined)),
+        };
+        Ok(Summary { frames, dropped_frames: self.dropped_frames() })
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.shared.stopping.store(true, Ordering::Release);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+// Creates `path` and the callbacks of an input stream recording to it, at
+// `rate` with `channels` channels.
+pub fn recording<T: Sample, P: AsRef<Path>>(path: P, rate: u32, channels: u32,
+                                            layout: ChannelLayout, options: &Options)
+        -> Result<(DataCallback<T>, StateCallback, Recording)> {
+    if rate == 0 || channels == 0 || channels > u16::MAX as u32 {
+        return Err(Error::Stream(::Error::InvalidParameter));
+    }
+    let ch = channels as usize;
+    let sink = match options.format {
+        FileFormat::Wav => {
+            let spec = Spec::new(rate, channels as u16, options.encoding);
+            let spec = if layout.channel_count() == channels {
+                spec.with_layout(layout)
+            } else {
+                spec
+            };
+            Sink::Wav(WavWriter::create(path, spec)?)
+        }
+        FileFormat::Raw => Sink::Raw(BufWriter::new(File::create(path)?), options.encoding,
+                                     Vec::new()),
+    };
+    let buffer_frames = Frames::from_duration(options.buffer, rate).0.max(CHUNK_FRAMES as u64);
+    let (mut producer, consumer) = ring_buffer(buffer_frames as usize * ch);
+    let max_frames = options.max_duration.map(|d| Frames::from_duration(d, rate).0);
+    let shared = Arc::new(Shared {
+        paused: AtomicBool::new(false),
+        stopping: AtomicBool::new(false),
+        failed: AtomicBool::new(false),
+        finished: AtomicBool::new(false),
+        recorded: AtomicU64::new(0),
+        written: AtomicU64::new(0),
+        dropped_frames: AtomicU64::new(0),
+        overruns: AtomicUsize::new(0),
+    });
+
+    let thread_shared = shared.clone();
+    let writer = thread::Builder::new()
+        .name("cult recorder".to_string())
+        .spawn(move || {
+            let result = write_loop(consumer, sink, ch, &thread_shared);
+            thread_shared.finished.store(true, Ordering::Release);
+            result
+        })?;
+
+    let cb_shared = shared.clone();
+    let data_cb: DataCallback<T> = Box::new(move |ibuf: &[T], _: &mut [T]| {
+        let frames = ibuf.len() / ch;
+        if cb_shared.paused.load(Ordering::Relaxed) {
+            return frames;
+        }
+        let recorded = cb_shared.recorded.load(Ordering::Relaxed);
+        let take = max_frames.map_or(frames, |max| {
+            (max.saturating_sub(recorded) as usize).min(frames)
+        });
+        let room = producer.free_len() / ch;
+        let pushed = producer.push_slice(&ibuf[.. take.min(room) * ch]) / ch;
+        if pushed < take {
+            cb_shared.overruns.fetch_add(1, Ordering::Relaxed);
+            cb_shared.dropped_frames.fetch_add((take - pushed) as u64, Ordering::Relaxed);
+        }
+        cb_shared.recorded.store(recorded + take as u64, Ordering::Relaxed);
+        if take < frames {
+            // a short return drains the stream
+            cb_shared.stopping.store(true, Ordering::Release);
+        }
+        take
+    });
+
+    let st_shared = shared.clone();
+    let state_cb: StateCallback = Box::new(move |state: State| {
+        match state {
+            State::Error => {
+                st_shared.failed.store(true, Ordering::Release);
+                st_shared.stopping.store(true, Ordering::Release);
+            }
+            State::Drained => st_shared.stopping.store(true, Ordering::Release),
+            State::Started | State::Stopped => {}
+        }
+    });
+
+    Ok((data_cb, state_cb, Recording { shared, writer: Some(writer), rate }))
+}
+
+pub struct Recorder<T: Sample> {
+    stream: Stream<T>,
+    recording: Recording,
+}
+
+impl<T: Sample> Recorder<T> {
+    pub fn start<P: AsRef<Path>>(ctx: &Context, device: Option<&DevId>,
+                                 params: StreamParams<T>, path: P) -> Result<Recorder<T>> {
+        Recorder::start_with_options(ctx, device, params, path, &Options::default())
+    }
+
+    pub fn start_with_options<P: AsRef<Path>>(ctx: &Context, device: Option<&DevId>,
+                                              params: StreamParams<T>, path: P,
+                                              options: &Options) -> Result<Recorder<T>> {
+        let (data_cb, state_cb, recording) =
+            recording(path, params.rate(), params.channels(), params.layout(), options)?;
+        let stream = Stream::new(ctx, "cult recorder", device, Some(params), None, None,
+                                 options.latency, data_cb, Some(state_cb))?;
+        stream.start()?;
+        Ok(Recorder { stream, recording })
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn stream(&self) -> &Stream<T> {
+        &self.stream
+    }
+
+    pub fn pause(&self) {
+        self.recording.pause()
+    }
+
+    pub fn resume(&self) {
+        self.recording.resume()
+    }
+
+    // Stops the stream and finalizes the file.
+    pub fn stop(self) -> Result<Summary> {
+        let stopped = self.stream.stop();
+        let summary = self.recording.finish()?;
+        stopped?;
+        Ok(summary)
+    }
+}
@@ -0,0 +1,264 @@
+use std::path::Path;
+
+use pipeline;
+use stats::StreamStats;
+use wav::{self, Encoding, Spec, WavWriter};
+use {ChannelLayout, DataCallback, Error, Result, Sample, State, StateCallback, StreamData,
+     StreamParams};
+
+// Runs a data callback without a device, as fast as it goes, e.g. to test it
+// on machines without sound cards or to produce reference files. The callback
+// is wrapped in the same conversions `Stream::new` would insert for the
+// parameters, so buffers are sized and converted as they would be on the
+// device side, and called through the same trampoline as cubeb would. There
+// is a single clock, drift compensation is ignored.
+
+// Frames per callback.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Periods {
+    Fixed(usize),
+    // Uniformly drawn between `min` and `max` included, the same sequence for
+    // the same seed.
+    Random { min: usize, max: usize, seed: u64 },
+}
+
+// Frames rendered at once when writing a file.
+const FILE_CHUNK_FRAMES: u64 = 16384;
+
+pub struct OfflineStream<T: Sample> {
+    data: Box<StreamData<T>>,
+    controls: pipeline::Controls,
+    state_cb: Option<StateCallback>,
+    // device side
+    rate: u32,
+    in_channels: usize,
+    out_channels: usize,
+    out_layout: ChannelLayout,
+    periods: Periods,
+    random: u64,
+    // interleaved, silence once exhausted
+    input: Vec<T>,
+    input_frame: usize,
+    scratch: Vec<T>,
+    frames: u64,
+    started: bool,
+    drained: bool,
+}
+
+impl<T: Sample> OfflineStream<T> {
+    pub fn new(in_params: Option<StreamParams<T>>, out_params: Option<StreamParams<T>>,
+               data_cb: DataCallback<T>, state_cb: Option<StateCallback>)
+            -> Result<OfflineStream<T>> {
+        let main_params = match out_params.or(in_params) {
+            Some(p) => p,
+            None => return Err(Error::InvalidParameter),
+        };
+        let (data_cb, controls) =
+            pipeline::build(data_cb, in_params.as_ref(), out_params.as_ref())?;
+        let rate = main_params.device_rate();
+        Ok(OfflineStream {
+            data: StreamData::new(data_cb, None, in_params.as_ref(), out_params.as_ref()),
+            controls,
+            state_cb,
+            rate,
+            in_channels: in_params.map_or(0, |p| p.device_channels() as usize),
+            out_channels: out_params.map_or(0, |p| p.device_channels() as usize),
+            out_layout: out_params.map_or(ChannelLayout::Undefined, |p| p.device_layout()),
+            periods: Periods::Fixed(512),
+            random: 0,
+            input: Vec::new(),
+            input_frame: 0,
+            scratch: Vec::new(),
+            frames: 0,
+            started: false,
+            drained: false,
+        })
+    }
+
+    // 512 frames per callback by default.
+    pub fn with_periods(mut self, periods: Periods) -> OfflineStream<T> {
+        self.periods = periods;
+        if let Periods::Random { seed, .. } = periods {
+            // xorshift never leaves zero
+            self.random = seed | 1;
+        }
+        self
+    }
+
+    // Interleaved input at the device's rate and channel count. Callbacks
+    // past its end get silence.
+    pub fn with_input(mut self, input: Vec<T>) -> OfflineStream<T> {
+        self.input = input;
+        self.input_frame = 0;
+        self
+    }
+
+    fn next_period(&mut self) -> usize {
+        match self.periods {
+            Periods::Fixed(frames) => frames.max(1),
+            Periods::Random { min, max, .. } => {
+                let (min, max) = (min.max(1), max.max(min.max(1)));
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 7;
+                self.random ^= self.random << 17;
+                min + (self.random % (max - min + 1) as u64) as usize
+            }
+        }
+    }
+
+    // Calls the data callback until `frames` frames have been rendered or
+    // it drained the stream, appending the output to `out`. Returns the
+    // number of frames rendered. A panic of the callback is raised again
+    // from here, after `State::Error`.
+    pub fn render_into(&mut self, out: &mut Vec<T>, frames: u64) -> u64 {
+        if !self.started {
+            self.started = true;
+            self.notify(State::Started);
+        }
+        let mut done = 0;
+        while done < frames && !self.drained {
+            let period = (self.next_period() as u64).min(frames - done) as usize;
+
+            self.scratch.clear();
+            let from = (self.input_frame * self.in_channels).min(self.input.len());
+            let to = ((self.input_frame + period) * self.in_channels).min(self.input.len());
+            self.scratch.extend_from_slice(&self.input[from .. to]);
+            self.scratch.resize(period * self.in_channels, T::default());
+            let base = out.len();
+            out.resize(base + period * self.out_channels, T::default());
+
+            let returned = self.data.call(&self.scratch, &mut out[base ..], period);
+            if returned < 0 {
+                // the callback panicked
+                out.truncate(base);
+                self.drained = true;
+                self.notify(State::Error);
+                self.frames += done;
+                self.data.rethrow_panic();
+                return done;
+            }
+            let returned = (returned as usize).min(period);
+            out.truncate(base + returned * self.out_channels);
+            self.input_frame += period;
+            done += returned as u64;
+            if returned < period {
+                self.drained = true;
+                self.notify(State::Drained);
+            }
+        }
+        self.frames += done;
+        done
+    }
+
+    // Renders up to `frames` frames, fewer if the callback drained the
+    // stream. Empty for input only streams.
+    pub fn render(&mut self, frames: u64) -> Vec<T> {
+        let mut out = Vec::new();
+        self.render_into(&mut out, frames);
+        out
+    }
+
+    // Renders up to `frames` frames to a WAV file of the output's device
+    // side format. Returns the number of frames written.
+    pub fn render_to_wav<P: AsRef<Path>>(&mut self, path: P, frames: u64,
+                                         encoding: Encoding) -> wav::Result<u64> {
+        if self.out_channels == 0 {
+            return Err(wav::Error::Unsupported("no output to render"));
+        }
+        let spec = Spec::new(self.rate, self.out_channels as u16, encoding)
+            .with_layout(self.out_layout);
+        let mut writer = WavWriter::create(path, spec)?;
+        let mut buf = Vec::new();
+        let mut done = 0;
+        while done < frames && !self.drained {
+            buf.clear();
+            done += self.render_into(&mut buf, (frames - done).min(FILE_CHUNK_FRAMES));
+            writer.write(&buf)?;
+        }
+        writer.finalize()?;
+        Ok(done)
+    }
+
+    fn notify(&mut self, state: State) {
+        if let Some(cb) = self.state_cb.as_mut() {
+            cb(state);
+        }
+    }
+
+    // As `Stream::set_output_mix_matrix`, taking effect at the next callback.
+    pub fn set_output_mix_matrix(&self, matrix: &[f32]) -> Result<()> {
+        match self.controls.output_mix {
+            Some(ref mix) => mix.set_matrix(matrix),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    pub fn set_input_mix_matrix(&self, matrix: &[f32]) -> Result<()> {
+        match self.controls.input_mix {
+            Some(ref mix) => mix.set_matrix(matrix),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    // As `Stream::set_output_routing`, taking effect at the next callback.
+    pub fn set_output_routing(&self, map: &[Option<u32>]) -> Result<()> {
+        match self.controls.output_route {
+            Some(ref route) => route.set_map(map),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    pub fn set_input_routing(&self, map: &[Option<u32>]) -> Result<()> {
+        match self.controls.input_route {
+            Some(ref route) => route.set_map(map),
+            None => Err(Error::InvalidParameter),
+        }
+    }
+
+    pub fn output_routing(&self) -> Option<Vec<Option<u32>>> {
+        self.controls.output_route.as_ref().map(|r| r.map())
+    }
+
+    pub fn input_routing(&self) -> Option<Vec<Option<u32>>> {
+        self.controls.input_route.as_ref().map(|r| r.map())
+    }
+
+    // What `Stream::latency` reports for a device latency of `device_frames`
+    // at the device's rate: in frames at the callback's rate, including what
+    // the resampler holds.
+    pub fn latency(&self, device_frames: u32) -> u32 {
+        match self.controls.rate {
+            Some(ref rate) => rate.callback_latency(device_frames, self.out_channels > 0),
+            None => device_frames,
+        }
+    }
+
+    // Frames rendered so far, at the device's rate.
+    pub fn frames_rendered(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.drained
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        #[allow(unused_mut)]
+        let mut stats = self.data.stats.snapshot();
+        #[cfg(feature = "rt-check")]
+        {
+            stats.rt_violations = self.data.rt_violations.count();
+        }
+        stats
+    }
+
+    // Heap operations caught in the data callback so far.
+    #[cfg(feature = "rt-check")]
+    pub fn rt_violations(&self) -> usize {
+        self.data.rt_violations.count()
+    }
+}
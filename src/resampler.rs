@@ -0,0 +1,225 @@
+use std::f64::consts::PI;
+
+use Sample;
+
+// Streaming sample-rate converter for interleaved frames. `Sinc` is a
+// Blackman-windowed sinc interpolator with `SINC_HALF_WIDTH` taps on each
+// side, low-passed below the lower of the two Nyquist frequencies; `Linear`
+// interpolates between neighbouring frames and is much cheaper.
+
+const SINC_HALF_WIDTH: usize = 16;
+// kernel table resolution, in points per input frame
+const SINC_PHASES: usize = 256;
+// fraction of the Nyquist frequency kept by the sinc filter
+const SINC_ROLLOFF: f64 = 0.94;
+// largest input block accepted per `process` call, in frames
+const BLOCK_FRAMES: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Quality {
+    Linear,
+    Sinc,
+}
+
+pub struct Resampler {
+    channels: usize,
+    in_rate: u32,
+    out_rate: u32,
+    quality: Quality,
+    half: usize,
+    // input frames per output frame, adjustable with `set_ratio`
+    ratio: f64,
+    kernel: Vec<f32>,
+    // interleaved input frames, the first one being frame `base` of the
+    // stream
+    buf: Vec<f32>,
+    frames: usize,
+    // position of the next output frame, in frames from the start of `buf`
+    pos: f64,
+    weights: Vec<f32>,
+}
+
+fn blackman(x: f64) -> f64 {
+    // x in [-1, 1]
+    let x = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * x).cos() + 0.08 * (4.0 * PI * x).cos()
+}
+
+impl Resampler {
+    pub fn new(channels: usize, in_rate: u32, out_rate: u32, quality: Quality) -> Resampler {
+        assert!(channels > 0 && in_rate > 0 && out_rate > 0);
+        let half = match quality {
+            Quality::Linear => 1,
+            Quality::Sinc => SINC_HALF_WIDTH,
+        };
+        let mut r = Resampler {
+            channels,
+            in_rate,
+            out_rate,
+            quality,
+            half,
+            ratio: in_rate as f64 / out_rate as f64,
+            kernel: Vec::new(),
+            buf: vec![0_f32; (2 * half + BLOCK_FRAMES + 1) * channels],
+            frames: 0,
+            pos: 0.0,
+            weights: vec![0_f32; 2 * half],
+        };
+        if quality == Quality::Sinc {
+            r.build_kernel();
+        }
+        r.reset();
+        r
+    }
+
+    fn build_kernel(&mut self) {
+        let cutoff = SINC_ROLLOFF * (self.out_rate as f64 / self.in_rate as f64).min(1.0);
+        let half = self.half as f64;
+        let len = 2 * self.half * SINC_PHASES + 1;
+        self.kernel = (0 .. len).map(|i| {
+            let d = i as f64 / SINC_PHASES as f64 - half;
+            let sinc = if d == 0.0 { 1.0 } else { (PI * cutoff * d).sin() / (PI * cutoff * d) };
+            (cutoff * sinc * blackman(d / half)) as f32
+        }).collect();
+    }
+
+    // Forgets all buffered input; the next output starts at the next input.
+    pub fn reset(&mut self) {
+        for s in self.buf.iter_mut() {
+            *s = 0_f32;
+        }
+        // history of silence so the first frames have left neighbours
+        self.frames = self.half - 1;
+        self.pos = (self.half - 1) as f64;
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn quality(&self) -> Quality {
+        self.quality
+    }
+
+    pub fn in_rate(&self) -> u32 {
+        self.in_rate
+    }
+
+    pub fn out_rate(&self) -> u32 {
+        self.out_rate
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    // Input frames consumed per output frame. Small changes around
+    // `in_rate / out_rate` are how drift gets corrected; the filter cutoff
+    // is not recomputed.
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.ratio = ratio;
+    }
+
+    // Delay added by the filter, in input frames.
+    pub fn latency(&self) -> usize {
+        self.half
+    }
+
+    // Input frames buffered but not yet fully used, on top of `latency`.
+    pub fn buffered(&self) -> f64 {
+        (self.frames as f64 - self.pos).max(0.0)
+    }
+
+    // Input frames needed to produce `frames` more output frames.
+    pub fn input_needed(&self, frames: usize) -> usize {
+        let last = self.pos + (frames.max(1) - 1) as f64 * self.ratio;
+        let needed = last.floor() as usize + self.half + 1;
+        needed.saturating_sub(self.frames)
+    }
+
+    // Consumes input and produces output until one of them runs out.
+    // Returns the number of input frames consumed and output frames written.
+    pub fn process<T: Sample>(&mut self, input: &[T], output: &mut [T]) -> (usize, usize) {
+        let ch = self.channels;
+        let in_frames = input.len() / ch;
+        let out_frames = output.len() / ch;
+        let mut consumed = 0;
+        let mut produced = 0;
+
+        loop {
+            // fill the buffer with as much input as fits
+            let room = self.buf.len() / ch - self.frames;
+            let take = room.min(in_frames - consumed);
+            for (d, s) in self.buf[self.frames * ch .. (self.frames + take) * ch].iter_mut()
+                    .zip(input[consumed * ch .. (consumed + take) * ch].iter()) {
+                *d = s.to_f32();
+            }
+            self.frames += take;
+            consumed += take;
+
+            // produce while the kernel of the next output frame is buffered
+            while produced < out_frames {
+                let base = self.pos.floor() as usize;
+                if base + self.half >= self.frames {
+                    break;
+                }
+                let out = &mut output[produced * ch .. (produced + 1) * ch];
+                self.interpolate(base, self.pos - base as f64, out);
+                produced += 1;
+                self.pos += self.ratio;
+            }
+
+            // drop frames no later output frame needs
+            let keep_from = (self.pos.floor() as usize + 1).saturating_sub(self.half);
+            if keep_from > 0 {
+                let keep_from = keep_from.min(self.frames);
+                self.buf.copy_within(keep_from * ch .. self.frames * ch, 0);
+                self.frames -= keep_from;
+                self.pos -= keep_from as f64;
+            }
+
+            if produced == out_frames || consumed == in_frames {
+                break;
+            }
+        }
+        (consumed, produced)
+    }
+
+    fn interpolate<T: Sample>(&mut self, base: usize, frac: f64, out: &mut [T]) {
+        let ch = self.channels;
+        let half = self.half;
+        // taps cover frames base - half + 1 ..= base + half
+        let first = base + 1 - half;
+        match self.quality {
+            Quality::Linear => {
+                self.weights[0] = (1.0 - frac) as f32;
+                self.weights[1] = frac as f32;
+            }
+            Quality::Sinc => {
+                let mut sum = 0_f32;
+                for (k, w) in self.weights.iter_mut().enumerate() {
+                    // distance from the output position to tap k, shifted
+                    // into the table's [0, 2 * half] range
+                    let d = frac + (2 * half - 1) as f64 - k as f64;
+                    let x = d * SINC_PHASES as f64;
+                    let i = (x.floor() as usize).min(self.kernel.len() - 2);
+                    let f = (x - i as f64) as f32;
+                    *w = self.kernel[i] + (self.kernel[i + 1] - self.kernel[i]) * f;
+                    sum += *w;
+                }
+                if sum != 0_f32 {
+                    for w in self.weights.iter_mut() {
+                        *w /= sum;
+                    }
+                }
+            }
+        }
+        for (c, o) in out.iter_mut().enumerate() {
+            let mut acc = 0_f32;
+            for (k, w) in self.weights.iter().enumerate() {
+                acc += self.buf[(first + k) * ch + c] * w;
+            }
+            *o = T::from_f32(acc);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Bounded single-producer single-consumer queue. Neither side ever blocks or
+// allocates once the buffer is created, so either end can live on the audio
+// thread.
+
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+struct Inner<T> {
+    buf: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // read index, only advanced by the consumer
+    head: CachePadded<AtomicUsize>,
+    // write index, only advanced by the producer
+    tail: CachePadded<AtomicUsize>,
+}
+
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+impl<T> Inner<T> {
+    fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    unsafe fn slot(&self, index: usize) -> *mut T {
+        (*self.buf[index % self.buf.len()].get()).as_mut_ptr()
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let head = self.head.0.load(Ordering::Relaxed);
+        let tail = self.tail.0.load(Ordering::Relaxed);
+        let mut i = head;
+        while i != tail {
+            unsafe { ptr::drop_in_place(self.slot(i)); }
+            i = i.wrapping_add(1);
+        }
+    }
+}
+
+pub struct Producer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+pub struct Consumer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Producer<T> {}
+unsafe impl<T: Send> Send for Consumer<T> {}
+
+pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "ring buffer capacity must be non-zero");
+    let buf = (0 .. capacity)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect::<Vec<_>>()
+        .into_boxed_slice();
+    let inner = Arc::new(Inner {
+        buf,
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+    (Producer { inner: inner.clone() }, Consumer { inner })
+}
+
+impl<T> Producer<T> {
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.inner.tail.0.load(Ordering::Relaxed);
+        let head = self.inner.head.0.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn free_len(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.free_len() == 0 {
+            return Err(value);
+        }
+        let tail = self.inner.tail.0.load(Ordering::Relaxed);
+        unsafe { ptr::write(self.inner.slot(tail), value); }
+        self.inner.tail.0.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T: Copy> Producer<T> {
+    pub fn push_slice(&mut self, values: &[T]) -> usize {
+        let count = values.len().min(self.free_len());
+        let tail = self.inner.tail.0.load(Ordering::Relaxed);
+        for (i, v) in values[.. count].iter().enumerate() {
+            unsafe { ptr::write(self.inner.slot(tail.wrapping_add(i)), *v); }
+        }
+        self.inner.tail.0.store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.inner.head.0.load(Ordering::Relaxed);
+        let tail = self.inner.tail.0.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let head = self.inner.head.0.load(Ordering::Relaxed);
+        let value = unsafe { ptr::read(self.inner.slot(head)) };
+        self.inner.head.0.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    pub fn discard(&mut self, count: usize) -> usize {
+        let count = count.min(self.len());
+        let head = self.inner.head.0.load(Ordering::Relaxed);
+        for i in 0 .. count {
+            unsafe { ptr::drop_in_place(self.inner.slot(head.wrapping_add(i))); }
+        }
+        self.inner.head.0.store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    pub fn pop_slice(&mut self, values: &mut [T]) -> usize {
+        let count = values.len().min(self.len());
+        let head = self.inner.head.0.load(Ordering::Relaxed);
+        for (i, v) in values[.. count].iter_mut().enumerate() {
+            *v = unsafe { ptr::read(self.inner.slot(head.wrapping_add(i))) };
+        }
+        self.inner.head.0.store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
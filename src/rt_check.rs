@@ -0,0 +1,150 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::thread;
+
+// Debug aid catching heap allocations made from a data callback. Install
+// `RtCheckAllocator` as the global allocator of the application:
+//
+//     #[global_allocator]
+//     static ALLOC: cult::rt_check::RtCheckAllocator = cult::rt_check::RtCheckAllocator::system();
+//
+// While `data_callback_f32`/`data_callback_i16` run, every allocation,
+// reallocation or deallocation on that thread is a violation, handled
+// according to the `Policy` set with `set_policy`. Panics never leave the
+// trampolines: the stream fails, and the panic is raised again by the next
+// `Stream::start`, `stop` or `stats`, or by `OfflineStream::render`.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum Policy {
+    // Report with a backtrace, then panic when the callback returns.
+    Panic   = 0,
+    // Report the first violation of each stream with a backtrace.
+    LogOnce = 1,
+    // Only count, see `Stream::rt_violations`.
+    Count   = 2,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(Policy::LogOnce as u8);
+
+pub fn set_policy(policy: Policy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn policy() -> Policy {
+    match POLICY.load(Ordering::Relaxed) {
+        0 => Policy::Panic,
+        1 => Policy::LogOnce,
+        _ => Policy::Count,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Violations {
+    count: AtomicUsize,
+    logged: AtomicBool,
+}
+
+impl Violations {
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+thread_local! {
+    // Violation counters of the stream whose callback runs on this thread.
+    static CURRENT: Cell<*const Violations> = const { Cell::new(ptr::null()) };
+    // Set while reporting, so that the report itself can allocate.
+    static SUSPENDED: Cell<bool> = const { Cell::new(false) };
+    static PANIC_PENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub struct Guard {
+    previous: *const Violations,
+}
+
+pub fn enter(violations: &Violations) -> Guard {
+    let previous = CURRENT.with(|c| c.replace(violations as *const _));
+    Guard { previous }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.previous));
+        // not while the callback itself unwinds, that would abort
+        if PANIC_PENDING.with(|p| p.replace(false)) && !thread::panicking() {
+            panic!("heap operation in a cult data callback, see the report above");
+        }
+    }
+}
+
+fn check(what: &str, size: usize) {
+    // `try_with` because the allocator can run while thread locals are torn
+    // down.
+    let current = CURRENT.try_with(|c| c.get()).unwrap_or(ptr::null());
+    if current.is_null() || SUSPENDED.try_with(|s| s.get()).unwrap_or(true) {
+        return;
+    }
+    let violations = unsafe { &*current };
+    violations.count.fetch_add(1, Ordering::Relaxed);
+
+    let policy = policy();
+    let report = match policy {
+        Policy::Panic => true,
+        Policy::LogOnce => !violations.logged.swap(true, Ordering::Relaxed),
+        Policy::Count => false,
+    };
+    if !report {
+        return;
+    }
+    SUSPENDED.with(|s| s.set(true));
+    eprintln!("cult: {} of {} bytes in a data callback\n{}",
+              what, size, Backtrace::force_capture());
+    SUSPENDED.with(|s| s.set(false));
+    if policy == Policy::Panic {
+        // unwinding out of an allocator is undefined behaviour, so the panic
+        // is raised by the guard once the callback is done
+        PANIC_PENDING.with(|p| p.set(true));
+    }
+}
+
+pub struct RtCheckAllocator<A = System> {
+    inner: A,
+}
+
+impl RtCheckAllocator<System> {
+    pub const fn system() -> RtCheckAllocator<System> {
+        RtCheckAllocator { inner: System }
+    }
+}
+
+impl<A> RtCheckAllocator<A> {
+    pub const fn new(inner: A) -> RtCheckAllocator<A> {
+        RtCheckAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for RtCheckAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        check("allocation", layout.size());
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        check("allocation", layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        check("reallocation", new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        check("deallocation", layout.size());
+        self.inner.dealloc(ptr, layout)
+    }
+}
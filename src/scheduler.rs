@@ -0,0 +1,185 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+use clock::StreamClock;
+use command::{command_channel, CommandReceiver, CommandSender};
+use gc::{self, Collector, Retirer};
+use {DataCallback, Sample};
+
+// Sample-accurate playback of buffers layered on an output callback: each
+// scheduled buffer is mixed over what the callback rendered, starting exactly
+// at its stream frame, wherever that falls in a period. Stream frames count
+// from the first frame the callback rendered, as `Stream::position` does.
+//
+// Entries are handed over through a command queue and, once played or
+// cancelled, retired to a `Collector` that must be attached to the stream
+// (`Stream::attach_collector`) or otherwise collected.
+
+struct EntryState {
+    cancelled: AtomicBool,
+    done: AtomicBool,
+}
+
+struct Entry<T: Sample> {
+    start: u64,
+    buffer: Vec<T>,
+    state: Arc<EntryState>,
+}
+
+// A played or cancelled buffer, waiting to be freed.
+pub struct Retired<T: Sample>(Entry<T>);
+
+pub struct Cancel {
+    state: Arc<EntryState>,
+}
+
+impl Cancel {
+    // Stops the buffer at the next period, or keeps it from starting.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Played to the end, or cancelled and removed.
+    pub fn is_done(&self) -> bool {
+        self.state.done.load(Ordering::Acquire)
+    }
+}
+
+pub struct Scheduler<T: Sample> {
+    sender: CommandSender<Entry<T>>,
+    channels: usize,
+    rendered: Arc<AtomicU64>,
+}
+
+impl<T: Sample> Scheduler<T> {
+    // Plays the interleaved `buffer` from stream frame `start_frame`. A start
+    // in the past plays what remains, keeping the alignment. Gives the buffer
+    // back when the queue is full.
+    pub fn schedule(&mut self, start_frame: u64, buffer: Vec<T>) -> Result<Cancel, Vec<T>> {
+        let state = Arc::new(EntryState {
+            cancelled: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+        });
+        let entry = Entry { start: start_frame, buffer, state: state.clone() };
+        match self.sender.send(entry) {
+            Ok(()) => Ok(Cancel { state }),
+            Err(entry) => Err(entry.buffer),
+        }
+    }
+
+    // Plays `buffer` so that it is heard at `instant`, using a clock of the
+    // stream the scheduler renders for. Gives the buffer back when the clock
+    // has no estimate yet or the queue is full.
+    pub fn schedule_at(&mut self, instant: Instant, clock: &StreamClock<T>, buffer: Vec<T>)
+            -> Result<Cancel, Vec<T>> {
+        match clock.instant_to_frame(instant) {
+            Ok(frame) => {
+                let frame = (frame as i64 - clock.offset()).max(0) as u64;
+                self.schedule(frame, buffer)
+            }
+            Err(_) => Err(buffer),
+        }
+    }
+
+    // Frames rendered so far, i.e. the stream frame of the next callback.
+    pub fn current_frame(&self) -> u64 {
+        self.rendered.load(Ordering::Relaxed)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+}
+
+struct Mixer<T: Sample> {
+    receiver: CommandReceiver<Entry<T>>,
+    retirer: Retirer<Retired<T>>,
+    active: Vec<Entry<T>>,
+    // `active` never grows past this, so that it never allocates
+    capacity: usize,
+    channels: usize,
+    frame: u64,
+    rendered: Arc<AtomicU64>,
+}
+
+impl<T: Sample> Mixer<T> {
+    fn mix(&mut self, out: &mut [T]) {
+        let ch = self.channels;
+        let frames = (out.len() / ch) as u64;
+        while self.active.len() < self.capacity {
+            match self.receiver.try_recv() {
+                Some(entry) => self.active.push(entry),
+                None => break,
+            }
+        }
+
+        let (begin, end) = (self.frame, self.frame + frames);
+        for entry in self.active.iter() {
+            if entry.state.cancelled.load(Ordering::Relaxed) {
+                continue;
+            }
+            let entry_end = entry.start + (entry.buffer.len() / ch) as u64;
+            let from = entry.start.max(begin);
+            let to = entry_end.min(end);
+            if from >= to {
+                continue;
+            }
+            let dst = &mut out[(from - begin) as usize * ch .. (to - begin) as usize * ch];
+            let src = &entry.buffer[(from - entry.start) as usize * ch ..
+                                    (to - entry.start) as usize * ch];
+            for (o, s) in dst.iter_mut().zip(src.iter()) {
+                *o = T::from_f32(o.to_f32() + s.to_f32());
+            }
+        }
+
+        self.frame = end;
+        self.rendered.store(end, Ordering::Relaxed);
+        let mut i = 0;
+        while i < self.active.len() {
+            let entry = &self.active[i];
+            let entry_end = entry.start + (entry.buffer.len() / ch) as u64;
+            if entry_end > end && !entry.state.cancelled.load(Ordering::Relaxed) {
+                i += 1;
+                continue;
+            }
+            entry.state.done.store(true, Ordering::Release);
+            let entry = self.active.swap_remove(i);
+            if let Err(Retired(entry)) = self.retirer.retire(Retired(entry)) {
+                // the collector is behind, try again next period
+                self.active.push(entry);
+                break;
+            }
+        }
+    }
+}
+
+// Wraps the output callback `data_cb` of a `channels` channel stream, with
+// room for `capacity` pending and as many playing buffers.
+pub fn scheduler<T: Sample>(channels: u32, capacity: usize, mut data_cb: DataCallback<T>)
+        -> (DataCallback<T>, Scheduler<T>, Collector<Retired<T>>) {
+    assert!(channels > 0 && capacity > 0);
+    let channels = channels as usize;
+    let (sender, receiver) = command_channel(capacity);
+    let (retirer, collector) = gc::channel(capacity);
+    let rendered = Arc::new(AtomicU64::new(0));
+    let mut mixer = Mixer {
+        receiver,
+        retirer,
+        active: Vec::with_capacity(capacity),
+        capacity,
+        channels,
+        frame: 0,
+        rendered: rendered.clone(),
+    };
+    let cb: DataCallback<T> = Box::new(move |ibuf: &[T], obuf: &mut [T]| {
+        let returned = data_cb(ibuf, obuf).min(obuf.len() / channels);
+        mixer.mix(&mut obuf[.. returned * channels]);
+        returned
+    });
+    (cb, Scheduler { sender, channels, rendered }, collector)
+}
@@ -0,0 +1,68 @@
+// Audio producers pulled by the `Mixer`, on the audio thread: `fill` must not
+// block, allocate or free.
+
+pub trait Source: Send {
+    // Writes interleaved frames of `channels` channels to `out`, returns how
+    // many. Fewer frames than `out` holds means the source is over.
+    fn fill(&mut self, out: &mut [f32], channels: usize) -> usize;
+}
+
+impl<F: FnMut(&mut [f32], usize) -> usize + Send> Source for F {
+    fn fill(&mut self, out: &mut [f32], channels: usize) -> usize {
+        self(out, channels)
+    }
+}
+
+// Plays an interleaved buffer once. Mono buffers go to every channel, stereo
+// ones are averaged for a mono output; otherwise channels are matched by
+// index, extra ones being silent or dropped.
+pub struct BufferSource {
+    data: Vec<f32>,
+    channels: usize,
+    position: usize,
+}
+
+impl BufferSource {
+    pub fn new(data: Vec<f32>, channels: usize) -> BufferSource {
+        assert!(channels > 0);
+        BufferSource { data, channels, position: 0 }
+    }
+
+    // In frames.
+    pub fn len(&self) -> usize {
+        self.data.len() / self.channels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.len() - self.position
+    }
+}
+
+impl Source for BufferSource {
+    fn fill(&mut self, out: &mut [f32], channels: usize) -> usize {
+        let frames = (out.len() / channels).min(self.remaining());
+        let src_ch = self.channels;
+        let src = &self.data[self.position * src_ch .. (self.position + frames) * src_ch];
+        for (o, s) in out.chunks_mut(channels).zip(src.chunks(src_ch)) {
+            if src_ch == channels {
+                o.copy_from_slice(s);
+            } else if src_ch == 1 {
+                for v in o.iter_mut() {
+                    *v = s[0];
+                }
+            } else if channels == 1 && src_ch == 2 {
+                o[0] = 0.5 * (s[0] + s[1]);
+            } else {
+                for (c, v) in o.iter_mut().enumerate() {
+                    *v = if c < src_ch { s[c] } else { 0_f32 };
+                }
+            }
+        }
+        self.position += frames;
+        frames
+    }
+}
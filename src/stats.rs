@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Lock-free timing statistics, updated by the data callback trampolines and
+// read from any thread with `Stream::stats`.
+
+// Bucket `i` counts values in [2^i, 2^(i+1)) microseconds, the last one
+// everything above.
+pub const HISTOGRAM_BUCKETS: usize = 24;
+
+// A callback arriving later than this factor times the previous period is
+// treated as a probable xrun.
+const LATE_FACTOR: f64 = 1.5;
+
+pub struct Histogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram { buckets: Default::default() }
+    }
+
+    fn record(&self, value: Duration) {
+        let us = value.as_micros() as u64;
+        let bucket = if us == 0 { 0 } else { 63 - us.leading_zeros() as usize };
+        self.buckets[bucket.min(HISTOGRAM_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> [u64; HISTOGRAM_BUCKETS] {
+        let mut buckets = [0; HISTOGRAM_BUCKETS];
+        for (b, a) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *b = a.load(Ordering::Relaxed);
+        }
+        buckets
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HistogramSnapshot {
+    pub buckets: [u64; HISTOGRAM_BUCKETS],
+}
+
+impl HistogramSnapshot {
+    // Lower bound of bucket `i`.
+    pub fn bucket_start(i: usize) -> Duration {
+        if i == 0 { Duration::from_micros(0) } else { Duration::from_micros(1 << i) }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    // Upper bound of the bucket holding the `q` quantile (0 to 1).
+    pub fn quantile(&self, q: f64) -> Duration {
+        let total = self.count();
+        if total == 0 {
+            return Duration::from_micros(0);
+        }
+        let target = (q.clamp(0.0, 1.0) * total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Duration::from_micros(1 << (i + 1));
+            }
+        }
+        Duration::from_micros(1 << HISTOGRAM_BUCKETS)
+    }
+}
+
+pub struct CallbackStats {
+    epoch: Instant,
+    rate: u32,
+    has_input: bool,
+    has_output: bool,
+    duration: Histogram,
+    interval: Histogram,
+    callbacks: AtomicU64,
+    frames_requested: AtomicU64,
+    frames_returned: AtomicU64,
+    short_returns: AtomicU64,
+    late_callbacks: AtomicU64,
+    overloads: AtomicU64,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+    busy_ns: AtomicU64,
+    budget_ns: AtomicU64,
+    max_duration_ns: AtomicU64,
+    // permyriad, to keep it in an integer atomic
+    peak_load: AtomicUsize,
+    // ns since epoch of the previous callback start, 0 before the first one
+    last_start_ns: AtomicU64,
+    last_period_ns: AtomicU64,
+}
+
+impl CallbackStats {
+    pub fn new(rate: u32, has_input: bool, has_output: bool) -> CallbackStats {
+        CallbackStats {
+            epoch: Instant::now(),
+            rate,
+            has_input,
+            has_output,
+            duration: Histogram::new(),
+            interval: Histogram::new(),
+            callbacks: AtomicU64::new(0),
+            frames_requested: AtomicU64::new(0),
+            frames_returned: AtomicU64::new(0),
+            short_returns: AtomicU64::new(0),
+            late_callbacks: AtomicU64::new(0),
+            overloads: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+            busy_ns: AtomicU64::new(0),
+            budget_ns: AtomicU64::new(0),
+            max_duration_ns: AtomicU64::new(0),
+            peak_load: AtomicUsize::new(0),
+            last_start_ns: AtomicU64::new(0),
+            last_period_ns: AtomicU64::new(0),
+        }
+    }
+
+    // Only ever called from the audio thread, so plain loads and stores are
+    // enough for the values it alone writes.
+    pub fn record(&self, start: Instant, end: Instant, requested: u64, returned: u64) {
+        let start_ns = (start - self.epoch).as_nanos() as u64 + 1;
+        let duration = end - start;
+        let duration_ns = duration.as_nanos() as u64;
+        let budget_ns = if self.rate > 0 { requested * 1_000_000_000 / self.rate as u64 } else { 0 };
+
+        let mut xrun = false;
+        let last_start = self.last_start_ns.swap(start_ns, Ordering::Relaxed);
+        if last_start != 0 {
+            let interval_ns = start_ns - last_start;
+            self.interval.record(Duration::from_nanos(interval_ns));
+            let last_period = self.last_period_ns.load(Ordering::Relaxed);
+            if last_period > 0 && interval_ns as f64 > last_period as f64 * LATE_FACTOR {
+                self.late_callbacks.fetch_add(1, Ordering::Relaxed);
+                xrun = true;
+            }
+        }
+        self.last_period_ns.store(budget_ns, Ordering::Relaxed);
+
+        self.duration.record(duration);
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.frames_requested.fetch_add(requested, Ordering::Relaxed);
+        self.frames_returned.fetch_add(returned, Ordering::Relaxed);
+        if returned < requested {
+            self.short_returns.fetch_add(1, Ordering::Relaxed);
+        }
+        if budget_ns > 0 && duration_ns > budget_ns {
+            self.overloads.fetch_add(1, Ordering::Relaxed);
+            xrun = true;
+        }
+        if xrun {
+            if self.has_output {
+                self.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+            if self.has_input {
+                self.overruns.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.busy_ns.fetch_add(duration_ns, Ordering::Relaxed);
+        self.budget_ns.fetch_add(budget_ns, Ordering::Relaxed);
+        if duration_ns > self.max_duration_ns.load(Ordering::Relaxed) {
+            self.max_duration_ns.store(duration_ns, Ordering::Relaxed);
+        }
+        if let Some(load) = (duration_ns * 10_000).checked_div(budget_ns) {
+            if load as usize > self.peak_load.load(Ordering::Relaxed) {
+                self.peak_load.store(load as usize, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> StreamStats {
+        let callbacks = self.callbacks.load(Ordering::Relaxed);
+        let busy_ns = self.busy_ns.load(Ordering::Relaxed);
+        let budget_ns = self.budget_ns.load(Ordering::Relaxed);
+        StreamStats {
+            callbacks,
+            frames_requested: self.frames_requested.load(Ordering::Relaxed),
+            frames_returned: self.frames_returned.load(Ordering::Relaxed),
+            short_returns: self.short_returns.load(Ordering::Relaxed),
+            late_callbacks: self.late_callbacks.load(Ordering::Relaxed),
+            overloads: self.overloads.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            mean_duration: Duration::from_nanos(busy_ns / callbacks.max(1)),
+            max_duration: Duration::from_nanos(self.max_duration_ns.load(Ordering::Relaxed)),
+            dsp_load: if budget_ns > 0 { 100.0 * busy_ns as f64 / budget_ns as f64 } else { 0.0 },
+            peak_dsp_load: self.peak_load.load(Ordering::Relaxed) as f64 / 100.0,
+            duration: HistogramSnapshot { buckets: self.duration.snapshot() },
+            interval: HistogramSnapshot { buckets: self.interval.snapshot() },
+            rt_violations: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct StreamStats {
+    pub callbacks: u64,
+    pub frames_requested: u64,
+    pub frames_returned: u64,
+    // callbacks that returned fewer frames than requested
+    pub short_returns: u64,
+    // callbacks that started much later than the previous period
+    pub late_callbacks: u64,
+    // callbacks that took longer than the real-time length of their period
+    pub overloads: u64,
+    // late or overloaded callbacks, counted against the output or input side
+    pub underruns: u64,
+    pub overruns: u64,
+    pub mean_duration: Duration,
+    pub max_duration: Duration,
+    // time spent in the callback relative to the real-time length of the
+    // periods, in percent, averaged since the stream was created
+    pub dsp_load: f64,
+    // worst single period, in percent
+    pub peak_dsp_load: f64,
+    pub duration: HistogramSnapshot,
+    pub interval: HistogramSnapshot,
+    // heap operations caught with the `rt-check` feature, 0 without it
+    pub rt_violations: usize,
+}
@@ -0,0 +1,359 @@
+use std::time::Duration;
+
+use generator::{Generator, Oscillator, Waveform};
+use source::Source;
+use units::Frames;
+use {DataCallback, Sample};
+
+// Call progress tones after ITU-T E.180 and its national annexes, and DTMF
+// digits. A tone is a list of segments, sounding or silent, played once or
+// repeated; the output fades in and out over `GATE_SECONDS` at the segment
+// boundaries so that cadences don't click.
+
+const GATE_SECONDS: f32 = 0.005;
+const MAX_FREQUENCIES: usize = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tone {
+    Dial,
+    Ringback,
+    Busy,
+    Congestion,
+    CallWaiting,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneSpec {
+    // summed, at equal levels
+    pub frequencies: &'static [f32],
+    // amplitude modulation frequency, e.g. Japan's 400Hz modulated by 16Hz
+    pub modulation: Option<f32>,
+    // alternating on and off durations in milliseconds, repeated, starting
+    // with on; empty for a continuous tone
+    pub cadence: &'static [u32],
+}
+
+const fn spec(frequencies: &'static [f32], cadence: &'static [u32]) -> ToneSpec {
+    ToneSpec { frequencies, modulation: None, cadence }
+}
+
+const fn modulated(frequency: &'static [f32], modulation: f32,
+                   cadence: &'static [u32]) -> ToneSpec {
+    ToneSpec { frequencies: frequency, modulation: Some(modulation), cadence }
+}
+
+// ISO 3166 code, then dial, ringback, busy, congestion and call waiting.
+static TONES: &[(&str, [ToneSpec; 5])] = &[
+    ("AU", [spec(&[413.0, 438.0], &[]),
+            spec(&[413.0, 438.0], &[400, 200, 400, 2000]),
+            spec(&[425.0], &[375, 375]),
+            spec(&[425.0], &[375, 375]),
+            spec(&[425.0], &[200, 100, 200, 4400])]),
+    ("BR", [spec(&[425.0], &[]),
+            spec(&[425.0], &[1000, 4000]),
+            spec(&[425.0], &[250, 250]),
+            spec(&[425.0], &[750, 250, 250, 250]),
+            spec(&[425.0], &[50, 1000])]),
+    ("CA", [spec(&[350.0, 440.0], &[]),
+            spec(&[440.0, 480.0], &[2000, 4000]),
+            spec(&[480.0, 620.0], &[500, 500]),
+            spec(&[480.0, 620.0], &[250, 250]),
+            spec(&[440.0], &[300, 9700])]),
+    ("CN", [spec(&[450.0], &[]),
+            spec(&[450.0], &[1000, 4000]),
+            spec(&[450.0], &[350, 350]),
+            spec(&[450.0], &[700, 700]),
+            spec(&[450.0], &[400, 4000])]),
+    ("DE", [spec(&[425.0], &[]),
+            spec(&[425.0], &[1000, 4000]),
+            spec(&[425.0], &[480, 480]),
+            spec(&[425.0], &[240, 240]),
+            spec(&[425.0], &[200, 200, 200, 5000])]),
+    ("ES", [spec(&[425.0], &[]),
+            spec(&[425.0], &[1500, 3000]),
+            spec(&[425.0], &[200, 200]),
+            spec(&[425.0], &[200, 200, 200, 200, 200, 600]),
+            spec(&[425.0], &[175, 175, 175, 3500])]),
+    ("FR", [spec(&[440.0], &[]),
+            spec(&[440.0], &[1500, 3500]),
+            spec(&[440.0], &[500, 500]),
+            spec(&[440.0], &[250, 250]),
+            spec(&[440.0], &[300, 10000])]),
+    ("GB", [spec(&[350.0, 440.0], &[]),
+            spec(&[400.0, 450.0], &[400, 200, 400, 2000]),
+            spec(&[400.0], &[375, 375]),
+            spec(&[400.0], &[400, 350, 225, 525]),
+            spec(&[400.0], &[100, 4000])]),
+    ("IN", [modulated(&[400.0], 25.0, &[]),
+            modulated(&[400.0], 25.0, &[400, 200, 400, 2000]),
+            spec(&[400.0], &[750, 750]),
+            spec(&[400.0], &[250, 250]),
+            spec(&[400.0], &[200, 100, 200, 7500])]),
+    ("IT", [spec(&[425.0], &[200, 200, 600, 1000]),
+            spec(&[425.0], &[1000, 4000]),
+            spec(&[425.0], &[500, 500]),
+            spec(&[425.0], &[200, 200]),
+            spec(&[425.0], &[400, 100, 250, 100, 150, 14000])]),
+    ("JP", [spec(&[400.0], &[]),
+            modulated(&[400.0], 16.0, &[1000, 2000]),
+            spec(&[400.0], &[500, 500]),
+            spec(&[400.0], &[500, 500]),
+            modulated(&[400.0], 16.0, &[500, 8000])]),
+    ("NL", [spec(&[425.0], &[]),
+            spec(&[425.0], &[1000, 4000]),
+            spec(&[425.0], &[500, 500]),
+            spec(&[425.0], &[250, 250]),
+            spec(&[425.0], &[500, 9500])]),
+    ("RU", [spec(&[425.0], &[]),
+            spec(&[425.0], &[800, 3200]),
+            spec(&[425.0], &[350, 350]),
+            spec(&[425.0], &[175, 175]),
+            spec(&[425.0], &[200, 5000])]),
+    ("SE", [spec(&[425.0], &[]),
+            spec(&[425.0], &[1000, 5000]),
+            spec(&[425.0], &[250, 250]),
+            spec(&[425.0], &[250, 750]),
+            spec(&[425.0], &[200, 500, 200, 5000])]),
+    ("US", [spec(&[350.0, 440.0], &[]),
+            spec(&[440.0, 480.0], &[2000, 4000]),
+            spec(&[480.0, 620.0], &[500, 500]),
+            spec(&[480.0, 620.0], &[250, 250]),
+            spec(&[440.0], &[300, 9700])]),
+];
+
+// Countries with a tone table.
+pub fn countries() -> Vec<&'static str> {
+    TONES.iter().map(|&(country, _)| country).collect()
+}
+
+impl ToneSpec {
+    // `country` is an ISO 3166 alpha-2 code, in any case.
+    pub fn lookup(country: &str, tone: Tone) -> Option<ToneSpec> {
+        let (_, specs) = TONES.iter().find(|&&(c, _)| c.eq_ignore_ascii_case(country))?;
+        Some(specs[tone as usize])
+    }
+}
+
+pub(crate) const DTMF_ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+pub(crate) const DTMF_COLUMNS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+pub(crate) const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+// Low and high group frequencies of a key, `A` to `D` in either case.
+pub fn dtmf_frequencies(key: char) -> Option<(f32, f32)> {
+    let key = key.to_ascii_uppercase();
+    for (row, keys) in DTMF_KEYS.iter().enumerate() {
+        if let Some(column) = keys.iter().position(|&k| k == key) {
+            return Some((DTMF_ROWS[row], DTMF_COLUMNS[column]));
+        }
+    }
+    None
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Segment {
+    frequencies: [f32; MAX_FREQUENCIES],
+    count: usize,
+    modulation: Option<f32>,
+    // zero for forever
+    frames: u64,
+    on: bool,
+}
+
+pub struct ToneGenerator {
+    rate: u32,
+    level: f32,
+    segments: Vec<Segment>,
+    repeat: bool,
+    segment: usize,
+    elapsed: u64,
+    oscillators: [Oscillator; MAX_FREQUENCIES],
+    modulator: Oscillator,
+    // fades the output in and out
+    gate: f32,
+    gate_step: f32,
+    // past the last segment of a tone played once
+    ended: bool,
+}
+
+impl ToneGenerator {
+    // `level` is the peak amplitude of the whole tone.
+    pub fn new(spec: ToneSpec, rate: u32, level: f32) -> ToneGenerator {
+        assert!(!spec.frequencies.is_empty() && spec.frequencies.len() <= MAX_FREQUENCIES);
+        let mut frequencies = [0_f32; MAX_FREQUENCIES];
+        frequencies[.. spec.frequencies.len()].copy_from_slice(spec.frequencies);
+        let on = Segment {
+            frequencies,
+            count: spec.frequencies.len(),
+            modulation: spec.modulation,
+            frames: 0,
+            on: true,
+        };
+        let segments = if spec.cadence.is_empty() {
+            vec![on]
+        } else {
+            spec.cadence.iter().enumerate().map(|(i, &ms)| Segment {
+                frames: millis_to_frames(ms, rate),
+                on: i % 2 == 0,
+                ..on
+            }).collect()
+        };
+        ToneGenerator::with_segments(segments, true, rate, level)
+    }
+
+    pub fn for_country(country: &str, tone: Tone, rate: u32,
+                       level: f32) -> Option<ToneGenerator> {
+        ToneSpec::lookup(country, tone).map(|spec| ToneGenerator::new(spec, rate, level))
+    }
+
+    // Plays `keys` once, each for `on` followed by `off` of silence. None if
+    // one of them is not a DTMF key.
+    pub fn dtmf(keys: &str, on: Duration, off: Duration, rate: u32,
+                level: f32) -> Option<ToneGenerator> {
+        let (on, off) = (Frames::from_duration(on, rate).0, Frames::from_duration(off, rate).0);
+        let mut segments = Vec::new();
+        for key in keys.chars() {
+            let (low, high) = dtmf_frequencies(key)?;
+            let tone = Segment {
+                frequencies: [low, high, 0.0],
+                count: 2,
+                modulation: None,
+                frames: on.max(1),
+                on: true,
+            };
+            segments.push(tone);
+            segments.push(Segment { frames: off.max(1), on: false, ..tone });
+        }
+        Some(ToneGenerator::with_segments(segments, false, rate, level))
+    }
+
+    fn with_segments(segments: Vec<Segment>, repeat: bool, rate: u32,
+                     level: f32) -> ToneGenerator {
+        assert!(rate > 0);
+        let silent = || Oscillator::new(Waveform::Sine, rate, 0.0, 0.0);
+        let mut generator = ToneGenerator {
+            rate,
+            level,
+            ended: segments.is_empty(),
+            segments,
+            repeat,
+            segment: 0,
+            elapsed: 0,
+            oscillators: [silent(), silent(), silent()],
+            modulator: silent(),
+            gate: 0.0,
+            gate_step: 1.0 / (GATE_SECONDS * rate as f32).max(1.0),
+        };
+        generator.enter();
+        generator
+    }
+
+    // Restarts the oscillators on a sounding segment, unless they already
+    // play the same frequencies.
+    fn enter(&mut self) {
+        if self.ended {
+            return;
+        }
+        let segment = self.segments[self.segment];
+        if !segment.on {
+            return;
+        }
+        let same = self.gate > 0.0
+            && self.modulator.frequency() == segment.modulation.unwrap_or(0.0)
+            && self.oscillators.iter().zip(&segment.frequencies).all(|(o, &f)| o.frequency() == f);
+        if same {
+            return;
+        }
+        let amplitude = self.level / segment.count as f32;
+        for (o, &f) in self.oscillators.iter_mut().zip(&segment.frequencies) {
+            let amplitude = if f > 0.0 { amplitude } else { 0.0 };
+            *o = Oscillator::new(Waveform::Sine, self.rate, f, amplitude);
+        }
+        self.modulator = Oscillator::new(Waveform::Sine, self.rate,
+                                         segment.modulation.unwrap_or(0.0), 1.0);
+    }
+
+    fn advance(&mut self) {
+        if self.ended {
+            return;
+        }
+        let frames = self.segments[self.segment].frames;
+        self.elapsed += 1;
+        if frames == 0 || self.elapsed < frames {
+            return;
+        }
+        self.elapsed = 0;
+        self.segment += 1;
+        if self.segment == self.segments.len() {
+            if !self.repeat {
+                self.ended = true;
+                return;
+            }
+            self.segment = 0;
+        }
+        self.enter();
+    }
+
+    // A tone played once, e.g. DTMF keys, ended and faded out.
+    pub fn is_finished(&self) -> bool {
+        self.ended && self.gate == 0.0
+    }
+
+    // Like `fill_frames`, but stops once finished. Returns the number of
+    // frames written.
+    pub fn fill_until_finished<T: Sample>(&mut self, out: &mut [T], channels: usize) -> usize {
+        let mut played = 0;
+        for frame in out.chunks_mut(channels) {
+            if self.is_finished() {
+                break;
+            }
+            let v = T::from_f32(self.next_sample());
+            for s in frame.iter_mut() {
+                *s = v;
+            }
+            played += 1;
+        }
+        played
+    }
+
+    // Plays on every channel of a stream, draining it once finished.
+    pub fn into_callback<T: Sample>(mut self, channels: usize) -> DataCallback<T> {
+        Box::new(move |_: &[T], out: &mut [T]| self.fill_until_finished(out, channels))
+    }
+}
+
+fn millis_to_frames(ms: u32, rate: u32) -> u64 {
+    Frames::from_duration(Duration::from_millis(ms as u64), rate).0.max(1)
+}
+
+impl Generator for ToneGenerator {
+    fn next_sample(&mut self) -> f32 {
+        let on = !self.ended && self.segments[self.segment].on;
+        self.gate = if on {
+            (self.gate + self.gate_step).min(1.0)
+        } else {
+            (self.gate - self.gate_step).max(0.0)
+        };
+        let mut value = 0.0;
+        if self.gate > 0.0 {
+            for o in self.oscillators.iter_mut() {
+                value += o.next_sample();
+            }
+            if self.modulator.frequency() > 0.0 {
+                value *= 0.5 * (1.0 + self.modulator.next_sample());
+            }
+        }
+        self.advance();
+        value * self.gate
+    }
+}
+
+impl Source for ToneGenerator {
+    fn fill(&mut self, out: &mut [f32], channels: usize) -> usize {
+        self.fill_until_finished(out, channels)
+    }
+}
@@ -0,0 +1,76 @@
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Latest value handoff between one writer and one reader. The writer fills
+// a back buffer and publishes it, the reader picks the latest published one
+// up when it wants to; neither ever waits for the other, and values are
+// reused in place so nothing allocates once created. Values published while
+// the reader was not looking are skipped.
+
+// set on the shared index when it holds a value the reader has not seen
+const FRESH: usize = 4;
+
+struct Inner<T> {
+    buffers: [UnsafeCell<T>; 3],
+    // index of the buffer in the middle, with `FRESH`
+    shared: AtomicUsize,
+}
+
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+pub struct Writer<T> {
+    inner: Arc<Inner<T>>,
+    back: usize,
+}
+
+pub struct Reader<T> {
+    inner: Arc<Inner<T>>,
+    front: usize,
+}
+
+unsafe impl<T: Send> Send for Writer<T> {}
+unsafe impl<T: Send> Send for Reader<T> {}
+
+pub fn triple_buffer<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+    let inner = Arc::new(Inner {
+        buffers: [UnsafeCell::new(initial.clone()), UnsafeCell::new(initial.clone()),
+                  UnsafeCell::new(initial)],
+        shared: AtomicUsize::new(1),
+    });
+    (Writer { inner: inner.clone(), back: 0 }, Reader { inner, front: 2 })
+}
+
+impl<T> Writer<T> {
+    // The buffer to fill before `publish`. It holds whatever it held when
+    // it was last handed back, not necessarily the latest value.
+    pub fn back(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.buffers[self.back].get() }
+    }
+
+    pub fn publish(&mut self) {
+        let previous = self.inner.shared.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = previous & !FRESH;
+    }
+
+    // Replaces the back buffer's value and publishes it.
+    pub fn write(&mut self, value: T) {
+        *self.back() = value;
+        self.publish();
+    }
+}
+
+impl<T> Reader<T> {
+    pub fn has_update(&self) -> bool {
+        self.inner.shared.load(Ordering::Relaxed) & FRESH != 0
+    }
+
+    // The latest published value, or the initial one.
+    pub fn read(&mut self) -> &T {
+        if self.has_update() {
+            let previous = self.inner.shared.swap(self.front, Ordering::AcqRel);
+            self.front = previous & !FRESH;
+        }
+        unsafe { &*self.inner.buffers[self.front].get() }
+    }
+}
@@ -0,0 +1,130 @@
+use std::ops::{Add, Sub};
+use std::time::Duration;
+
+// Counts of audio that cannot be mixed up with each other: a frame holds one
+// sample per channel, and lasts 1 / rate seconds.
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Frames(pub u64);
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Samples(pub u64);
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+impl Frames {
+    pub fn to_samples(self, channels: u32) -> Samples {
+        Samples(self.0 * channels as u64)
+    }
+
+    pub fn to_duration(self, rate: u32) -> Duration {
+        if rate == 0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_nanos((self.0 as u128 * NANOS_PER_SEC / rate as u128) as u64)
+    }
+
+    // Rounded to the nearest frame.
+    pub fn from_duration(duration: Duration, rate: u32) -> Frames {
+        let nanos = duration.as_nanos() * rate as u128;
+        Frames(((nanos + NANOS_PER_SEC / 2) / NANOS_PER_SEC) as u64)
+    }
+
+    // The same length of time at another rate, rounded up.
+    pub fn at_rate(self, from: u32, to: u32) -> Frames {
+        if from == to || from == 0 {
+            return self;
+        }
+        Frames((self.0 * to as u64).div_ceil(from as u64))
+    }
+}
+
+impl Samples {
+    // Whole frames only.
+    pub fn to_frames(self, channels: u32) -> Frames {
+        Frames(self.0.checked_div(channels as u64).unwrap_or(0))
+    }
+}
+
+impl From<u32> for Frames {
+    fn from(frames: u32) -> Frames {
+        Frames(frames as u64)
+    }
+}
+
+impl From<u64> for Frames {
+    fn from(frames: u64) -> Frames {
+        Frames(frames)
+    }
+}
+
+impl From<u64> for Samples {
+    fn from(samples: u64) -> Samples {
+        Samples(samples)
+    }
+}
+
+impl Add for Frames {
+    type Output = Frames;
+    fn add(self, other: Frames) -> Frames {
+        Frames(self.0 + other.0)
+    }
+}
+
+// Saturates at zero: a position before another one is no distance at all.
+impl Sub for Frames {
+    type Output = Frames;
+    fn sub(self, other: Frames) -> Frames {
+        Frames(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Add for Samples {
+    type Output = Samples;
+    fn add(self, other: Samples) -> Samples {
+        Samples(self.0 + other.0)
+    }
+}
+
+// Saturates at zero: a position before another one is no distance at all.
+impl Sub for Samples {
+    type Output = Samples;
+    fn sub(self, other: Samples) -> Samples {
+        Samples(self.0.saturating_sub(other.0))
+    }
+}
+
+// Latency asked for when creating a stream. Plain integers are frames, as
+// `Context::min_latency` returns them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Latency {
+    Frames(Frames),
+    Duration(Duration),
+}
+
+impl Latency {
+    pub fn to_frames(self, rate: u32) -> Frames {
+        match self {
+            Latency::Frames(frames) => frames,
+            Latency::Duration(duration) => Frames::from_duration(duration, rate),
+        }
+    }
+}
+
+impl From<u32> for Latency {
+    fn from(frames: u32) -> Latency {
+        Latency::Frames(Frames(frames as u64))
+    }
+}
+
+impl From<Frames> for Latency {
+    fn from(frames: Frames) -> Latency {
+        Latency::Frames(frames)
+    }
+}
+
+impl From<Duration> for Latency {
+    fn from(duration: Duration) -> Latency {
+        Latency::Duration(duration)
+    }
+}
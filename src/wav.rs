@@ -0,0 +1,546 @@
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::result;
+
+use {Channel, ChannelLayout, Sample};
+
+// RIFF/WAVE files of 16, 24 or 32 bit integer or 32 bit float samples, read
+// into and written from any `Sample` type, a chunk at a time.
+//
+// The writer leaves the chunk sizes at 0xFFFFFFFF, "up to the end of the
+// file", until `flush` or `finalize` patches them, so that a recording cut
+// off before that still reads back; `repair` patches such a file in place.
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // Not a WAV file, or a broken one.
+    Format(&'static str),
+    // A valid file cult cannot read, e.g. 8 bit or compressed.
+    Unsupported(&'static str),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Format(what) => write!(f, "invalid WAV file: {}", what),
+            Error::Unsupported(what) => write!(f, "unsupported WAV file: {}", what),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    Pcm16,
+    Pcm24,
+    Pcm32,
+    Float32,
+}
+
+impl Encoding {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            Encoding::Pcm16 => 2,
+            Encoding::Pcm24 => 3,
+            Encoding::Pcm32 | Encoding::Float32 => 4,
+        }
+    }
+
+    pub(crate) fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            Encoding::Pcm16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768_f32,
+            Encoding::Pcm24 => {
+                let v = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                v as f32 / 8388608_f32
+            }
+            Encoding::Pcm32 => {
+                let v = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (v as f64 / 2147483648_f64) as f32
+            }
+            Encoding::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    pub(crate) fn encode(self, v: f32, out: &mut Vec<u8>) {
+        match self {
+            Encoding::Pcm16 => {
+                let v = (v * 32768_f32).round().clamp(-32768_f32, 32767_f32) as i16;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            Encoding::Pcm24 => {
+                let v = (v * 8388608_f32).round().clamp(-8388608_f32, 8388607_f32) as i32;
+                out.extend_from_slice(&v.to_le_bytes()[.. 3]);
+            }
+            Encoding::Pcm32 => {
+                let v = (v as f64 * 2147483648_f64).round()
+                    .clamp(-2147483648_f64, 2147483647_f64) as i32;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            Encoding::Float32 => out.extend_from_slice(&v.to_le_bytes()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Spec {
+    pub rate: u32,
+    pub channels: u16,
+    pub encoding: Encoding,
+    // From the channel mask of WAVE_FORMAT_EXTENSIBLE files, `Undefined` when
+    // there is none or it matches no layout.
+    pub layout: ChannelLayout,
+}
+
+impl Spec {
+    // Mono and stereo get their layout, others stay `Undefined`.
+    pub fn new(rate: u32, channels: u16, encoding: Encoding) -> Spec {
+        let layout = match channels {
+            1 => ChannelLayout::Mono,
+            2 => ChannelLayout::Stereo,
+            _ => ChannelLayout::Undefined,
+        };
+        Spec { rate, channels, encoding, layout }
+    }
+
+    pub fn with_layout(self, layout: ChannelLayout) -> Spec {
+        Spec { layout, ..self }
+    }
+
+    pub fn block_align(&self) -> usize {
+        self.channels as usize * self.encoding.bytes_per_sample()
+    }
+}
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// KSDATAFORMAT_SUBTYPE_* GUIDs, after their first two bytes: the format tag
+const SUBFORMAT_TAIL: [u8; 14] =
+    [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+const UNKNOWN_SIZE: u32 = 0xFFFFFFFF;
+const CHUNK_FRAMES: usize = 4096;
+
+fn speaker_bit(channel: Channel) -> u32 {
+    match channel {
+        Channel::Left => 0x1,
+        Channel::Right => 0x2,
+        Channel::Mono | Channel::Center => 0x4,
+        Channel::LFE => 0x8,
+        Channel::RearLeftSurround => 0x10,
+        Channel::RearRightSurround => 0x20,
+        Channel::RearCenter => 0x100,
+        Channel::LeftSurround => 0x200,
+        Channel::RightSurround => 0x400,
+    }
+}
+
+// Layouts tried in order, so that e.g. 0x3 reads as stereo, not dual mono.
+const LAYOUTS: [ChannelLayout; 16] = [
+    ChannelLayout::Mono, ChannelLayout::Mono_LFE, ChannelLayout::Stereo,
+    ChannelLayout::Stereo_LFE, ChannelLayout::F3, ChannelLayout::F3_LFE, ChannelLayout::F2_1,
+    ChannelLayout::F2_1_LFE, ChannelLayout::F3_1, ChannelLayout::F3_1_LFE, ChannelLayout::F2_2,
+    ChannelLayout::F2_2_LFE, ChannelLayout::F3_2, ChannelLayout::F3_2_LFE,
+    ChannelLayout::F3_R3_LFE, ChannelLayout::F3_4_LFE,
+];
+
+pub fn mask_from_layout(layout: ChannelLayout) -> u32 {
+    layout.channels().iter().fold(0, |mask, &c| mask | speaker_bit(c))
+}
+
+// Files often put the surrounds of 4.0 and 5.1 on the back speakers, which
+// are then taken as side ones.
+pub fn layout_from_mask(mask: u32, channels: u16) -> ChannelLayout {
+    let back = speaker_bit(Channel::RearLeftSurround) | speaker_bit(Channel::RearRightSurround);
+    let side = speaker_bit(Channel::LeftSurround) | speaker_bit(Channel::RightSurround);
+    let moved = if mask & side == 0 && mask & back == back { mask & !back | side } else { mask };
+    for &candidate in [mask, moved].iter() {
+        for &layout in LAYOUTS.iter() {
+            if mask_from_layout(layout) == candidate && layout.channel_count() == channels as u32 {
+                return layout;
+            }
+        }
+    }
+    ChannelLayout::Undefined
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn parse_fmt(fmt: &[u8]) -> Result<Spec> {
+    if fmt.len() < 16 {
+        return Err(Error::Format("fmt chunk too short"));
+    }
+    let mut tag = u16_at(fmt, 0);
+    let channels = u16_at(fmt, 2);
+    let rate = u32_at(fmt, 4);
+    let block_align = u16_at(fmt, 12) as usize;
+    let bits = u16_at(fmt, 14);
+    let mut mask = 0;
+    if tag == FORMAT_EXTENSIBLE {
+        if fmt.len() < 40 {
+            return Err(Error::Format("extensible fmt chunk too short"));
+        }
+        mask = u32_at(fmt, 20);
+        if fmt[26 .. 40] != SUBFORMAT_TAIL {
+            return Err(Error::Unsupported("unknown sub-format"));
+        }
+        tag = u16_at(fmt, 24);
+    }
+    let encoding = match (tag, bits) {
+        (FORMAT_PCM, 16) => Encoding::Pcm16,
+        (FORMAT_PCM, 24) => Encoding::Pcm24,
+        (FORMAT_PCM, 32) => Encoding::Pcm32,
+        (FORMAT_FLOAT, 32) => Encoding::Float32,
+        (FORMAT_PCM, _) | (FORMAT_FLOAT, _) => return Err(Error::Unsupported("sample size")),
+        _ => return Err(Error::Unsupported("compressed format")),
+    };
+    if channels == 0 || rate == 0 {
+        return Err(Error::Format("no channels or rate"));
+    }
+    let spec = Spec::new(rate, channels, encoding);
+    if block_align != spec.block_align() {
+        return Err(Error::Format("block alignment does not match the format"));
+    }
+    if mask != 0 {
+        return Ok(spec.with_layout(layout_from_mask(mask, channels)));
+    }
+    Ok(spec)
+}
+
+struct Header {
+    spec: Spec,
+    data_start: u64,
+    data_len: u64,
+    // offset of the data chunk's size field
+    data_size_at: u64,
+    // the data chunk's size was known and fits in the file
+    sized: bool,
+}
+
+// Reads up to the start of the data chunk. Data of unknown or overlong size
+// runs to the end of the file, in whole frames.
+fn parse_header<R: Read + Seek>(reader: &mut R) -> Result<Header> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff).map_err(|_| Error::Format("too short"))?;
+    if &riff[0 .. 4] != b"RIFF" || &riff[8 .. 12] != b"WAVE" {
+        return Err(Error::Format("not a RIFF/WAVE file"));
+    }
+    let mut spec = None;
+    let mut at = 12;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk).map_err(|_| Error::Format("no data chunk"))?;
+        let size = u32_at(&chunk, 4);
+        at += 8;
+        match &chunk[0 .. 4] {
+            b"fmt " => {
+                if size > 1024 {
+                    return Err(Error::Format("fmt chunk too long"));
+                }
+                let mut fmt = vec![0u8; size as usize];
+                reader.read_exact(&mut fmt).map_err(|_| Error::Format("fmt chunk cut off"))?;
+                spec = Some(parse_fmt(&fmt)?);
+                if size % 2 == 1 {
+                    reader.seek(SeekFrom::Current(1))?;
+                }
+            }
+            b"data" => {
+                let spec = spec.ok_or(Error::Format("data before fmt chunk"))?;
+                let available = file_len.saturating_sub(at);
+                let declared = if size == UNKNOWN_SIZE { available } else { size as u64 };
+                let align = spec.block_align() as u64;
+                let data_len = declared.min(available) / align * align;
+                let sized = size != UNKNOWN_SIZE && declared <= available;
+                return Ok(Header { spec, data_start: at, data_len, data_size_at: at - 4, sized });
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(size as i64 + (size % 2) as i64))?;
+            }
+        }
+        at += size as u64 + (size % 2) as u64;
+    }
+}
+
+pub struct WavReader<R: Read + Seek> {
+    reader: R,
+    spec: Spec,
+    data_start: u64,
+    frames: u64,
+    position: u64,
+    bytes: Vec<u8>,
+}
+
+impl WavReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<WavReader<BufReader<File>>> {
+        WavReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    pub fn new(mut reader: R) -> Result<WavReader<R>> {
+        let header = parse_header(&mut reader)?;
+        Ok(WavReader {
+            reader,
+            spec: header.spec,
+            data_start: header.data_start,
+            frames: header.data_len / header.spec.block_align() as u64,
+            position: 0,
+            bytes: Vec::new(),
+        })
+    }
+
+    pub fn spec(&self) -> Spec {
+        self.spec
+    }
+
+    // In the whole file.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // Reads interleaved frames into `out`, returns how many, 0 at the end.
+    pub fn read<T: Sample>(&mut self, out: &mut [T]) -> Result<usize> {
+        let ch = self.spec.channels as usize;
+        let bytes_per_sample = self.spec.encoding.bytes_per_sample();
+        let wanted = ((out.len() / ch) as u64).min(self.frames - self.position) as usize;
+        let mut done = 0;
+        while done < wanted {
+            let frames = (wanted - done).min(CHUNK_FRAMES);
+            self.bytes.resize(frames * self.spec.block_align(), 0);
+            self.reader.read_exact(&mut self.bytes)?;
+            let dst = &mut out[done * ch .. (done + frames) * ch];
+            for (o, b) in dst.iter_mut().zip(self.bytes.chunks(bytes_per_sample)) {
+                *o = T::from_f32(self.spec.encoding.decode(b));
+            }
+            done += frames;
+            self.position += frames as u64;
+        }
+        Ok(done)
+    }
+
+    // Reads the rest of the file.
+    pub fn read_to_end<T: Sample>(&mut self) -> Result<Vec<T>> {
+        let samples = (self.frames - self.position) as usize * self.spec.channels as usize;
+        let mut out = vec![T::default(); samples];
+        let read = self.read(&mut out)?;
+        out.truncate(read * self.spec.channels as usize);
+        Ok(out)
+    }
+
+    // To `frame`, or the end.
+    pub fn seek(&mut self, frame: u64) -> Result<()> {
+        let frame = frame.min(self.frames);
+        let offset = self.data_start + frame * self.spec.block_align() as u64;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.position = frame;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+pub struct WavWriter<W: Write + Seek> {
+    writer: Option<W>,
+    spec: Spec,
+    header_len: u64,
+    data_size_at: u64,
+    frames: u64,
+    bytes: Vec<u8>,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, spec: Spec) -> Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(path)?), spec)
+    }
+}
+
+fn fmt_chunk(spec: &Spec) -> Vec<u8> {
+    let bits = spec.encoding.bytes_per_sample() as u16 * 8;
+    let tag = match spec.encoding {
+        Encoding::Float32 => FORMAT_FLOAT,
+        _ => FORMAT_PCM,
+    };
+    let mask = if spec.layout.channel_count() == spec.channels as u32 {
+        mask_from_layout(spec.layout)
+    } else {
+        0
+    };
+    // WAVE_FORMAT_EXTENSIBLE is required past two channels or 16 bits
+    let extensible = spec.channels > 2 || (tag == FORMAT_PCM && bits > 16) ||
+                     (mask != 0 && spec.layout != ChannelLayout::Mono &&
+                      spec.layout != ChannelLayout::Stereo);
+    let mut fmt = Vec::with_capacity(40);
+    fmt.extend_from_slice(&(if extensible { FORMAT_EXTENSIBLE } else { tag }).to_le_bytes());
+    fmt.extend_from_slice(&spec.channels.to_le_bytes());
+    fmt.extend_from_slice(&spec.rate.to_le_bytes());
+    fmt.extend_from_slice(&(spec.rate * spec.block_align() as u32).to_le_bytes());
+    fmt.extend_from_slice(&(spec.block_align() as u16).to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+    if extensible {
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt.extend_from_slice(&mask.to_le_bytes());
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&SUBFORMAT_TAIL);
+    } else if tag != FORMAT_PCM {
+        fmt.extend_from_slice(&0u16.to_le_bytes());
+    }
+    fmt
+}
+
+// Points the RIFF and data chunk sizes at `data_len` bytes of data, plus the
+// padding byte of an odd sized chunk if `padded`.
+fn patch_sizes<W: Write + Seek>(writer: &mut W, data_size_at: u64, data_len: u64, padded: bool)
+        -> io::Result<()> {
+    let riff_len = data_size_at + 4 + data_len + if padded { data_len % 2 } else { 0 } - 8;
+    writer.seek(SeekFrom::Start(4))?;
+    writer.write_all(&(riff_len.min(UNKNOWN_SIZE as u64 - 1) as u32).to_le_bytes())?;
+    writer.seek(SeekFrom::Start(data_size_at))?;
+    writer.write_all(&(data_len.min(UNKNOWN_SIZE as u64 - 1) as u32).to_le_bytes())?;
+    Ok(())
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, spec: Spec) -> Result<WavWriter<W>> {
+        if spec.channels == 0 || spec.rate == 0 {
+            return Err(Error::Format("no channels or rate"));
+        }
+        let fmt = fmt_chunk(&spec);
+        let mut header = Vec::with_capacity(20 + fmt.len() + 8);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        header.extend_from_slice(&fmt);
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(WavWriter {
+            writer: Some(writer),
+            spec,
+            header_len: header.len() as u64,
+            data_size_at: header.len() as u64 - 4,
+            frames: 0,
+            bytes: Vec::new(),
+        })
+    }
+
+    pub fn spec(&self) -> Spec {
+        self.spec
+    }
+
+    // Written so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Writes interleaved frames; a trailing partial frame is an error.
+    pub fn write<T: Sample>(&mut self, samples: &[T]) -> Result<()> {
+        let ch = self.spec.channels as usize;
+        if !samples.len().is_multiple_of(ch) {
+            return Err(Error::Format("partial frame"));
+        }
+        let writer = self.writer.as_mut().unwrap();
+        for chunk in samples.chunks(CHUNK_FRAMES * ch) {
+            self.bytes.clear();
+            for &s in chunk {
+                self.spec.encoding.encode(s.to_f32(), &mut self.bytes);
+            }
+            writer.write_all(&self.bytes)?;
+            self.frames += (chunk.len() / ch) as u64;
+        }
+        Ok(())
+    }
+
+    fn data_len(&self) -> u64 {
+        self.frames * self.spec.block_align() as u64
+    }
+
+    // Makes what was written so far a valid file, e.g. periodically while
+    // recording.
+    pub fn flush(&mut self) -> Result<()> {
+        let (data_size_at, data_len) = (self.data_size_at, self.data_len());
+        let writer = self.writer.as_mut().unwrap();
+        patch_sizes(writer, data_size_at, data_len, false)?;
+        writer.seek(SeekFrom::Start(self.header_len + data_len))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Pads and patches the file, and gives the underlying writer back.
+    pub fn finalize(mut self) -> Result<W> {
+        let (data_size_at, data_len) = (self.data_size_at, self.data_len());
+        let mut writer = self.writer.take().unwrap();
+        writer.seek(SeekFrom::Start(self.header_len + data_len))?;
+        if data_len % 2 == 1 {
+            writer.write_all(&[0])?;
+        }
+        patch_sizes(&mut writer, data_size_at, data_len, true)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            let _ = patch_sizes(&mut writer, self.data_size_at, self.data_len(), false);
+            let _ = writer.flush();
+        }
+    }
+}
+
+// Patches the sizes of a file whose writer never got to, e.g. after a crash,
+// dropping a trailing partial frame. Returns the frames it holds. Files with
+// a valid data chunk are left alone.
+pub fn repair<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let header = parse_header(&mut file)?;
+    let frames = header.data_len / header.spec.block_align() as u64;
+    if header.sized {
+        return Ok(frames);
+    }
+    file.set_len(header.data_start + header.data_len)?;
+    patch_sizes(&mut file, header.data_size_at, header.data_len, false)?;
+    if header.data_len % 2 == 1 {
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&[0])?;
+        patch_sizes(&mut file, header.data_size_at, header.data_len, true)?;
+    }
+    file.sync_all()?;
+    Ok(frames)
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "async")]
+
+extern crate cult;
+extern crate futures_core;
+extern crate futures_sink;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+use cult::async_io::{input_queue, output_queue, state_stream};
+use cult::{State, StateCallback};
+use futures_core::Stream;
+use futures_sink::Sink;
+
+// Counts its wakeups.
+struct Counter(AtomicUsize);
+
+impl Wake for Counter {
+  fn wake(self: Arc<Self>) {
+    self.0.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+fn waker() -> (Arc<Counter>, Waker) {
+  let counter = Arc::new(Counter(AtomicUsize::new(0)));
+  (counter.clone(), Waker::from(counter))
+}
+
+fn ignore_states() -> StateCallback {
+  Box::new(|_| {})
+}
+
+#[test]
+fn input_wakes_and_yields_batches() {
+  let (mut data_cb, _, mut queue) = input_queue::<f32>(2, 8, ignore_states()).unwrap();
+  let (wakes, waker) = waker();
+  let mut cx = Context::from_waker(&waker);
+
+  assert!(Pin::new(&mut queue).poll_next(&mut cx).is_pending());
+  assert_eq!(data_cb(&[1.0, 2.0, 3.0, 4.0], &mut []), 2);
+  assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+  match Pin::new(&mut queue).poll_next(&mut cx) {
+    Poll::Ready(Some(batch)) => assert_eq!(batch, vec![1.0, 2.0, 3.0, 4.0]),
+    other => panic!("{:?}", other),
+  }
+  assert!(Pin::new(&mut queue).poll_next(&mut cx).is_pending());
+}
+
+#[test]
+fn input_pauses_while_stopped_and_ends_on_error() {
+  let (mut data_cb, mut state_cb, mut queue) = input_queue::<f32>(1, 8, ignore_states()).unwrap();
+  let (_, waker) = waker();
+  let mut cx = Context::from_waker(&waker);
+
+  state_cb(State::Started);
+  state_cb(State::Stopped);
+  assert!(Pin::new(&mut queue).poll_next(&mut cx).is_pending());
+  state_cb(State::Started);
+  data_cb(&[0.5], &mut []);
+  assert_eq!(Pin::new(&mut queue).poll_next(&mut cx), Poll::Ready(Some(vec![0.5])));
+
+  // what was captured before the error is still yielded, then nothing
+  data_cb(&[0.25], &mut []);
+  state_cb(State::Error);
+  assert_eq!(Pin::new(&mut queue).poll_next(&mut cx), Poll::Ready(Some(vec![0.25])));
+  assert_eq!(Pin::new(&mut queue).poll_next(&mut cx), Poll::Ready(None));
+  state_cb(State::Started);
+  data_cb(&[0.125], &mut []);
+  assert_eq!(Pin::new(&mut queue).poll_next(&mut cx), Poll::Ready(None));
+}
+
+#[test]
+fn input_counts_overruns() {
+  let (mut data_cb, _, mut queue) = input_queue::<i16>(2, 3, ignore_states()).unwrap();
+  let (_, waker) = waker();
+  let mut cx = Context::from_waker(&waker);
+
+  data_cb(&[1, 1, 2, 2], &mut []);
+  data_cb(&[3, 3, 4, 4], &mut []);
+  assert_eq!((queue.overruns(), queue.dropped_frames()), (1, 1));
+  assert_eq!(Pin::new(&mut queue).poll_next(&mut cx),
+             Poll::Ready(Some(vec![1, 1, 2, 2, 3, 3])));
+}
+
+#[test]
+fn output_applies_backpressure() {
+  let (mut data_cb, _, mut queue) = output_queue::<f32>(2, 2, ignore_states()).unwrap();
+  let (wakes, waker) = waker();
+  let mut cx = Context::from_waker(&waker);
+
+  // whole frames only, as many as fit
+  match queue.poll_write(&mut cx, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]) {
+    Poll::Ready(Ok(4)) => (),
+    other => panic!("{:?}", other),
+  }
+  assert!(queue.poll_write(&mut cx, &[5.0, 6.0]).is_pending());
+
+  let mut out = [9.0; 6];
+  assert_eq!(data_cb(&[], &mut out), 3);
+  assert_eq!(out, [1.0, 2.0, 3.0, 4.0, 0.0, 0.0]);
+  assert!(wakes.0.load(Ordering::SeqCst) >= 1);
+
+  let buf = [5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+  let mut write = queue.write(&buf);
+  assert!(Pin::new(&mut write).poll(&mut cx).is_pending());
+  data_cb(&[], &mut out);
+  assert!(matches!(Pin::new(&mut write).poll(&mut cx), Poll::Ready(Ok(()))));
+  data_cb(&[], &mut out);
+  assert_eq!(out, [9.0, 10.0, 0.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn output_flushes_and_drains() {
+  let (states, mut found) = state_stream(4);
+  let (mut data_cb, mut state_cb, mut queue) = output_queue::<f32>(1, 4, states).unwrap();
+  let (_, waker) = waker();
+  let mut cx = Context::from_waker(&waker);
+
+  state_cb(State::Started);
+  Pin::new(&mut queue).start_send(vec![1.0, 2.0]).unwrap();
+  assert!(Pin::new(&mut queue).poll_flush(&mut cx).is_pending());
+  // flushing does not wait on a stopped stream, and waits again once restarted
+  state_cb(State::Stopped);
+  assert!(Pin::new(&mut queue).poll_flush(&mut cx).is_ready());
+  state_cb(State::Started);
+  assert!(Pin::new(&mut queue).poll_flush(&mut cx).is_pending());
+  let mut out = [0.0; 4];
+  assert_eq!(data_cb(&[], &mut out), 4);
+  assert!(Pin::new(&mut queue).poll_flush(&mut cx).is_ready());
+
+  // closing makes the callback return short once empty
+  Pin::new(&mut queue).start_send(vec![3.0]).unwrap();
+  assert!(Pin::new(&mut queue).poll_close(&mut cx).is_pending());
+  assert_eq!(data_cb(&[], &mut out), 1);
+  assert_eq!(out[0], 3.0);
+
+  let mut states = Vec::new();
+  while let Poll::Ready(Some(state)) = Pin::new(&mut found).poll_next(&mut cx) {
+    states.push(format!("{:?}", state));
+  }
+  assert_eq!(states, ["Started", "Stopped", "Started"]);
+}
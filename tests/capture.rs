@@ -0,0 +1,69 @@
+extern crate cult;
+
+use std::thread;
+use std::time::Duration;
+
+use cult::capture::input_reader;
+use cult::State;
+
+#[test]
+fn overruns_drop_the_newest_frames() {
+  let (mut reader, mut data_cb, _) = input_reader::<i16>(2, 4, None);
+  assert_eq!(data_cb(&[1, 2, 3, 4, 5, 6], &mut []), 3);
+  assert_eq!((reader.overruns(), reader.dropped_frames()), (0, 0));
+  // one frame of room left
+  assert_eq!(data_cb(&[7, 8, 9, 10, 11, 12], &mut []), 3);
+  assert_eq!((reader.overruns(), reader.dropped_frames()), (1, 2));
+  assert_eq!(reader.available_frames(), 4);
+
+  // whole frames only, in the order they were captured
+  let mut buf = [0_i16; 5];
+  assert_eq!(reader.try_read(&mut buf), 4);
+  assert_eq!(buf[.. 4], [1, 2, 3, 4]);
+  let mut buf = [0_i16; 8];
+  assert_eq!(reader.try_read(&mut buf), 4);
+  assert_eq!(buf[.. 4], [5, 6, 7, 8]);
+  assert_eq!(reader.try_read(&mut buf), 0);
+
+  data_cb(&[13, 14], &mut []);
+  assert_eq!(reader.try_read(&mut buf), 2);
+  assert_eq!(buf[.. 2], [13, 14]);
+  assert_eq!((reader.overruns(), reader.dropped_frames()), (1, 2));
+}
+
+#[test]
+fn reads_block_until_filled_or_stopped() {
+  let (mut reader, mut data_cb, mut state_cb) = input_reader::<f32>(1, 64, None);
+  // not running, what is there comes back at once
+  data_cb(&[1.0], &mut []);
+  let mut buf = [0_f32; 4];
+  assert_eq!(reader.read(&mut buf), 1);
+
+  state_cb(State::Started);
+  let reading = thread::spawn(move || {
+    let mut buf = [0_f32; 6];
+    let read = reader.read(&mut buf);
+    (reader, read, buf)
+  });
+  for period in [[2.0, 3.0], [4.0, 5.0], [6.0, 7.0]].iter() {
+    thread::sleep(Duration::from_millis(20));
+    data_cb(period, &mut []);
+  }
+  let (mut reader, read, buf) = reading.join().unwrap();
+  assert_eq!(read, 6);
+  assert_eq!(buf, [2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+
+  // stopping hands over what arrived so far
+  let reading = thread::spawn(move || {
+    let mut buf = [0_f32; 6];
+    let read = reader.read(&mut buf);
+    (read, buf)
+  });
+  thread::sleep(Duration::from_millis(20));
+  data_cb(&[8.0], &mut []);
+  thread::sleep(Duration::from_millis(20));
+  state_cb(State::Stopped);
+  let (read, buf) = reading.join().unwrap();
+  assert_eq!(read, 1);
+  assert_eq!(buf[0], 8.0);
+}
@@ -0,0 +1,60 @@
+extern crate cult;
+
+use cult::ChannelLayout;
+use cult::channel_mixer::{ChannelMixer, Lfe, MixOptions};
+
+const H: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+fn close(a: f32, b: f32) -> bool {
+  (a - b).abs() < 1e-6
+}
+
+#[test]
+fn stereo_to_mono_and_back() {
+  let down = ChannelMixer::new(ChannelLayout::Stereo, ChannelLayout::Mono,
+                               MixOptions::default()).unwrap();
+  assert_eq!(down.matrix().len(), 2);
+  assert!(down.matrix().iter().all(|&c| close(c, H)));
+
+  let up = ChannelMixer::new(ChannelLayout::Mono, ChannelLayout::Stereo,
+                             MixOptions::default()).unwrap();
+  let mut out = [0_f32; 4];
+  assert_eq!(up.mix(&[0.5_f32, -0.5], &mut out), 2);
+  assert!(close(out[0], 0.5 * H) && close(out[1], 0.5 * H));
+  assert!(close(out[2], -0.5 * H) && close(out[3], -0.5 * H));
+}
+
+#[test]
+fn surround_downmix() {
+  let m = ChannelMixer::new(ChannelLayout::F3_2_LFE, ChannelLayout::Stereo,
+                            MixOptions::default()).unwrap();
+  // L R C LFE LS RS
+  assert_eq!(&m.matrix()[.. 6], &[1.0, 0.0, H, 0.0, H, 0.0]);
+  assert_eq!(&m.matrix()[6 ..], &[0.0, 1.0, H, 0.0, 0.0, H]);
+
+  let with_lfe = ChannelMixer::new(ChannelLayout::F3_2_LFE, ChannelLayout::Stereo,
+                                   MixOptions { lfe: Lfe::MixToMains(1.0), normalize: true })
+    .unwrap();
+  assert!(with_lfe.coefficient(0, 3) > 0.0);
+  let row: f32 = with_lfe.matrix()[.. 6].iter().sum();
+  assert!(close(row, 1.0));
+
+  let mut out = [0_i16; 2];
+  m.mix(&[16384_i16, 0, 0, 32767, 0, 0], &mut out);
+  assert_eq!(out, [16384, 0]);
+}
+
+#[test]
+fn identical_layouts_are_identity() {
+  for layout in [ChannelLayout::Mono, ChannelLayout::Stereo, ChannelLayout::F3_4_LFE].iter() {
+    let m = ChannelMixer::new(*layout, *layout, MixOptions::default()).unwrap();
+    let n = layout.channel_count() as usize;
+    for o in 0 .. n {
+      for i in 0 .. n {
+        assert_eq!(m.coefficient(o, i), if o == i { 1.0 } else { 0.0 });
+      }
+    }
+  }
+  assert!(ChannelMixer::new(ChannelLayout::Undefined, ChannelLayout::Mono,
+                            MixOptions::default()).is_err());
+}
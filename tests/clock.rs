@@ -0,0 +1,44 @@
+extern crate cult;
+
+use cult::clock::ClockEstimator;
+use std::time::{Duration, Instant};
+
+#[test]
+fn interpolates_between_callbacks() {
+  let t0 = Instant::now();
+  let ms = |n: u64| t0 + Duration::from_millis(n);
+  let mut clock = ClockEstimator::new(48000);
+
+  // 10ms callbacks, 480 frames of latency
+  clock.observe(960, 480, ms(0));
+  assert_eq!(clock.now_playing_frame(ms(0)), 480);
+  // the position has not moved yet, time has
+  clock.observe(960, 480, ms(5));
+  assert_eq!(clock.now_playing_frame(ms(5)), 720);
+  // seen late, the new position is behind the extrapolation: no going back
+  clock.observe(1440, 480, ms(12));
+  assert_eq!(clock.now_playing_frame(ms(12)), 960);
+  assert_eq!(clock.now_playing_frame(ms(13)), 1008);
+
+  assert_eq!(clock.frame_to_instant(1440), Some(ms(22)));
+  assert_eq!(clock.instant_to_frame(ms(32)), Some(1920));
+  // a stopped stream does not run away
+  assert_eq!(clock.now_playing_frame(ms(1000)), 960 + 4800);
+}
+
+#[test]
+fn position_jumps_are_absorbed() {
+  let t0 = Instant::now();
+  let ms = |n: u64| t0 + Duration::from_millis(n);
+  let mut clock = ClockEstimator::new(1000);
+
+  clock.observe(5000, 0, ms(0));
+  clock.observe(5010, 0, ms(10));
+  // a new device starts counting from 0
+  clock.observe(0, 0, ms(20));
+  assert_eq!(clock.jumps(), 1);
+  assert_eq!(clock.now_playing_frame(ms(20)), 5020);
+  clock.observe(10, 0, ms(30));
+  assert_eq!(clock.now_playing_frame(ms(30)), 5030);
+  assert_eq!(clock.offset(), 5020);
+}
@@ -0,0 +1,392 @@
+#![cfg(feature = "codecs")]
+
+extern crate cult;
+
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use cult::{ChannelLayout, Context};
+use cult::codecs::CodecDecoder;
+use cult::player::{open_decoder, playback, Converter, Decoder, Options, Player};
+use cult::resampler::Quality;
+
+const BLOCK: usize = 256;
+
+fn temp_path(name: &str) -> PathBuf {
+  std::env::temp_dir().join(format!("cult-codecs-{}-{}", name, std::process::id()))
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+  let mut crc = 0_u8;
+  for &b in bytes {
+    crc ^= b;
+    for _ in 0 .. 8 {
+      crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+    }
+  }
+  crc
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+  let mut crc = 0_u16;
+  for &b in bytes {
+    crc ^= (b as u16) << 8;
+    for _ in 0 .. 8 {
+      crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+    }
+  }
+  crc
+}
+
+// A mono 16-bit FLAC file of verbatim 256 frame blocks.
+fn flac(rate: u32, samples: &[i16]) -> Vec<u8> {
+  assert!(samples.len().is_multiple_of(BLOCK) && samples.len() / BLOCK < 128);
+  let mut out = b"fLaC".to_vec();
+  // last metadata block, STREAMINFO
+  out.extend_from_slice(&[0x80, 0, 0, 34]);
+  out.extend_from_slice(&[(BLOCK >> 8) as u8, BLOCK as u8, (BLOCK >> 8) as u8, BLOCK as u8]);
+  out.extend_from_slice(&[0; 6]);
+  let info = (rate as u64) << 44 | 15 << 36 | samples.len() as u64;
+  out.extend((0 .. 8).rev().map(|i| (info >> (8 * i)) as u8));
+  out.extend_from_slice(&[0; 16]);
+
+  for (number, block) in samples.chunks(BLOCK).enumerate() {
+    // fixed blocking, 256 frames, rate from STREAMINFO, mono, 16 bits
+    let mut frame = vec![0xff, 0xf8, 0x80, 0x08, number as u8];
+    let crc = crc8(&frame);
+    frame.push(crc);
+    // verbatim subframe
+    frame.push(0x02);
+    for s in block {
+      frame.extend_from_slice(&s.to_be_bytes());
+    }
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    out.extend(frame);
+  }
+  out
+}
+
+#[test]
+fn decodes_and_seeks_flac() {
+  let samples: Vec<i16> = (0 .. 4 * BLOCK).map(|i| (i as i16 - 512) * 16).collect();
+  let path = temp_path("ramp.flac");
+  fs::write(&path, flac(22050, &samples)).unwrap();
+
+  let mut decoder = CodecDecoder::open(&path).unwrap();
+  assert_eq!((decoder.rate(), decoder.channels()), (22050, 1));
+  assert_eq!(decoder.frames(), Some(4 * BLOCK as u64));
+  let expected: Vec<f32> = samples.iter().map(|&s| s as f32 / 32768.0).collect();
+  let mut out = vec![0_f32; 4 * BLOCK];
+  assert_eq!(decoder.read(&mut out[.. 300]).unwrap(), 300);
+  assert_eq!(&out[.. 300], &expected[.. 300]);
+
+  decoder.seek(700).unwrap();
+  assert_eq!(decoder.position(), 700);
+  assert_eq!(decoder.read(&mut out).unwrap(), 4 * BLOCK - 700);
+  assert_eq!(&out[.. 4 * BLOCK - 700], &expected[700 ..]);
+  assert_eq!(decoder.read(&mut out).unwrap(), 0);
+
+  // not a WAVE file, so the codecs take over
+  let decoder = open_decoder(&path).unwrap();
+  assert_eq!(decoder.frames(), Some(4 * BLOCK as u64));
+  fs::remove_file(&path).unwrap();
+}
+
+// Appends the `n` low bits of `value`, lowest first for Vorbis, highest
+// first for MP3.
+fn put(bits: &mut Vec<bool>, value: u64, n: u32, lsb_first: bool) {
+  for i in 0 .. n {
+    let shift = if lsb_first { i } else { n - 1 - i };
+    bits.push(value >> shift & 1 != 0);
+  }
+}
+
+fn pack(bits: &[bool], lsb_first: bool) -> Vec<u8> {
+  bits.chunks(8).map(|byte| {
+    byte.iter().enumerate().fold(0_u8, |acc, (i, &bit)| {
+      let shift = if lsb_first { i } else { 7 - i };
+      acc | (bit as u8) << shift
+    })
+  }).collect()
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = 0_u32;
+  for &b in bytes {
+    crc ^= (b as u32) << 24;
+    for _ in 0 .. 8 {
+      crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+    }
+  }
+  crc
+}
+
+// One Ogg page per packet.
+fn ogg_page(packet: &[u8], sequence: u32, granule: u64, flags: u8) -> Vec<u8> {
+  let mut page = b"OggS".to_vec();
+  page.extend_from_slice(&[0, flags]);
+  page.extend_from_slice(&granule.to_le_bytes());
+  page.extend_from_slice(&0x6375_6c74_u32.to_le_bytes());
+  page.extend_from_slice(&sequence.to_le_bytes());
+  page.extend_from_slice(&[0; 4]);
+  let mut lacing = vec![255_u8; packet.len() / 255];
+  lacing.push((packet.len() % 255) as u8);
+  page.push(lacing.len() as u8);
+  page.extend(lacing);
+  page.extend_from_slice(packet);
+  let crc = crc32(&page);
+  page[22 .. 26].copy_from_slice(&crc.to_le_bytes());
+  page
+}
+
+// A mono Ogg Vorbis file of short blocks, each holding the same single
+// frequency line of the MDCT, `line` of 128, near `(line + 0.5) * rate / 256`.
+fn vorbis(rate: u32, packets: usize, line: usize) -> Vec<u8> {
+  let mut ident = b"\x01vorbis".to_vec();
+  ident.extend_from_slice(&[0, 0, 0, 0, 1]);
+  ident.extend_from_slice(&rate.to_le_bytes());
+  ident.extend_from_slice(&[0; 12]);
+  // 256 frame blocks, framing
+  ident.extend_from_slice(&[0x88, 1]);
+
+  let mut comment = b"\x03vorbis".to_vec();
+  comment.extend_from_slice(&4_u32.to_le_bytes());
+  comment.extend_from_slice(b"cult");
+  comment.extend_from_slice(&[0, 0, 0, 0, 1]);
+
+  let mut bits = Vec::new();
+  {
+    let mut field = |value: u64, n: u32| put(&mut bits, value, n, true);
+    // two codebooks of two one bit entries, the second one mapping to 0.0
+    // and 0.25
+    field(1, 8);
+    for lookup in 0 .. 2 {
+      field(0x56_4342, 24);
+      field(1, 16);
+      field(2, 24);
+      field(0, 2);
+      field(0, 10);
+      field(lookup, 4);
+      if lookup == 1 {
+        field(0, 32);
+        field(786 << 21 | 1, 32);
+        field(0, 5);
+        field(0b10, 2);
+      }
+    }
+    // time domain transforms
+    field(0, 6);
+    field(0, 16);
+    // a flat floor 1 over 128 lines
+    field(0, 6);
+    field(1, 16);
+    field(0, 5);
+    field(0, 2);
+    field(7, 4);
+    // a residue 1 of 16 line partitions, class 1 using the second codebook
+    field(0, 6);
+    field(1, 16);
+    field(0, 24);
+    field(128, 24);
+    field(15, 24);
+    field(1, 6);
+    field(0, 8);
+    field(0, 4);
+    field(1, 4);
+    field(1, 8);
+    // a mapping of a single submap, a short block mode, framing
+    field(0, 6);
+    field(0, 16);
+    field(0, 4);
+    field(0, 24);
+    field(0, 6);
+    field(0, 41);
+    field(1, 1);
+  }
+  let mut setup = b"\x05vorbis".to_vec();
+  setup.extend(pack(&bits, true));
+
+  let mut bits = Vec::new();
+  {
+    let mut field = |value: u64, n: u32| put(&mut bits, value, n, true);
+    // audio, floor at 0dB
+    field(0, 1);
+    field(1, 1);
+    field(255, 8);
+    field(255, 8);
+    for partition in 0 .. 8 {
+      let used = partition == line / 16;
+      field(used as u64, 1);
+      if used {
+        field(1 << (line % 16), 16);
+      }
+    }
+  }
+  let audio = pack(&bits, true);
+
+  let mut out = ogg_page(&ident, 0, 0, 2);
+  out.extend(ogg_page(&comment, 1, 0, 0));
+  out.extend(ogg_page(&setup, 2, 0, 0));
+  for i in 0 .. packets {
+    let flags = if i + 1 == packets { 4 } else { 0 };
+    out.extend(ogg_page(&audio, 3 + i as u32, i as u64 * 128, flags));
+  }
+  out
+}
+
+// A mono MPEG-1 layer III file at 32kHz and 64kbps, each granule holding
+// the same single frequency line of 576, near `(line + 0.5) * 16000 / 576`.
+fn mp3(frames: usize, line: usize) -> Vec<u8> {
+  assert!(line % 2 == 1 && line < 64);
+  let pairs = line / 2 + 1;
+  let mut bits = Vec::new();
+  {
+    let mut field = |value: u64, n: u32| put(&mut bits, value, n, false);
+    field(0xfffb_58c0, 32);
+    // main_data_begin, private bits, scfsi
+    field(0, 18);
+    for _ in 0 .. 2 {
+      // part2_3_length, big_values, global_gain, scalefac_compress,
+      // window_switching
+      field(pairs as u64 + 3, 12);
+      field(pairs as u64, 9);
+      field(202, 8);
+      field(0, 5);
+      // table 1 everywhere, region counts, preflag, scalefac_scale,
+      // count1table
+      field(1, 5);
+      field(1, 5);
+      field(1, 5);
+      field(15, 4);
+      field(7, 3);
+      field(0, 3);
+    }
+    for _ in 0 .. 2 {
+      // (0, 0) pairs, then (0, 1) and a positive sign
+      for _ in 0 .. pairs - 1 {
+        field(1, 1);
+      }
+      field(0b0010, 4);
+    }
+  }
+  let mut frame = pack(&bits, false);
+  frame.resize(288, 0);
+  frame.repeat(frames)
+}
+
+// The strongest of a few frequencies in `samples`, by correlation.
+fn strongest(samples: &[f32], rate: u32, candidates: &[f32]) -> f32 {
+  let power = |f: f32| {
+    let w = 2.0 * std::f32::consts::PI * f / rate as f32;
+    let (mut re, mut im) = (0.0, 0.0);
+    for (i, &s) in samples.iter().enumerate() {
+      re += s * (w * i as f32).cos();
+      im += s * (w * i as f32).sin();
+    }
+    re * re + im * im
+  };
+  candidates.iter().cloned().fold(0.0, |best, f| if power(f) > power(best) { f } else { best })
+}
+
+fn read_all(decoder: &mut dyn Decoder) -> Vec<f32> {
+  let channels = decoder.channels() as usize;
+  let mut out = Vec::new();
+  let mut chunk = vec![0_f32; 1024 * channels];
+  loop {
+    let read = decoder.read(&mut chunk).unwrap();
+    if read == 0 {
+      return out;
+    }
+    out.extend_from_slice(&chunk[.. read * channels]);
+  }
+}
+
+fn peak(samples: &[f32]) -> f32 {
+  samples.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+}
+
+const CANDIDATES: [f32; 4] = [500.0, 1000.0, 1500.0, 2000.0];
+
+fn fixtures() -> (PathBuf, PathBuf) {
+  let ogg = temp_path("line.ogg");
+  fs::write(&ogg, vorbis(22050, 101, 11)).unwrap();
+  let mp3_path = temp_path("line.mp3");
+  fs::write(&mp3_path, mp3(20, 35)).unwrap();
+  (ogg, mp3_path)
+}
+
+#[test]
+fn decodes_and_seeks_vorbis_and_mp3() {
+  let (ogg, mp3) = fixtures();
+  // 100 blocks after the first, 20 frames of 1152
+  for &(ref path, rate, frames) in &[(&ogg, 22050, 12_800), (&mp3, 32000, 23_040)] {
+    let mut decoder = open_decoder(path).unwrap();
+    assert_eq!((decoder.rate(), decoder.channels()), (rate, 1));
+    assert_eq!(decoder.frames(), Some(frames));
+    let samples = read_all(&mut *decoder);
+    assert_eq!(samples.len() as u64, frames);
+    let middle = &samples[2000 .. 10_000];
+    assert!((peak(middle) - 0.25).abs() < 0.01, "{}", peak(middle));
+    assert_eq!(strongest(middle, rate, &CANDIDATES), 1000.0);
+
+    decoder.seek(frames / 2).unwrap();
+    assert_eq!(decoder.position(), frames / 2);
+    assert_eq!(read_all(&mut *decoder).len() as u64, frames - frames / 2);
+    // past the end is the end of the stream
+    decoder.seek(2 * frames).unwrap();
+    assert_eq!(read_all(&mut *decoder).len(), 0);
+  }
+  fs::remove_file(&ogg).unwrap();
+  fs::remove_file(&mp3).unwrap();
+}
+
+#[test]
+fn converts_vorbis_and_mp3() {
+  let (ogg, mp3) = fixtures();
+  for &(ref path, frames) in &[(&ogg, 27_864), (&mp3, 34_560)] {
+    let decoder = open_decoder(path).unwrap();
+    let mut converter =
+      Converter::new(decoder, 48000, 2, ChannelLayout::Stereo, Quality::Sinc).unwrap();
+    let samples = read_all(&mut converter);
+    assert_eq!(converter.frames(), Some(frames));
+    assert!((samples.len() as i64 / 2 - frames as i64).abs() <= 1, "{}", samples.len());
+    let left: Vec<f32> = samples.chunks(2).map(|frame| {
+      assert_eq!(frame[0], frame[1]);
+      frame[0]
+    }).collect();
+    assert_eq!(strongest(&left[5000 .. 20_000], 48000, &CANDIDATES), 1000.0);
+  }
+
+  // the MP3 queued after the Vorbis file plays at its rate
+  let (mut cb, _, player) = playback::<f32>(open_decoder(&ogg).unwrap(),
+                                            &Options::default()).unwrap();
+  player.queue(open_decoder(&mp3).unwrap()).unwrap();
+  let mut played = Vec::new();
+  let mut out = vec![0_f32; 1024];
+  loop {
+    thread::sleep(Duration::from_millis(5));
+    let n = cb(&[], &mut out);
+    played.extend_from_slice(&out[.. n]);
+    if n < out.len() {
+      break;
+    }
+  }
+  assert_eq!((player.rate(), player.channels(), player.underruns()), (22050, 1, 0));
+  assert!((played.len() as i64 - (12_800 + 15_876)).abs() <= 1, "{}", played.len());
+  assert_eq!(strongest(&played[15_000 .. 25_000], 22050, &CANDIDATES), 1000.0);
+
+  // a player opens at the file's rate and channels, where there is a device
+  if let Ok(ctx) = Context::new("cult codecs", None) {
+    if let Ok(player) = Player::<f32>::open(&ctx, None, &ogg) {
+      let playback = player.playback();
+      assert_eq!((playback.rate(), playback.channels()), (22050, 1));
+      player.queue(&mp3).unwrap();
+    }
+  }
+  fs::remove_file(&ogg).unwrap();
+  fs::remove_file(&mp3).unwrap();
+}
+
@@ -0,0 +1,69 @@
+extern crate cult;
+
+use cult::command::command_channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq)]
+enum Command {
+  Gain(f32),
+  Stop,
+}
+
+#[test]
+fn commands_are_bounded_and_ordered() {
+  let (mut tx, mut rx) = command_channel(3);
+  assert_eq!(tx.capacity(), 3);
+  assert_eq!(rx.try_recv(), None);
+
+  assert!(tx.send(Command::Gain(0.5)).is_ok());
+  assert!(tx.send(Command::Gain(0.25)).is_ok());
+  assert!(tx.send(Command::Stop).is_ok());
+  // full: the command comes back
+  assert_eq!(tx.send(Command::Gain(1.0)), Err(Command::Gain(1.0)));
+  assert_eq!((tx.pending(), rx.pending()), (3, 3));
+
+  assert_eq!(rx.try_recv(), Some(Command::Gain(0.5)));
+  assert!(tx.send(Command::Gain(1.0)).is_ok());
+  let rest: Vec<Command> = rx.drain().collect();
+  assert_eq!(rest, [Command::Gain(0.25), Command::Stop, Command::Gain(1.0)]);
+  assert_eq!(rx.pending(), 0);
+}
+
+#[test]
+fn sending_never_waits_for_the_receiver() {
+  let (mut tx, mut rx) = command_channel::<u32>(16);
+  // nobody drains while the sender floods
+  let sender = thread::spawn(move || {
+    let start = Instant::now();
+    let sent = (0 .. 100_000).filter(|&i| tx.send(i).is_ok()).count();
+    (sent, start.elapsed())
+  });
+  let (sent, elapsed) = sender.join().unwrap();
+  assert_eq!(sent, 16);
+  assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+  assert_eq!(rx.drain().collect::<Vec<_>>(), (0 .. 16).collect::<Vec<_>>());
+}
+
+#[test]
+fn commands_across_threads() {
+  let (mut tx, mut rx) = command_channel::<u32>(8);
+  let sender = thread::spawn(move || {
+    let mut next = 0;
+    while next < 100_000 {
+      match tx.send(next) {
+        Ok(()) => next += 1,
+        Err(_) => thread::yield_now(),
+      }
+    }
+  });
+  let mut expected = 0;
+  while expected < 100_000 {
+    for c in rx.drain() {
+      assert_eq!(c, expected);
+      expected += 1;
+    }
+    thread::yield_now();
+  }
+  sender.join().unwrap();
+}
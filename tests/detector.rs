@@ -0,0 +1,170 @@
+extern crate cult;
+
+use std::time::Duration;
+
+use cult::detector::{detecting_callback, detector, Event, Events, Options};
+use cult::generator::{Color, Generator, Noise, Oscillator, Waveform};
+use cult::tones::{Tone, ToneGenerator, ToneSpec};
+
+const RATE: u32 = 8000;
+
+fn ms(ms: u64) -> u64 {
+  ms * RATE as u64 / 1000
+}
+
+fn events(events: &mut Events) -> Vec<Event> {
+  let mut all = Vec::new();
+  while let Some(event) = events.try_recv() {
+    all.push(event);
+  }
+  all
+}
+
+// `tone` plus quiet noise, run through a callback in periods of 160 frames.
+fn detect(mut tone: ToneGenerator, frames: u64, options: &Options) -> Vec<Event> {
+  let (mut cb, mut found) = detecting_callback::<f32>(RATE, 1, options).unwrap();
+  let mut noise = Noise::new(Color::White, RATE, 0.01, 3);
+  let input: Vec<f32> = (0 .. frames).map(|_| tone.next_sample() + noise.next_sample()).collect();
+  for period in input.chunks(160) {
+    assert_eq!(cb(period, &mut []), period.len());
+  }
+  events(&mut found)
+}
+
+// A key pressed for `on` after 100ms of silence, its high group `twist` dB
+// louder.
+fn key(low: f32, high: f32, twist: f32, on: u64) -> Vec<f32> {
+  let mut low = Oscillator::new(Waveform::Sine, RATE, low, 0.2);
+  let mut high = Oscillator::new(Waveform::Sine, RATE, high, 0.2 * 10_f32.powf(twist / 20.0));
+  let mut samples = vec![0_f32; ms(100) as usize];
+  samples.extend((0 .. ms(on)).map(|_| low.next_sample() + high.next_sample()));
+  samples.extend(vec![0_f32; ms(100) as usize]);
+  samples
+}
+
+fn near(frame: u64, expected: u64) -> bool {
+  // within a DTMF block
+  (frame as i64 - expected as i64).abs() <= 102
+}
+
+#[test]
+fn detects_dtmf_keys_in_order() {
+  let (on, off) = (Duration::from_millis(50), Duration::from_millis(50));
+  let keys = ToneGenerator::dtmf("159#*0D", on, off, RATE, 0.5).unwrap();
+  let found = detect(keys, ms(800), &Options::default());
+  assert_eq!(found.len(), 14, "{:?}", found);
+  for (i, pair) in found.chunks(2).enumerate() {
+    let start = ms(100 * i as u64);
+    match (pair[0], pair[1]) {
+      (Event::KeyDown { key, frame }, Event::KeyUp { key: up, frame: end }) => {
+        assert_eq!(key, "159#*0D".chars().nth(i).unwrap());
+        assert_eq!(key, up);
+        assert!(near(frame, start) && near(end, start + ms(50)), "{:?}", pair);
+      }
+      _ => panic!("{:?}", pair),
+    }
+  }
+}
+
+#[test]
+fn applies_twist_energy_and_duration_rules() {
+  let run = |samples: &[f32], channels: u32| {
+    let (mut detector, mut found) = detector(RATE, channels, &Options::default()).unwrap();
+    detector.process(samples);
+    events(&mut found)
+  };
+  let keys = |found: &[Event]| -> String {
+    found.iter().filter_map(|e| match *e {
+      Event::KeyDown { key, .. } => Some(key),
+      _ => None,
+    }).collect()
+  };
+  // '5', forward twist up to 8dB, reverse up to 4dB
+  assert_eq!(keys(&run(&key(770.0, 1336.0, 6.0, 60), 1)), "5");
+  assert_eq!(keys(&run(&key(770.0, 1336.0, 10.0, 60), 1)), "");
+  assert_eq!(keys(&run(&key(770.0, 1336.0, -3.0, 60), 1)), "5");
+  assert_eq!(keys(&run(&key(770.0, 1336.0, -6.0, 60), 1)), "");
+  // 40ms at least
+  assert_eq!(keys(&run(&key(770.0, 1336.0, 0.0, 40), 1)), "5");
+  assert_eq!(keys(&run(&key(770.0, 1336.0, 0.0, 20), 1)), "");
+  // too quiet
+  let quiet: Vec<f32> = key(770.0, 1336.0, 0.0, 60).iter().map(|s| s * 0.05).collect();
+  assert_eq!(keys(&run(&quiet, 1)), "");
+  // buried in noise
+  let mut noise = Noise::new(Color::White, RATE, 0.5, 7);
+  let noisy: Vec<f32> = key(770.0, 1336.0, 0.0, 60).iter()
+    .map(|s| s + noise.next_sample()).collect();
+  assert_eq!(keys(&run(&noisy, 1)), "");
+  // the same on both channels of a stereo input
+  let stereo: Vec<f32> = key(941.0, 1633.0, 0.0, 60).iter().flat_map(|&s| vec![s, s]).collect();
+  assert_eq!(keys(&run(&stereo, 2)), "D");
+}
+
+#[test]
+fn tells_call_progress_tones_apart() {
+  let options = Options { country: Some("GB".to_string()), ..Options::default() };
+  assert!(detector(RATE, 1, &Options { country: Some("XX".to_string()), ..Options::default() })
+          .is_none());
+
+  let busy = ToneGenerator::for_country("GB", Tone::Busy, RATE, 0.5).unwrap();
+  let found = detect(busy, ms(3000), &options);
+  match found[..] {
+    [Event::Tone { tone: Tone::Busy, frame }] => assert!(frame <= ms(100)),
+    _ => panic!("{:?}", found),
+  }
+
+  let congestion = ToneGenerator::for_country("GB", Tone::Congestion, RATE, 0.5).unwrap();
+  let found = detect(congestion, ms(3000), &options);
+  match found[..] {
+    [Event::Tone { tone: Tone::Congestion, .. }] => (),
+    _ => panic!("{:?}", found),
+  }
+
+  // US dial and ringback share 440Hz
+  let options = Options { country: Some("US".to_string()), ..Options::default() };
+  let ringback = ToneGenerator::for_country("US", Tone::Ringback, RATE, 0.5).unwrap();
+  let found = detect(ringback, ms(8000), &options);
+  match found[..] {
+    [Event::Tone { tone: Tone::Ringback, frame }] => assert!(frame <= ms(100)),
+    _ => panic!("{:?}", found),
+  }
+
+  // 3s of dial tone, then silence
+  let mut dial = ToneGenerator::for_country("US", Tone::Dial, RATE, 0.5).unwrap();
+  let (mut detector, mut found) = detector(RATE, 1, &options).unwrap();
+  let tone: Vec<f32> = (0 .. ms(3000)).map(|_| dial.next_sample()).collect();
+  detector.process(&tone);
+  detector.process(&vec![0_f32; ms(1000) as usize]);
+  let found = events(&mut found);
+  match found[..] {
+    [Event::Tone { tone: Tone::Dial, frame: 0 }, Event::ToneEnded { tone: Tone::Dial, frame }] =>
+      assert!((frame as i64 - ms(3000) as i64).abs() <= ms(50) as i64),
+    _ => panic!("{:?}", found),
+  }
+}
+
+#[test]
+fn detects_the_tones_of_every_country() {
+  let kinds = [Tone::Dial, Tone::Ringback, Tone::Busy, Tone::Congestion, Tone::CallWaiting];
+  let mut missed = Vec::new();
+  for country in cult::tones::countries() {
+    let options = Options { country: Some(country.to_string()), ..Options::default() };
+    for &tone in kinds.iter() {
+      // busy and congestion are the same in some countries, and then busy
+      let busy = ToneSpec::lookup(country, Tone::Busy);
+      let expected = if ToneSpec::lookup(country, tone) == busy { Tone::Busy } else { tone };
+      let generator = ToneGenerator::for_country(country, tone, RATE, 0.5).unwrap();
+      let found: Vec<Tone> = detect(generator, ms(30_000), &options).iter()
+        .filter_map(|e| match *e {
+          Event::Tone { tone, .. } => Some(tone),
+          _ => None,
+        }).collect();
+      if found != [expected] {
+        missed.push(format!("{} {:?}: {:?}", country, tone, found));
+      }
+    }
+  }
+  assert!(missed.is_empty(), "{:#?}", missed);
+  assert_eq!(ToneSpec::lookup("AU", Tone::Congestion), ToneSpec::lookup("AU", Tone::Busy));
+  assert_eq!(ToneSpec::lookup("JP", Tone::Congestion), ToneSpec::lookup("JP", Tone::Busy));
+}
@@ -0,0 +1,42 @@
+extern crate cult;
+
+use cult::drift::bridge;
+use cult::resampler::Quality;
+use std::time::{Duration, Instant};
+
+// Writer and reader exchange 480 frame periods at 48kHz on a simulated
+// clock, the writer's clock running `ppm` faster. Returns the estimated
+// drift, the final fill level, and the number of xruns.
+fn simulate(ppm: f64, seconds: usize) -> (f64, usize, usize) {
+  let rate = 48000;
+  let period = 480;
+  let (mut writer, mut reader, monitor) = bridge::<f32>(1, rate, 1440, Quality::Linear);
+  let input = vec![0.25_f32; period];
+  let mut output = vec![0_f32; period];
+  let start = Instant::now();
+  let at = |periods: f64| start + Duration::from_secs_f64(periods * period as f64 / rate as f64);
+  let writer_period = 1.0 / (1.0 + ppm * 1e-6);
+  // the devices are out of phase by a third of a period
+  let (mut t_write, mut t_read) = (0.0, 0.33);
+  for _ in 0..seconds * rate as usize / period {
+    // whichever device's next period comes first
+    while t_write <= t_read {
+      writer.write_at(&input, at(t_write));
+      t_write += writer_period;
+    }
+    reader.read_at(&mut output, at(t_read));
+    t_read += 1.0;
+  }
+  (monitor.ppm(), monitor.fill(), monitor.underruns() + monitor.overruns())
+}
+
+#[test]
+fn drift_is_estimated_and_absorbed() {
+  for &ppm in [150.0, -80.0].iter() {
+    let (estimate, fill, xruns) = simulate(ppm, 300);
+    assert!((estimate - ppm).abs() < 5.0, "{} ppm estimated as {}", ppm, estimate);
+    // within a period of the target
+    assert!((1440 - 480 ..= 1440 + 480).contains(&fill), "fill {}", fill);
+    assert_eq!(xruns, 0);
+  }
+}
@@ -0,0 +1,117 @@
+extern crate cult;
+
+use cult::gc;
+
+// Through the data callback trampoline of an offline stream, counting every
+// heap operation made inside the callback with a test allocator, so that it
+// runs with or without the `rt-check` feature.
+mod trampoline {
+  use cult::command::{command_channel, CommandSender};
+  use cult::gc::{self, Collector};
+  use cult::render::OfflineStream;
+  use cult::{ChannelLayout, DataCallback, StreamParams};
+  use std::alloc::{GlobalAlloc, Layout, System};
+  use std::cell::Cell;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::thread;
+  use std::time::Duration;
+
+  struct CountingAllocator;
+
+  thread_local! {
+    // Set while a callback of `table_stream` runs on this thread.
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+  }
+
+  static HEAP_OPS: AtomicUsize = AtomicUsize::new(0);
+
+  fn count() {
+    // `try_with` because the allocator can run while thread locals are torn
+    // down.
+    if IN_CALLBACK.try_with(|c| c.get()).unwrap_or(false) {
+      HEAP_OPS.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+      count();
+      System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+      count();
+      System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+      count();
+      System.realloc(ptr, layout, size)
+    }
+  }
+
+  #[global_allocator]
+  static ALLOC: CountingAllocator = CountingAllocator;
+
+  // A callback swapping in the tables it is sent, either retiring the old
+  // ones or dropping them in place.
+  fn table_stream(retire: bool)
+      -> (OfflineStream<f32>, CommandSender<Vec<f32>>, Collector<Vec<f32>>) {
+    let (tx, mut rx) = command_channel::<Vec<f32>>(4);
+    let (mut retirer, collector) = gc::channel::<Vec<f32>>(4);
+    let mut table = vec![0_f32; 64];
+    let cb: DataCallback<f32> = Box::new(move |_: &[f32], obuf: &mut [f32]| {
+      IN_CALLBACK.with(|c| c.set(true));
+      if let Some(new_table) = rx.try_recv() {
+        let old = std::mem::replace(&mut table, new_table);
+        if retire {
+          retirer.retire(old).expect("garbage queue full");
+        }
+      }
+      for (o, t) in obuf.iter_mut().zip(table.iter().cycle()) {
+        *o = *t;
+      }
+      IN_CALLBACK.with(|c| c.set(false));
+      obuf.len()
+    });
+    let params = StreamParams::new(48000, 1, ChannelLayout::Mono);
+    (OfflineStream::new(None, Some(params), cb, None).unwrap(), tx, collector)
+  }
+
+  #[test]
+  fn retired_buffers_are_freed_off_the_audio_thread() {
+    let (mut stream, mut tx, collector) = table_stream(true);
+    let collector_thread = collector.spawn(Duration::from_millis(1));
+    let mut out = Vec::with_capacity(128);
+    for i in 0 .. 100 {
+      let _ = tx.send(vec![i as f32; 64]);
+      out.clear();
+      assert_eq!(stream.render_into(&mut out, 128), 128);
+      assert_eq!(out[0], i as f32);
+      thread::sleep(Duration::from_millis(1));
+    }
+    drop(collector_thread);
+    assert_eq!(HEAP_OPS.swap(0, Ordering::Relaxed), 0);
+
+    // dropping them in the callback is caught
+    let (mut stream, mut tx, _) = table_stream(false);
+    for i in 0 .. 10 {
+      let _ = tx.send(vec![i as f32; 64]);
+      out.clear();
+      stream.render_into(&mut out, 128);
+    }
+    assert_eq!(HEAP_OPS.load(Ordering::Relaxed), 10);
+  }
+}
+
+#[test]
+fn collector_drops_everything_retired() {
+  let (mut retirer, mut collector) = gc::channel::<Box<[u8; 32]>>(8);
+  for _ in 0 .. 8 {
+    retirer.retire(Box::new([0; 32])).unwrap();
+  }
+  assert!(retirer.retire(Box::new([0; 32])).is_err());
+  assert_eq!(collector.pending(), 8);
+  assert_eq!(collector.collect(), 8);
+  assert_eq!(retirer.pending(), 0);
+}
@@ -0,0 +1,126 @@
+extern crate cult;
+
+use std::f64::consts::PI;
+
+use cult::generator::{Color, Generator, Noise, Oscillator, Waveform};
+use cult::source::Source;
+
+const RATE: u32 = 48000;
+
+fn take<G: Generator>(generator: &mut G, frames: usize) -> Vec<f32> {
+  (0 .. frames).map(|_| generator.next_sample()).collect()
+}
+
+fn max_step(samples: &[f32]) -> f32 {
+  samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max)
+}
+
+// Power at `frequency`, relative to a full scale sine.
+fn power_at(samples: &[f32], frequency: f64) -> f64 {
+  let w = 2.0 * PI * frequency / RATE as f64;
+  let (mut re, mut im) = (0.0, 0.0);
+  for (n, &s) in samples.iter().enumerate() {
+    re += s as f64 * (w * n as f64).cos();
+    im -= s as f64 * (w * n as f64).sin();
+  }
+  let half = samples.len() as f64 / 2.0;
+  (re * re + im * im) / (half * half)
+}
+
+#[test]
+fn sine_keeps_its_phase() {
+  let mut osc = Oscillator::new(Waveform::Sine, RATE, 440.0, 0.5);
+  let samples = take(&mut osc, 60 * RATE as usize);
+  for (n, &s) in samples.iter().enumerate().step_by(997) {
+    let expected = 0.5 * (2.0 * PI * 440.0 * n as f64 / RATE as f64).sin();
+    assert!((s as f64 - expected).abs() < 1e-4, "{} {} {}", n, s, expected);
+  }
+  assert!(osc.phase() >= 0.0 && osc.phase() < 1.0);
+}
+
+#[test]
+fn changes_do_not_click() {
+  let mut osc = Oscillator::new(Waveform::Sine, RATE, 440.0, 0.0);
+  let mut samples = take(&mut osc, 100);
+  osc.set_amplitude(1.0);
+  samples.extend(take(&mut osc, 1000));
+  osc.set_frequency(880.0);
+  samples.extend(take(&mut osc, 1000));
+  assert_eq!((osc.frequency(), osc.amplitude()), (880.0, 1.0));
+  // at most the slope of a full scale 880Hz sine
+  assert!(max_step(&samples) < (2.0 * PI * 880.0 / RATE as f64) as f32 * 1.01);
+
+  let mut noise = Noise::new(Color::Brown, RATE, 0.0, 1);
+  take(&mut noise, 100);
+  noise.set_amplitude(1.0);
+  assert!(max_step(&take(&mut noise, 1000)) < 0.08);
+}
+
+#[test]
+fn band_limited_waveforms_alias_less() {
+  let f0 = 3100.0;
+  let frames = RATE as usize;
+  let naive: Vec<(Waveform, Vec<f32>)> = vec![
+    (Waveform::Saw, (0 .. frames).map(|n| {
+      let p = (n as f64 * f0 / RATE as f64).fract();
+      (2.0 * p - 1.0) as f32
+    }).collect()),
+    (Waveform::Square, (0 .. frames).map(|n| {
+      let p = (n as f64 * f0 / RATE as f64).fract();
+      if p < 0.5 { 1.0 } else { -1.0 }
+    }).collect()),
+    (Waveform::Triangle, (0 .. frames).map(|n| {
+      let p = (n as f64 * f0 / RATE as f64).fract();
+      (if p < 0.5 { 4.0 * p - 1.0 } else { 3.0 - 4.0 * p }) as f32
+    }).collect()),
+  ];
+  for (waveform, naive) in naive {
+    let mut osc = Oscillator::new(waveform, RATE, f0 as f32, 1.0);
+    let samples = take(&mut osc, frames);
+    // 11th and 13th harmonics, folded back below Nyquist
+    for &alias in &[48000.0 - 11.0 * f0, 13.0 * f0 - 48000.0] {
+      let (band_limited, naive) = (power_at(&samples, alias), power_at(&naive, alias));
+      assert!(band_limited < naive / 4.0, "{:?} {} {} {}", waveform, alias, band_limited, naive);
+    }
+    // the fundamental is untouched, within a dB
+    let fundamental = power_at(&samples, f0) / power_at(&naive, f0);
+    assert!(fundamental > 0.8 && fundamental < 1.25, "{:?} {}", waveform, fundamental);
+  }
+}
+
+#[test]
+fn seeded_noise_colors() {
+  let mut a = Noise::new(Color::White, RATE, 1.0, 42);
+  let mut b = Noise::new(Color::White, RATE, 1.0, 42);
+  let mut c = Noise::new(Color::White, RATE, 1.0, 43);
+  let white = take(&mut a, RATE as usize);
+  assert_eq!(white, take(&mut b, RATE as usize));
+  assert_ne!(white, take(&mut c, RATE as usize));
+
+  // share of the power in sample to sample differences, 2 for white noise
+  let roughness = |s: &[f32]| {
+    let power: f32 = s.iter().map(|x| x * x).sum();
+    let steps: f32 = s.windows(2).map(|w| (w[1] - w[0]) * (w[1] - w[0])).sum();
+    steps / power
+  };
+  let pink = take(&mut Noise::new(Color::Pink, RATE, 1.0, 42), RATE as usize);
+  let brown = take(&mut Noise::new(Color::Brown, RATE, 1.0, 42), RATE as usize);
+  assert!((roughness(&white) - 2.0).abs() < 0.05);
+  assert!(roughness(&pink) < 1.0 && roughness(&brown) < 0.25 * roughness(&pink));
+  for s in &[white, pink, brown] {
+    assert!(s.iter().all(|x| x.abs() <= 1.0));
+  }
+}
+
+#[test]
+fn fills_frames_of_any_sample_type() {
+  let mut osc = Oscillator::new(Waveform::Square, RATE, 100.0, 0.5);
+  let mut out = vec![0_i16; 2 * 64];
+  osc.fill_frames(&mut out, 2);
+  assert!(out.chunks(2).all(|f| f[0] == f[1]));
+  assert_eq!(out[10], 16384);
+
+  let mut noise = Noise::new(Color::Pink, RATE, 1.0, 0);
+  let mut out = vec![0_f32; 3 * 10];
+  assert_eq!(noise.fill(&mut out, 3), 10);
+}
@@ -0,0 +1,134 @@
+extern crate cult;
+
+use std::f64::consts::PI;
+use std::fs;
+
+use cult::loudness::{loudness_meter, measure_file, metered, Loudness};
+use cult::meter::Side;
+use cult::wav::{Encoding, Spec, WavWriter};
+use cult::ChannelLayout;
+
+const RATE: u32 = 48000;
+
+// `seconds` of a 1kHz sine at `dbfs` on the channels of `channels` that are
+// set, continuing from `frame`.
+fn sine(dbfs: f64, seconds: f64, channels: &[bool], frame: &mut usize) -> Vec<f32> {
+  let amplitude = 10_f64.powf(dbfs / 20.0);
+  let frames = (seconds * RATE as f64) as usize;
+  let mut out = Vec::with_capacity(frames * channels.len());
+  for n in *frame .. *frame + frames {
+    let s = (amplitude * (2.0 * PI * 1000.0 * n as f64 / RATE as f64).sin()) as f32;
+    out.extend(channels.iter().map(|&on| if on { s } else { 0.0 }));
+  }
+  *frame += frames;
+  out
+}
+
+// Stereo sections of (dBFS, seconds), as in EBU Tech 3341 and 3342.
+fn measure(sections: &[(f64, f64)]) -> Loudness {
+  let (mut meter, mut reader) = loudness_meter(RATE, 2, ChannelLayout::Stereo);
+  let mut frame = 0;
+  for &(dbfs, seconds) in sections {
+    meter.process(&sine(dbfs, seconds, &[true, true], &mut frame));
+  }
+  assert_eq!(reader.loudness(), meter.loudness());
+  meter.loudness()
+}
+
+fn near(value: f64, expected: f64, tolerance: f64) -> bool {
+  (value - expected).abs() <= tolerance
+}
+
+#[test]
+fn momentary_short_term_and_integrated() {
+  let l = measure(&[(-23.0, 20.0)]);
+  assert!(near(l.momentary, -23.0, 0.1) && near(l.short_term, -23.0, 0.1), "{:?}", l);
+  assert!(near(l.integrated, -23.0, 0.1) && near(l.max_momentary, -23.0, 0.1), "{:?}", l);
+  let l = measure(&[(-33.0, 20.0)]);
+  assert!(near(l.integrated, -33.0, 0.1), "{:?}", l);
+
+  // nothing until the first 400ms block
+  let (mut meter, mut reader) = loudness_meter(RATE, 2, ChannelLayout::Stereo);
+  meter.process(&sine(-23.0, 0.3, &[true, true], &mut 0));
+  assert_eq!(reader.loudness(), Loudness::default());
+}
+
+#[test]
+fn gates_quiet_passages() {
+  // the quiet ends are under the relative gate
+  let l = measure(&[(-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0)]);
+  assert!(near(l.integrated, -23.0, 0.1), "{:?}", l);
+  // they are not, but the silence is under the absolute one
+  let l = measure(&[(-26.0, 20.0), (-20.0, 20.1), (-26.0, 20.0), (-200.0, 10.0)]);
+  assert!(near(l.integrated, -23.0, 0.1), "{:?}", l);
+  assert!(near(l.max_short_term, -20.0, 0.1), "{:?}", l);
+}
+
+#[test]
+fn loudness_range() {
+  let l = measure(&[(-20.0, 20.0), (-30.0, 20.0)]);
+  assert!(near(l.range, 10.0, 1.0), "{:?}", l);
+  let l = measure(&[(-20.0, 20.0), (-15.0, 20.0)]);
+  assert!(near(l.range, 5.0, 1.0), "{:?}", l);
+  // far quieter passages are gated out
+  let l = measure(&[(-50.0, 20.0), (-35.0, 20.0), (-20.0, 20.0), (-35.0, 20.0), (-50.0, 20.0)]);
+  assert!(near(l.range, 15.0, 1.0), "{:?}", l);
+}
+
+#[test]
+fn weights_surround_channels() {
+  let run = |channels: &[bool]| {
+    let (mut meter, _) = loudness_meter(RATE, 6, ChannelLayout::F3_2_LFE);
+    meter.process(&sine(-20.0, 5.0, channels, &mut 0));
+    meter.loudness().integrated
+  };
+  // L, R, C, LFE, LS, RS
+  let front = run(&[true, false, false, false, false, false]);
+  assert!(near(front, -23.01, 0.1), "{}", front);
+  assert!(near(run(&[false, false, true, false, false, false]), front, 0.01));
+  assert!(near(run(&[false, false, false, false, true, false]), front + 1.49, 0.05));
+  assert_eq!(run(&[false, false, false, true, false, false]), f64::NEG_INFINITY);
+
+  // without a layout all channels count
+  let (mut meter, _) = loudness_meter(RATE, 6, ChannelLayout::Undefined);
+  meter.process(&sine(-20.0, 5.0, &[false, false, false, true, false, false], &mut 0));
+  assert!(near(meter.loudness().integrated, front, 0.1));
+}
+
+#[test]
+fn resets_from_the_reader() {
+  let (mut meter, mut reader) = loudness_meter(RATE, 2, ChannelLayout::Stereo);
+  let mut frame = 0;
+  meter.process(&sine(-10.0, 5.0, &[true, true], &mut frame));
+  reader.reset();
+  meter.process(&sine(-30.0, 5.0, &[true, true], &mut frame));
+  let l = reader.loudness();
+  assert!(near(l.integrated, -30.0, 0.1) && near(l.max_short_term, -30.0, 0.1), "{:?}", l);
+  assert!(l.max_momentary < -25.0, "{:?}", l);
+}
+
+#[test]
+fn measures_callbacks_and_files() {
+  let signal = sine(-23.0, 10.0, &[true, true], &mut 0);
+
+  let mut source = signal.clone();
+  let cb = Box::new(move |_: &[f32], out: &mut [f32]| {
+    let n = out.len().min(source.len());
+    out[.. n].copy_from_slice(&source[.. n]);
+    source.drain(.. n);
+    n / 2
+  });
+  let (mut cb, mut reader) = metered(cb, Side::Output, RATE, 2, ChannelLayout::Stereo);
+  let mut out = vec![0_f32; 2 * 1024];
+  while cb(&[], &mut out) == 1024 {}
+  assert!(near(reader.loudness().integrated, -23.0, 0.1), "{:?}", reader.loudness());
+
+  let path = std::env::temp_dir().join(format!("cult-loudness-{}.wav", std::process::id()));
+  let spec = Spec::new(RATE, 2, Encoding::Pcm24).with_layout(ChannelLayout::Stereo);
+  let mut writer = WavWriter::create(&path, spec).unwrap();
+  writer.write(&signal).unwrap();
+  writer.finalize().unwrap();
+  let l = measure_file(&path).unwrap();
+  fs::remove_file(&path).unwrap();
+  assert!(near(l.integrated, -23.0, 0.1), "{:?}", l);
+}
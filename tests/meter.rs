@@ -0,0 +1,79 @@
+extern crate cult;
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use cult::meter::{meter, metered, to_dbfs, Options, Side};
+
+const RATE: u32 = 48000;
+
+// `frames` of a sine at `frequency` on the first of `channels` channels.
+fn sine(frequency: f32, phase: f32, amplitude: f32, frames: usize, channels: usize) -> Vec<f32> {
+  let mut out = vec![0_f32; frames * channels];
+  for (n, frame) in out.chunks_mut(channels).enumerate() {
+    frame[0] = amplitude * (2.0 * PI * frequency * n as f32 / RATE as f32 + phase).sin();
+  }
+  out
+}
+
+#[test]
+fn peak_rms_and_true_peak() {
+  let (mut m, mut levels) = meter(RATE, 2, &Options::default());
+  // past the 300ms time constant
+  m.process(&sine(997.0, 0.0, 0.5, 3 * RATE as usize, 2));
+  let l = levels.levels()[0];
+  assert!((l.rms - 0.5 / 2_f32.sqrt()).abs() < 0.005, "{:?}", l);
+  assert!((l.peak - 0.5).abs() < 0.001 && (l.peak_hold - 0.5).abs() < 1e-4, "{:?}", l);
+  assert!((l.true_peak - 0.5).abs() < 0.01, "{:?}", l);
+  assert_eq!(levels.levels()[1], Default::default());
+
+  // a quarter of the rate, sampled 45 degrees off its peaks
+  let (mut m, mut levels) = meter(RATE, 1, &Options::default());
+  m.process(&sine(RATE as f32 / 4.0, PI / 4.0, 1.0, 4800, 1));
+  let l = levels.levels()[0];
+  assert!((to_dbfs(l.peak) + 3.01).abs() < 0.05, "{:?}", l);
+  assert!(to_dbfs(l.true_peak).abs() < 0.5, "{:?}", l);
+}
+
+#[test]
+fn ballistics_and_holds() {
+  let options = Options {
+    rms_attack: Duration::from_millis(10),
+    rms_release: Duration::from_secs(1),
+    ..Options::default()
+  };
+  let (mut m, mut levels) = meter(RATE, 1, &options);
+  m.process(&sine(1000.0, 0.0, 1.0, RATE as usize / 10, 1));
+  assert!(levels.levels()[0].rms > 0.7);
+
+  // 1.7s later peaks fell 20dB, the RMS level slowly, holds stay
+  m.process(&vec![0_f32; RATE as usize / 10]);
+  assert!(levels.levels()[0].rms > 0.6);
+  m.process(&vec![0_f32; 16 * RATE as usize / 10]);
+  let l = levels.levels()[0];
+  assert!((to_dbfs(l.peak) + 20.0).abs() < 0.2, "{:?}", l);
+  assert!((to_dbfs(l.true_peak) + 20.0).abs() < 0.5, "{:?}", l);
+  assert!(l.peak_hold > 0.99 && l.true_peak_hold > 0.99);
+
+  levels.reset_holds();
+  m.process(&[0_f32; 10]);
+  let l = levels.levels()[0];
+  assert!(l.peak_hold >= l.peak && l.peak_hold < 0.11, "{:?}", l);
+}
+
+#[test]
+fn meters_what_the_output_callback_returned() {
+  let cb = Box::new(|_: &[i16], out: &mut [i16]| {
+    for (i, s) in out.iter_mut().enumerate() {
+      *s = if i < 200 { 16384 } else { 32767 };
+    }
+    100
+  });
+  let options = Options { true_peak: false, ..Options::default() };
+  let (mut cb, mut levels) = metered(cb, Side::Output, RATE, 2, &options);
+  let mut out = vec![0_i16; 2 * 256];
+  assert_eq!(cb(&[], &mut out), 100);
+  let l = levels.levels();
+  assert_eq!((l[0].peak, l[1].peak), (0.5, 0.5));
+  assert_eq!(l[0].true_peak, 0.0);
+}
@@ -0,0 +1,77 @@
+extern crate cult;
+
+use cult::mixer::mixer_callback;
+use cult::source::BufferSource;
+
+#[test]
+fn sources_are_mixed_with_gain_and_pan() {
+  let (mut cb, mixer, mut collector) = mixer_callback(2, 4, 4);
+  let a = mixer.add(BufferSource::new(vec![0.25_f32; 6], 1)).unwrap();
+  let b = mixer.add(BufferSource::new(vec![0.5_f32; 8], 1)).unwrap();
+  b.set_gain(0.5);
+  b.set_pan(1.0);
+
+  let mut out = vec![0_f32; 2 * 8];
+  assert_eq!(cb(&[], &mut out), 8);
+  for (i, frame) in out.chunks(2).enumerate() {
+    let a = if i < 6 { 0.25 } else { 0.0 };
+    let b = if i < 8 { 0.25 } else { 0.0 };
+    assert!((frame[0] - a).abs() < 1e-6);
+    assert!((frame[1] - a - b).abs() < 1e-6);
+  }
+  assert!(a.is_finished());
+  assert!(!b.is_finished());
+  assert_eq!(mixer.playing(), 1);
+
+  cb(&[], &mut out);
+  assert!(out.iter().all(|&s| s == 0.0));
+  assert!(b.is_finished());
+  assert_eq!(collector.collect(), 2);
+}
+
+#[test]
+fn mono_output_ignores_pan() {
+  let (mut cb, mixer, _collector) = mixer_callback(1, 4, 4);
+  let left = mixer.add(BufferSource::new(vec![0.25_f32; 8], 1)).unwrap();
+  let centre = mixer.add(BufferSource::new(vec![0.125_f32; 8], 1)).unwrap();
+  left.set_pan(-1.0);
+  left.set_gain(0.5);
+  centre.set_gain(0.5);
+
+  let mut out = vec![0_f32; 8];
+  cb(&[], &mut out);
+  assert!(out.iter().all(|&s| (s - 0.1875).abs() < 1e-6), "{:?}", out);
+}
+
+#[test]
+fn voices_are_stolen_by_priority_then_age() {
+  let (mut cb, mixer, mut collector) = mixer_callback(1, 2, 8);
+  let long = || BufferSource::new(vec![0.1_f32; 10_000], 1);
+  let important = mixer.add_with_priority(long(), 5).unwrap();
+  let old = mixer.add(long()).unwrap();
+  let new = mixer.add_with_priority(long(), 5).unwrap();
+  let mut out = vec![0_f32; 128];
+  cb(&[], &mut out);
+  assert!(old.was_stolen() && old.is_finished());
+  assert!(!important.was_stolen() && !new.was_stolen());
+  assert_eq!(mixer.playing(), 2);
+
+  // nothing of lower priority to steal from
+  let low = mixer.add(long()).unwrap();
+  new.set_muted(true);
+  cb(&[], &mut out);
+  assert!(low.was_stolen() && low.is_finished());
+  assert!(!new.is_finished());
+  assert_eq!(mixer.steals(), 2);
+  // muting fades over the period
+  assert!(out[0] > 0.19);
+  assert!((out[127] - 0.1).abs() < 1e-6);
+
+  important.stop();
+  new.stop();
+  cb(&[], &mut out);
+  assert!(important.is_finished() && new.is_finished());
+  assert!(out[64 ..].iter().all(|&s| s == 0.0));
+  assert_eq!(mixer.playing(), 0);
+  assert_eq!(collector.collect(), 4);
+}
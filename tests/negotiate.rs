@@ -0,0 +1,84 @@
+extern crate cult;
+
+use cult::negotiate::{negotiate, Capabilities, Constraints, Conversion, RateConstraint};
+use cult::{ChannelLayout, StreamParams};
+
+fn usb_headset() -> Capabilities {
+  Capabilities {
+    formats: cult::DEVICE_FMT_S16LE,
+    default_format: cult::DEVICE_FMT_S16LE,
+    min_rate: 16000,
+    max_rate: 48000,
+    default_rate: 48000,
+    max_channels: 2,
+    latency_lo: 256,
+    latency_hi: 4096,
+  }
+}
+
+#[test]
+fn conversions_are_listed() {
+  let desired = StreamParams::<f32>::new(96000, 6, ChannelLayout::F3_2_LFE);
+  let constraints = Constraints { latency_frames: 128, ..Constraints::default() };
+  let n = negotiate(desired, &constraints, &usb_headset()).unwrap();
+
+  assert_eq!(n.params.rate(), 96000);
+  assert_eq!(n.params.device_rate(), 48000);
+  assert_eq!(n.params.channels(), 6);
+  assert_eq!(n.params.device_channels(), 2);
+  assert_eq!(n.params.device_layout(), ChannelLayout::Stereo);
+  // 128 frames at 96kHz are 64 at 48kHz, below the 256 the device allows
+  assert_eq!(n.latency_frames, 512);
+  assert_eq!(n.conversions.len(), 3);
+  assert!(n.conversions.contains(&Conversion::Rate { callback: 96000, device: 48000 }));
+  assert!(n.conversions.contains(&Conversion::Channels { callback: 6, device: 2 }));
+  // converted by the backend, not by cult
+  assert!(n.conversions.contains(&Conversion::BackendFormat {
+    callback: cult::native_float32(),
+    device: cult::DEVICE_FMT_S16LE,
+  }));
+}
+
+#[test]
+fn exact_rates_are_not_resampled() {
+  let constraints = Constraints { rate: RateConstraint::Exact, ..Constraints::default() };
+  let desired = StreamParams::<i16>::new(44100, 1, ChannelLayout::Mono);
+  let n = negotiate(desired, &constraints, &usb_headset()).unwrap();
+  assert!(n.conversions.is_empty());
+  assert_eq!(n.params.device_rate(), 44100);
+  assert_eq!(n.latency_frames, 256);
+
+  let desired = StreamParams::<i16>::new(8000, 1, ChannelLayout::Mono);
+  assert!(negotiate(desired, &constraints, &usb_headset()).is_err());
+}
+
+#[test]
+fn out_of_range_rates_go_to_the_closest_or_default_one() {
+  let device = Capabilities {
+    formats: cult::DEVICE_FMT_F32NE,
+    default_format: cult::DEVICE_FMT_F32NE,
+    min_rate: 8000,
+    max_rate: 192000,
+    default_rate: 48000,
+    max_channels: 2,
+    latency_lo: 256,
+    latency_hi: 4096,
+  };
+  let desired = StreamParams::<f32>::new(384000, 2, ChannelLayout::Stereo);
+  let n = negotiate(desired, &Constraints::default(), &device).unwrap();
+  assert_eq!(n.params.device_rate(), 192000);
+  assert_eq!(n.conversions, [Conversion::Rate { callback: 384000, device: 192000 }]);
+
+  let constraints = Constraints { rate: RateConstraint::DeviceDefault, ..Constraints::default() };
+  let n = negotiate(desired, &constraints, &device).unwrap();
+  assert_eq!(n.params.device_rate(), 48000);
+  assert_eq!(n.conversions, [Conversion::Rate { callback: 384000, device: 48000 }]);
+  // supported rates are kept
+  let desired = StreamParams::<f32>::new(44100, 2, ChannelLayout::Stereo);
+  assert_eq!(negotiate(desired, &constraints, &device).unwrap().params.device_rate(), 44100);
+
+  // closest when the default is not usable either
+  let device = Capabilities { default_rate: 0, ..device };
+  let desired = StreamParams::<f32>::new(4000, 2, ChannelLayout::Stereo);
+  assert_eq!(negotiate(desired, &constraints, &device).unwrap().params.device_rate(), 8000);
+}
@@ -0,0 +1,46 @@
+extern crate cult;
+
+use cult::param::{AtomicF32, AtomicF64};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+#[test]
+fn floats_round_trip_bit_for_bit() {
+  let x = AtomicF32::new(0.5);
+  assert_eq!(x.load(Ordering::Relaxed), 0.5);
+  for &v in &[-0.0_f32, f32::NAN, -f32::NAN, f32::INFINITY, f32::MIN_POSITIVE / 2.0,
+              f32::from_bits(0x7fc0_1234)] {
+    x.store(v, Ordering::Relaxed);
+    assert_eq!(x.load(Ordering::Relaxed).to_bits(), v.to_bits());
+  }
+  assert_eq!(x.swap(1.0, Ordering::Relaxed).to_bits(), 0x7fc0_1234);
+  assert_eq!(x.into_inner(), 1.0);
+  assert_eq!(AtomicF32::default().load(Ordering::Relaxed).to_bits(), 0);
+
+  let y = AtomicF64::new(0.25);
+  assert_eq!(y.load(Ordering::Relaxed), 0.25);
+  for &v in &[-0.0_f64, f64::NAN, -f64::NAN, f64::NEG_INFINITY, f64::MIN_POSITIVE / 2.0,
+              f64::from_bits(0x7ff8_0000_dead_beef)] {
+    y.store(v, Ordering::Relaxed);
+    assert_eq!(y.load(Ordering::Relaxed).to_bits(), v.to_bits());
+  }
+  assert_eq!(y.swap(2.0, Ordering::Relaxed).to_bits(), 0x7ff8_0000_dead_beef);
+  assert_eq!(y.into_inner(), 2.0);
+}
+
+#[test]
+fn floats_are_never_torn() {
+  let x = Arc::new(AtomicF64::new(1.0));
+  let writer = x.clone();
+  let t = thread::spawn(move || {
+    for i in 0 .. 100_000 {
+      writer.store(if i % 2 == 0 { -1.0 } else { 1.0 }, Ordering::Release);
+    }
+  });
+  for _ in 0 .. 100_000 {
+    let v = x.load(Ordering::Acquire);
+    assert!(v == 1.0 || v == -1.0, "{}", v);
+  }
+  t.join().unwrap();
+}
@@ -0,0 +1,138 @@
+extern crate cult;
+
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use cult::render::{OfflineStream, Periods};
+use cult::resampler::{Quality, Resampler};
+use cult::{ChannelLayout, DataCallback, StreamParams};
+
+// Every callback sample is `value`.
+fn constant(value: f32) -> DataCallback<f32> {
+  Box::new(move |_: &[f32], obuf: &mut [f32]| {
+    for s in obuf.iter_mut() {
+      *s = value;
+    }
+    obuf.len()
+  })
+}
+
+#[test]
+fn mix_matrices_change_at_the_next_callback() {
+  let params = StreamParams::new(48000, 1, ChannelLayout::Mono)
+    .with_device_layout(ChannelLayout::Stereo);
+  let mut stream = OfflineStream::new(None, Some(params), constant(0.5), None).unwrap()
+    .with_periods(Periods::Fixed(16));
+  assert!(stream.set_output_mix_matrix(&[1.0]).is_err());
+  assert!(stream.set_input_mix_matrix(&[1.0, 0.0]).is_err());
+  let out = stream.render(16);
+  assert!(out[0] > 0.0 && out[0] == out[1], "{:?}", &out[.. 2]);
+
+  // only the latest matrix is picked up, all of it
+  stream.set_output_mix_matrix(&[0.0, 1.0]).unwrap();
+  stream.set_output_mix_matrix(&[1.0, 0.0]).unwrap();
+  let out = stream.render(32);
+  assert!(out.chunks(2).all(|f| f == [0.5, 0.0]), "{:?}", out);
+  stream.set_output_mix_matrix(&[0.25, 2.0]).unwrap();
+  let out = stream.render(16);
+  assert!(out.chunks(2).all(|f| f == [0.125, 1.0]), "{:?}", out);
+}
+
+// Logical channel `c` of every frame is `values[c]`.
+fn channels(values: &'static [f32]) -> DataCallback<f32> {
+  Box::new(move |_: &[f32], obuf: &mut [f32]| {
+    for (s, v) in obuf.iter_mut().zip(values.iter().cycle()) {
+      *s = *v;
+    }
+    obuf.len() / values.len()
+  })
+}
+
+#[test]
+fn routes_logical_channels_to_device_channels() {
+  let params = StreamParams::new(48000, 2, ChannelLayout::Stereo).with_device_channels(4);
+  let mut stream = OfflineStream::new(None, Some(params), channels(&[0.25, 0.75]), None)
+    .unwrap().with_periods(Periods::Fixed(8));
+  // by default in order, the channels past them silent
+  assert_eq!(stream.output_routing(), Some(vec![Some(0), Some(1)]));
+  assert_eq!(&stream.render(8)[.. 8], &[0.25, 0.75, 0.0, 0.0, 0.25, 0.75, 0.0, 0.0]);
+
+  assert!(stream.set_output_routing(&[Some(4), None]).is_err());
+  assert!(stream.set_output_routing(&[Some(0)]).is_err());
+  assert!(stream.set_input_routing(&[Some(0), Some(1)]).is_err());
+
+  // remapped at the next callback, unrouted channels go nowhere
+  stream.set_output_routing(&[Some(3), None]).unwrap();
+  assert_eq!(stream.output_routing(), Some(vec![Some(3), None]));
+  let out = stream.render(8);
+  assert!(out.chunks(4).all(|f| f == [0.0, 0.0, 0.0, 0.25]), "{:?}", out);
+
+  // both on the same device channel add up
+  stream.set_output_routing(&[Some(2), Some(2)]).unwrap();
+  let out = stream.render(8);
+  assert!(out.chunks(4).all(|f| f == [0.0, 0.0, 1.0, 0.0]), "{:?}", out);
+}
+
+#[test]
+fn pulls_input_from_one_device_channel() {
+  let captured = Rc::new(RefCell::new(Vec::new()));
+  let sink = captured.clone();
+  let cb: DataCallback<f32> = Box::new(move |ibuf: &[f32], _: &mut [f32]| {
+    sink.borrow_mut().extend_from_slice(ibuf);
+    ibuf.len()
+  });
+  let params = StreamParams::new(48000, 1, ChannelLayout::Mono).with_device_channels(4);
+  // device channel `c` of frame `n` is `10 * n + c`
+  let input: Vec<f32> = (0 .. 16).flat_map(|n| (0 .. 4).map(move |c| (10 * n + c) as f32))
+    .collect();
+  let mut stream = OfflineStream::new(Some(params), None, cb, None).unwrap()
+    .with_periods(Periods::Fixed(8))
+    .with_input(input);
+  assert_eq!(stream.input_routing(), Some(vec![Some(0)]));
+
+  stream.set_input_routing(&[Some(2)]).unwrap();
+  stream.render(8);
+  stream.set_input_routing(&[None]).unwrap();
+  stream.render(8);
+  let expected: Vec<f32> = (0 .. 8).map(|n| (10 * n + 2) as f32)
+    .chain((0 .. 8).map(|_| 0.0)).collect();
+  assert_eq!(*captured.borrow(), expected);
+}
+
+#[test]
+fn resamples_to_the_device_rate_and_accounts_for_it() {
+  // a 1kHz sine at 44.1kHz, on a 48kHz device
+  let called = Rc::new(RefCell::new(0_u64));
+  let counter = called.clone();
+  let cb: DataCallback<f32> = Box::new(move |_: &[f32], obuf: &mut [f32]| {
+    let mut n = counter.borrow_mut();
+    for s in obuf.iter_mut() {
+      *s = (2.0 * PI * 1000.0 * *n as f64 / 44100.0).sin() as f32;
+      *n += 1;
+    }
+    obuf.len()
+  });
+  let params = StreamParams::new(44100, 1, ChannelLayout::Mono)
+    .with_device_rate(48000, Quality::Sinc);
+  let mut stream = OfflineStream::new(None, Some(params), cb, None).unwrap()
+    .with_periods(Periods::Fixed(480));
+  assert_eq!(stream.rate(), 48000);
+  let out = stream.render(48000);
+  assert_eq!(out.len(), 48000);
+
+  // a second at the callback's rate, plus what the filter looks ahead
+  let lookahead = Resampler::new(1, 44100, 48000, Quality::Sinc).latency() as u64;
+  let called = *called.borrow();
+  assert!((44100 ..= 44100 + 2 * lookahead + 480).contains(&called), "{}", called);
+  // still 1kHz: two zero crossings a millisecond once the filter is full
+  let crossings = out[4800 ..].windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count();
+  assert!((crossings as i64 - 2 * 900).abs() <= 2, "{}", crossings);
+
+  // the device's latency at the callback's rate, and what sits in the
+  // resampler: frames the callback produced but that were not played yet
+  let held = stream.latency(0) as u64;
+  assert_eq!(held, called - 48000 * 44100 / 48000);
+  assert!(held >= lookahead, "{} {}", held, lookahead);
+  assert_eq!(stream.latency(480), 441 + held as u32);
+}
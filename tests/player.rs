@@ -0,0 +1,207 @@
+extern crate cult;
+
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::io::Cursor;
+use std::thread;
+use std::time::Duration;
+
+use cult::ChannelLayout;
+use cult::player::{playback, Converter, Decoder, Options};
+use cult::resampler::Quality;
+use cult::wav::{Encoding, Spec, WavReader, WavWriter};
+
+// A mono float file holding its own frame numbers, plus `offset`.
+fn numbered(frames: usize, offset: usize) -> Box<dyn Decoder> {
+  numbered_at(8000, frames, offset)
+}
+
+fn numbered_at(rate: u32, frames: usize, offset: usize) -> Box<dyn Decoder> {
+  let spec = Spec::new(rate, 1, Encoding::Float32);
+  let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+  let samples: Vec<f32> = (offset .. offset + frames).map(|i| i as f32).collect();
+  writer.write(&samples).unwrap();
+  let file = writer.finalize().unwrap().into_inner();
+  Box::new(WavReader::new(Cursor::new(file)).unwrap())
+}
+
+fn settle() {
+  thread::sleep(Duration::from_millis(100));
+}
+
+fn ramp(from: usize, to: usize) -> Vec<f32> {
+  (from .. to).map(|i| i as f32).collect()
+}
+
+#[test]
+fn queued_files_play_gaplessly_then_drain() {
+  let (mut cb, _, player) = playback::<f32>(numbered(1000, 0), &Options::default()).unwrap();
+  player.queue(numbered(500, 1000)).unwrap();
+  settle();
+
+  let mut out = vec![0_f32; 600];
+  assert_eq!(cb(&[], &mut out), 600);
+  assert_eq!(out, ramp(0, 600));
+  assert_eq!((player.current_file(), player.position_frames()), (0, 600));
+
+  cb(&[], &mut out);
+  assert_eq!(out, ramp(600, 1200));
+  assert_eq!((player.current_file(), player.position_frames()), (1, 200));
+  // a latency later, the end of the first file is still heard
+  assert_eq!(player.heard_position(0), (1, 200));
+  assert_eq!(player.heard_position(200), (1, 0));
+  assert_eq!(player.heard_position(300), (0, 900));
+
+  // the end of the last file drains the stream
+  assert_eq!(cb(&[], &mut out), 300);
+  assert_eq!(&out[.. 300], &ramp(1200, 1500)[..]);
+  assert!(out[300 ..].iter().all(|&s| s == 0.0));
+  assert_eq!(player.underruns(), 0);
+}
+
+#[test]
+fn loops_seeks_and_pauses() {
+  let (mut cb, _, player) = playback::<f32>(numbered(10_000, 0), &Options::default()).unwrap();
+  settle();
+  // past what was read ahead already
+  player.set_loop(Some((5000, 5100)));
+  let mut out = vec![0_f32; 1000];
+  for _ in 0 .. 4 {
+    cb(&[], &mut out);
+    settle();
+  }
+
+  cb(&[], &mut out[.. 300]);
+  let mut expected = ramp(4000, 4300);
+  assert_eq!(&out[.. 300], &expected[..]);
+  cb(&[], &mut out);
+  expected = ramp(4300, 5100);
+  expected.extend(ramp(5000, 5100));
+  expected.extend(ramp(5000, 5100));
+  assert_eq!(out, expected);
+  // back at the loop start
+  assert_eq!(player.position_frames(), 5000);
+
+  player.pause();
+  cb(&[], &mut out);
+  assert!(out.iter().all(|&s| s == 0.0));
+  assert_eq!(player.position_frames(), 5000);
+  player.resume();
+
+  player.set_loop(None);
+  player.seek(500);
+  settle();
+  // flushes what was read ahead of the seek
+  cb(&[], &mut out);
+  assert_eq!(player.position_frames(), 500);
+  settle();
+  cb(&[], &mut out);
+  assert_eq!(out, ramp(500, 1500));
+  assert_eq!(player.position_frames(), 1500);
+}
+
+#[test]
+fn seeks_in_the_file_played_when_the_next_one_is_read_ahead() {
+  let (mut cb, _, player) = playback::<f32>(numbered(1000, 0), &Options::default()).unwrap();
+  player.queue(numbered(500, 1000)).unwrap();
+  settle();
+  let mut out = vec![0_f32; 600];
+  cb(&[], &mut out);
+
+  player.seek(100);
+  settle();
+  cb(&[], &mut out);
+  assert_eq!((player.current_file(), player.position_frames()), (0, 100));
+  settle();
+  cb(&[], &mut out);
+  assert_eq!(out, ramp(100, 700));
+  // the queued file plays again from its start
+  cb(&[], &mut out);
+  assert_eq!(out, ramp(700, 1300));
+  assert_eq!((player.current_file(), player.position_frames()), (1, 300));
+}
+
+#[test]
+fn loops_hold_the_queue_until_cleared() {
+  let (mut cb, _, player) = playback::<f32>(numbered(10_000, 0), &Options::default()).unwrap();
+  player.queue(numbered(500, 10_000)).unwrap();
+  settle();
+  player.set_loop(Some((5000, 5100)));
+  let mut out = vec![0_f32; 2000];
+  let mut played = Vec::new();
+  for _ in 0 .. 8 {
+    cb(&[], &mut out);
+    played.extend_from_slice(&out);
+    settle();
+  }
+  assert_eq!(&played[.. 5100], &ramp(0, 5100)[..]);
+  assert!(played[5100 ..].iter().all(|&s| s >= 5000.0 && s < 5100.0));
+  assert_eq!(player.current_file(), 0);
+
+  // what was read of the loop plays out, then the rest and the next file
+  player.set_loop(None);
+  let mut played = Vec::new();
+  while cb(&[], &mut out) == out.len() {
+    played.extend_from_slice(&out);
+    settle();
+  }
+  played.extend_from_slice(&out);
+  let rest = played.iter().position(|&s| s == 5100.0).unwrap();
+  assert!(played[.. rest].iter().all(|&s| s >= 5000.0 && s < 5100.0));
+  assert_eq!(&played[rest .. rest + 5400], &ramp(5100, 10_500)[..]);
+  assert_eq!(player.current_file(), 1);
+  assert_eq!(player.underruns(), 0);
+}
+
+#[test]
+fn converter_matches_rate_and_layout() {
+  let spec = Spec::new(8000, 1, Encoding::Float32);
+  let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+  writer.write(&vec![0.5_f32; 4000]).unwrap();
+  let file = writer.finalize().unwrap().into_inner();
+  let decoder = Box::new(WavReader::new(Cursor::new(file)).unwrap());
+  let mut converter =
+    Converter::new(decoder, 16000, 2, ChannelLayout::Stereo, Quality::Sinc).unwrap();
+  assert_eq!((converter.rate(), converter.channels()), (16000, 2));
+  assert_eq!(converter.frames(), Some(8000));
+
+  let mut out = vec![0_f32; 2 * 10_000];
+  let mut read = 0;
+  loop {
+    let n = converter.read(&mut out[2 * read ..]).unwrap();
+    if n == 0 {
+      break;
+    }
+    read += n;
+  }
+  assert!((read as i64 - 8000).abs() <= 40, "{}", read);
+  // mono spreads to both sides at -3dB
+  for frame in out[2 * 1000 .. 2 * 7000].chunks(2) {
+    assert_eq!(frame[0], frame[1]);
+    assert!((frame[0] - 0.5 * FRAC_1_SQRT_2).abs() < 1e-3);
+  }
+
+  converter.seek(4000).unwrap();
+  assert_eq!(converter.position(), 4000);
+  assert!(converter.read(&mut out).unwrap() > 3900);
+
+  // queued files are converted to what the playback started with
+  let (_, _, player) = playback::<f32>(numbered(1000, 0), &Options::default()).unwrap();
+  player.queue(numbered_at(44100, 10, 0)).unwrap();
+}
+
+#[test]
+fn converter_drains_the_resampler_between_reads() {
+  // each read fills up before the resampler is empty
+  let mut converter =
+    Converter::new(numbered(4000, 0), 48000, 1, ChannelLayout::Mono, Quality::Sinc).unwrap();
+  let mut out = vec![0_f32; 1024];
+  let mut read = 0;
+  loop {
+    let n = converter.read(&mut out).unwrap();
+    if n == 0 {
+      break;
+    }
+    read += n;
+  }
+  assert!((read as i64 - 24_000).abs() <= 1, "{}", read);
+}
@@ -0,0 +1,69 @@
+extern crate cult;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use cult::recorder::{recording, FileFormat, Options};
+use cult::wav::{Encoding, WavReader};
+use cult::{ChannelLayout, State};
+
+fn temp_path(name: &str) -> PathBuf {
+  std::env::temp_dir().join(format!("cult-recorder-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn records_until_the_maximum_duration() {
+  let path = temp_path("max.wav");
+  let options = Options { max_duration: Some(Duration::from_millis(100)), ..Options::default() };
+  let (mut cb, _, rec) =
+    recording::<f32, _>(&path, 8000, 2, ChannelLayout::Stereo, &options).unwrap();
+
+  let input: Vec<f32> = (0 .. 2 * 300).map(|i| (i / 2) as f32 / 1000.0).collect();
+  assert_eq!(cb(&input, &mut []), 300);
+  rec.pause();
+  assert_eq!(cb(&input, &mut []), 300);
+  rec.resume();
+  // 800 frames at most
+  assert_eq!(cb(&input, &mut []), 300);
+  assert_eq!(cb(&input, &mut []), 200);
+  assert_eq!(rec.frames_recorded(), 800);
+  assert_eq!(rec.duration(), Duration::from_millis(100));
+
+  let summary = rec.finish().unwrap();
+  assert_eq!(summary.frames, 800);
+  assert_eq!(summary.dropped_frames, 0);
+  let mut reader = WavReader::open(&path).unwrap();
+  assert_eq!(reader.spec().layout, ChannelLayout::Stereo);
+  let samples: Vec<f32> = reader.read_to_end().unwrap();
+  assert_eq!(samples.len(), 2 * 800);
+  assert!((samples[2 * 310] - 0.01).abs() < 1e-4);
+  fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn stalls_drop_frames_and_errors_finalize() {
+  let path = temp_path("raw.pcm");
+  let options = Options { format: FileFormat::Raw, encoding: Encoding::Pcm24,
+                          buffer: Duration::from_millis(1), ..Options::default() };
+  let (mut cb, mut state_cb, rec) =
+    recording::<i16, _>(&path, 48000, 1, ChannelLayout::Mono, &options).unwrap();
+
+  // more than the ring holds in one go
+  let input = vec![0x100_i16; 10_000];
+  assert_eq!(cb(&input, &mut []), 10_000);
+  assert_eq!(rec.dropped_frames(), 10_000 - 4096);
+  assert_eq!(rec.overruns(), 1);
+
+  state_cb(State::Error);
+  assert!(rec.failed());
+  while !rec.is_finished() {
+    std::thread::sleep(Duration::from_millis(5));
+  }
+  let summary = rec.finish().unwrap();
+  assert_eq!(summary.frames, 4096);
+  let bytes = fs::read(&path).unwrap();
+  assert_eq!(bytes.len(), 3 * 4096);
+  assert_eq!(&bytes[.. 3], &[0x00, 0x00, 0x01]);
+  fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,110 @@
+extern crate cult;
+
+use std::cell::RefCell;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use cult::render::{OfflineStream, Periods};
+use cult::wav::{Encoding, WavReader};
+use cult::{ChannelLayout, DataCallback, StreamParams};
+
+#[test]
+fn random_periods_are_seeded_and_bounded() {
+  let periods_with = |seed| {
+    let sizes = Rc::new(RefCell::new(Vec::new()));
+    let seen = sizes.clone();
+    let mut count = 0;
+    let params = StreamParams::<f32>::new(48000, 1, ChannelLayout::Mono);
+    let cb = Box::new(move |_: &[f32], out: &mut [f32]| {
+      seen.borrow_mut().push(out.len());
+      for s in out.iter_mut() {
+        *s = count as f32;
+        count += 1;
+      }
+      out.len()
+    });
+    let mut stream = OfflineStream::new(None, Some(params), cb, None).unwrap()
+      .with_periods(Periods::Random { min: 16, max: 300, seed });
+    let out = stream.render(10_000);
+    assert_eq!(out, (0 .. 10_000).map(|i| i as f32).collect::<Vec<_>>());
+    assert_eq!(stream.frames_rendered(), 10_000);
+    let sizes = sizes.borrow().clone();
+    assert_eq!(sizes.iter().sum::<usize>(), 10_000);
+    // all but the last one, cut short
+    assert!(sizes[.. sizes.len() - 1].iter().all(|n| (16 ..= 300).contains(n)));
+    sizes
+  };
+  let sizes = periods_with(7);
+  assert!(sizes.iter().any(|&n| n != sizes[0]));
+  assert_eq!(periods_with(7), sizes);
+  assert_ne!(periods_with(8), sizes);
+}
+
+#[test]
+fn duplex_input_then_silence() {
+  let params = StreamParams::<i16>::new(8000, 2, ChannelLayout::Stereo);
+  let input: Vec<i16> = (0 .. 2 * 1000).map(|i| i as i16).collect();
+  let mut stream = OfflineStream::new(Some(params), Some(params),
+                                      Box::new(|input: &[i16], out: &mut [i16]| {
+    out.copy_from_slice(input);
+    out.len() / 2
+  }), None).unwrap().with_periods(Periods::Fixed(300)).with_input(input.clone());
+  let out = stream.render(1200);
+  assert_eq!(&out[.. 2000], &input[..]);
+  assert!(out[2000 ..].iter().all(|&s| s == 0));
+  assert_eq!(out.len(), 2 * 1200);
+}
+
+#[test]
+fn drains_to_a_wav_file_through_the_device_conversions() {
+  let states = Rc::new(RefCell::new(Vec::new()));
+  let seen = states.clone();
+  let params = StreamParams::<f32>::new(8000, 1, ChannelLayout::Mono)
+    .with_device_layout(ChannelLayout::Stereo);
+  let mut left = 1000;
+  let cb = Box::new(move |_: &[f32], out: &mut [f32]| {
+    let n = out.len().min(left);
+    for s in &mut out[.. n] {
+      *s = 0.5;
+    }
+    left -= n;
+    n
+  });
+  let state_cb = Box::new(move |state| seen.borrow_mut().push(state));
+  let mut stream = OfflineStream::new(None, Some(params), cb, Some(state_cb)).unwrap();
+
+  let path = std::env::temp_dir().join(format!("cult-render-{}.wav", std::process::id()));
+  assert_eq!(stream.render_to_wav(&path, 100_000, Encoding::Float32).unwrap(), 1000);
+  assert!(stream.is_drained());
+  assert_eq!(format!("{:?}", states.borrow()), "[Started, Drained]");
+  assert_eq!(stream.stats().frames_returned, 1000);
+
+  let mut reader = WavReader::open(&path).unwrap();
+  assert_eq!((reader.spec().channels, reader.spec().layout), (2, ChannelLayout::Stereo));
+  let samples: Vec<f32> = reader.read_to_end().unwrap();
+  assert_eq!(samples.len(), 2 * 1000);
+  assert!(samples.iter().all(|&s| (s - 0.5 * std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6));
+  fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn callback_panics_are_raised_from_render() {
+  let mut calls = 0;
+  let cb: DataCallback<f32> = Box::new(move |_: &[f32], obuf: &mut [f32]| {
+    calls += 1;
+    if calls == 3 {
+      panic!("third period");
+    }
+    obuf.len()
+  });
+  let params = StreamParams::new(48000, 2, ChannelLayout::Stereo);
+  let mut stream = OfflineStream::new(None, Some(params), cb, None).unwrap()
+    .with_periods(Periods::Fixed(100));
+  let mut out = Vec::new();
+  let caught = panic::catch_unwind(AssertUnwindSafe(|| stream.render_into(&mut out, 1000)));
+  assert_eq!(*caught.unwrap_err().downcast_ref::<&str>().unwrap(), "third period");
+  assert_eq!(out.len(), 2 * 200);
+  assert_eq!(stream.frames_rendered(), 200);
+  assert!(stream.is_drained());
+}
@@ -0,0 +1,66 @@
+extern crate cult;
+
+use cult::resampler::{Quality, Resampler};
+use std::f64::consts::PI;
+
+fn sine(freq: f64, rate: u32, frames: usize) -> Vec<f32> {
+  (0..frames).map(|i| (2.0 * PI * freq * i as f64 / rate as f64).sin() as f32).collect()
+}
+
+// Feeds `input` in uneven blocks into an output buffer of uneven blocks.
+fn run(r: &mut Resampler, input: &[f32], out_frames: usize) -> Vec<f32> {
+  let ch = r.channels();
+  let in_frames = input.len() / ch;
+  let mut out = vec![0_f32; out_frames * ch];
+  let (mut used, mut made, mut block) = (0, 0, 1);
+  while made < out_frames {
+    let end = (used + block).min(in_frames);
+    let out_end = (made + 300).min(out_frames);
+    let (c, p) = r.process(&input[used * ch..end * ch], &mut out[made * ch..out_end * ch]);
+    if c == 0 && p == 0 {
+      break;
+    }
+    used += c;
+    made += p;
+    block = block * 3 % 997 + 1;
+  }
+  out.truncate(made * ch);
+  out
+}
+
+#[test]
+fn sine_survives_conversion() {
+  for &(quality, tolerance) in [(Quality::Sinc, 1e-3), (Quality::Linear, 2e-2)].iter() {
+    let mut r = Resampler::new(1, 48000, 44100, quality);
+    let input = sine(1000.0, 48000, 48000);
+    let out = run(&mut r, &input, 44100);
+    // all but the last filter length
+    assert_eq!(out.len(), 44100 - r.latency() * 44100 / 48000);
+    let expected = sine(1000.0, 44100, out.len());
+    // skip the start, where the filter sees the silence before the input
+    let err = out.iter().zip(expected.iter()).skip(100).take(40000)
+      .map(|(a, b)| (a - b).abs()).fold(0_f32, f32::max);
+    assert!(err < tolerance, "{:?}: error {}", quality, err);
+  }
+}
+
+#[test]
+fn high_frequencies_are_filtered_when_downsampling() {
+  // 20kHz is above the 11.025kHz Nyquist frequency of the output
+  let mut r = Resampler::new(2, 44100, 22050, Quality::Sinc);
+  let input: Vec<f32> = sine(20000.0, 44100, 22050).iter().flat_map(|&s| vec![s, s]).collect();
+  let out = run(&mut r, &input, 11025);
+  let peak = out.iter().skip(200).map(|s| s.abs()).fold(0_f32, f32::max);
+  assert!(peak < 0.01, "peak {}", peak);
+}
+
+#[test]
+fn input_needed_is_exact() {
+  let mut r = Resampler::new(1, 44100, 48000, Quality::Sinc);
+  let mut out = vec![0_f32; 256];
+  let needed = r.input_needed(256);
+  let input = vec![0.5_f32; needed];
+  assert_eq!(r.process(&input[..needed - 1], &mut out), (needed - 1, 255));
+  assert_eq!(r.process(&input[needed - 1..], &mut out[255..]), (1, 1));
+  assert_eq!(r.latency(), 16);
+}
@@ -0,0 +1,46 @@
+extern crate cult;
+
+use cult::ring::ring_buffer;
+use std::thread;
+
+#[test]
+fn ring_wraps_around() {
+  let (mut p, mut c) = ring_buffer::<i16>(5);
+  let mut out = [0_i16; 5];
+  for round in 0 .. 10 {
+    let base = round * 3;
+    assert_eq!(p.push_slice(&[base, base + 1, base + 2]), 3);
+    assert_eq!(c.len(), 3);
+    assert_eq!(c.pop_slice(&mut out), 3);
+    assert_eq!(&out[.. 3], &[base, base + 1, base + 2]);
+  }
+  assert_eq!(p.push_slice(&[0; 8]), 5);
+  assert!(p.push(1).is_err());
+  assert_eq!(c.discard(2), 2);
+  assert_eq!(p.free_len(), 2);
+}
+
+#[test]
+fn ring_across_threads() {
+  let (mut p, mut c) = ring_buffer::<u32>(64);
+  let writer = thread::spawn(move || {
+    let mut next = 0;
+    while next < 100_000 {
+      if p.push(next).is_ok() {
+        next += 1;
+      } else {
+        thread::yield_now();
+      }
+    }
+  });
+  let mut expected = 0;
+  while expected < 100_000 {
+    if let Some(v) = c.pop() {
+      assert_eq!(v, expected);
+      expected += 1;
+    } else {
+      thread::yield_now();
+    }
+  }
+  writer.join().unwrap();
+}
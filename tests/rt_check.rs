@@ -0,0 +1,80 @@
+#![cfg(feature = "rt-check")]
+
+extern crate cult;
+
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use cult::render::{OfflineStream, Periods};
+use cult::rt_check::{self, Policy, RtCheckAllocator, Violations};
+use cult::{ChannelLayout, DataCallback, StreamParams};
+
+#[global_allocator]
+static ALLOC: RtCheckAllocator = RtCheckAllocator::system();
+
+// The policy is global to the process.
+static POLICY: Mutex<()> = Mutex::new(());
+
+// An offline stream whose callback allocates and frees a buffer every
+// period, and the states it went through.
+fn allocating_stream() -> (OfflineStream<f32>, Rc<RefCell<Vec<String>>>) {
+  let cb: DataCallback<f32> = Box::new(|_: &[f32], obuf: &mut [f32]| {
+    let scratch = vec![0.5_f32; obuf.len()];
+    obuf.copy_from_slice(&scratch);
+    obuf.len()
+  });
+  let states = Rc::new(RefCell::new(Vec::new()));
+  let seen = states.clone();
+  let params = StreamParams::new(48000, 1, ChannelLayout::Mono);
+  let state_cb = Box::new(move |s| seen.borrow_mut().push(format!("{:?}", s)));
+  let stream = OfflineStream::new(None, Some(params), cb, Some(state_cb)).unwrap();
+  (stream.with_periods(Periods::Fixed(64)), states)
+}
+
+#[test]
+fn counts_allocations_inside_the_guard() {
+  let _policy = POLICY.lock().unwrap_or_else(|e| e.into_inner());
+  rt_check::set_policy(Policy::Count);
+  let violations = Violations::default();
+  let outside = vec![0_u8; 16];
+  {
+    let _guard = rt_check::enter(&violations);
+    let mut v = Vec::<u32>::with_capacity(4);
+    v.push(1);
+    drop(v);
+  }
+  drop(outside);
+  assert_eq!(violations.count(), 2);
+}
+
+#[test]
+fn log_once_keeps_counting_without_failing() {
+  let _policy = POLICY.lock().unwrap_or_else(|e| e.into_inner());
+  rt_check::set_policy(Policy::LogOnce);
+  let (mut stream, states) = allocating_stream();
+  assert_eq!(stream.render(256).len(), 256);
+  // an allocation and a deallocation per period
+  assert_eq!(stream.rt_violations(), 8);
+  assert_eq!(stream.stats().rt_violations, 8);
+  assert_eq!(*states.borrow(), ["Started"]);
+}
+
+#[test]
+fn panic_policy_fails_the_stream_and_raises_on_the_caller() {
+  let _policy = POLICY.lock().unwrap_or_else(|e| e.into_inner());
+  rt_check::set_policy(Policy::Panic);
+  let (mut stream, states) = allocating_stream();
+  let mut out = Vec::new();
+  let caught = panic::catch_unwind(AssertUnwindSafe(|| stream.render_into(&mut out, 256)));
+  rt_check::set_policy(Policy::LogOnce);
+
+  let payload = caught.unwrap_err();
+  let message = payload.downcast_ref::<&str>().unwrap();
+  assert!(message.contains("heap operation"), "{}", message);
+  assert!(out.is_empty());
+  assert_eq!(*states.borrow(), ["Started", "Error"]);
+  assert!(stream.is_drained());
+  assert_eq!(stream.render(64).len(), 0);
+}
@@ -0,0 +1,54 @@
+extern crate cult;
+
+use cult::scheduler::scheduler;
+use cult::DataCallback;
+
+fn silence() -> DataCallback<f32> {
+  Box::new(|_: &[f32], obuf: &mut [f32]| {
+    for s in obuf.iter_mut() {
+      *s = 0.0;
+    }
+    obuf.len() / 2
+  })
+}
+
+#[test]
+fn buffers_start_at_their_frame() {
+  let (mut cb, mut sched, mut collector) = scheduler(2, 4, silence());
+  let click = vec![1.0_f32; 2 * 3];
+  let a = sched.schedule(5, click.clone()).unwrap();
+  sched.schedule(8, vec![0.5_f32; 2 * 2]).unwrap();
+
+  let mut out = vec![0_f32; 2 * 4];
+  assert_eq!(cb(&[], &mut out), 4);
+  assert!(out.iter().all(|&s| s == 0.0));
+  assert!(!a.is_done());
+
+  // frames 4..8: the click covers 5, 6 and 7
+  cb(&[], &mut out);
+  assert_eq!(out, vec![0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+  assert!(a.is_done());
+  assert_eq!(collector.collect(), 1);
+
+  // frames 8..12
+  cb(&[], &mut out);
+  assert_eq!(out, vec![0.5, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0]);
+  assert_eq!(sched.current_frame(), 12);
+}
+
+#[test]
+fn cancelled_buffers_stop() {
+  let (mut cb, mut sched, mut collector) = scheduler(2, 4, silence());
+  let long = sched.schedule(0, vec![1.0_f32; 2 * 100]).unwrap();
+  let later = sched.schedule(50, vec![1.0_f32; 2 * 10]).unwrap();
+
+  let mut out = vec![0_f32; 2 * 10];
+  cb(&[], &mut out);
+  assert!(out.iter().all(|&s| s == 1.0));
+  long.cancel();
+  later.cancel();
+  cb(&[], &mut out);
+  assert!(out.iter().all(|&s| s == 0.0));
+  assert!(long.is_done() && later.is_done());
+  assert_eq!(collector.collect(), 2);
+}
@@ -2,6 +2,8 @@ extern crate cult;
 
 use std::sync::{Arc, Mutex, Condvar};
 
+use cult::generator::{Generator, Oscillator, Waveform};
+
 #[test]
 fn sine() {
   let ctx = cult::Context::new("rust-cubeb", None).unwrap();
@@ -10,17 +12,11 @@ fn sine() {
 
   let &(ref m1, ref cv1) = &*p1;
   let g = m1.lock().unwrap();
-  let mut phase: Box<f32> = Box::new(0.0);
+  let mut sine = Oscillator::new(Waveform::Sine, 44100, 440_f32, 1_f32);
 
   let cb: cult::DataCallback<f32> = Box::new(move |_: &[f32], obuf: &mut [f32]| {
-    let w = std::f32::consts::PI * 2_f32 * 440_f32 / 44100_f32;
     let &(ref _m2, ref cv2) = &*p2;
-    for i in 0 .. obuf.len() {
-      for j in 0 .. 1 {
-        obuf[i + j] = (*phase).sin();
-      }
-      (*phase) += w;
-    }
+    sine.fill_frames(obuf, 1);
     assert!(obuf.len() != 0);
     cv2.notify_one();
     obuf.len()
@@ -0,0 +1,33 @@
+extern crate cult;
+
+use cult::stats::CallbackStats;
+use std::time::{Duration, Instant};
+
+#[test]
+fn load_and_xruns() {
+  // 480 frames at 48kHz: 10ms periods
+  let stats = CallbackStats::new(48000, false, true);
+  let t0 = Instant::now();
+  let ms = |n: u64| Duration::from_millis(n);
+
+  stats.record(t0, t0 + ms(2), 480, 480);
+  stats.record(t0 + ms(10), t0 + ms(15), 480, 480);
+  // late by a whole period, and too slow
+  stats.record(t0 + ms(30), t0 + ms(42), 480, 480);
+  stats.record(t0 + ms(40), t0 + ms(41), 480, 240);
+
+  let s = stats.snapshot();
+  assert_eq!(s.callbacks, 4);
+  assert_eq!(s.frames_requested, 4 * 480);
+  assert_eq!(s.frames_returned, 3 * 480 + 240);
+  assert_eq!(s.short_returns, 1);
+  assert_eq!(s.late_callbacks, 1);
+  assert_eq!(s.overloads, 1);
+  assert_eq!(s.underruns, 1);
+  assert_eq!(s.overruns, 0);
+  assert!((s.dsp_load - 50.0).abs() < 0.01);
+  assert!((s.peak_dsp_load - 120.0).abs() < 0.01);
+  assert_eq!(s.max_duration, ms(12));
+  assert_eq!(s.interval.count(), 3);
+  assert_eq!(s.duration.quantile(1.0), Duration::from_micros(16384));
+}
@@ -0,0 +1,86 @@
+extern crate cult;
+
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use cult::generator::Generator;
+use cult::tones::{countries, dtmf_frequencies, Tone, ToneGenerator, ToneSpec};
+
+const RATE: u32 = 48000;
+
+fn take(generator: &mut ToneGenerator, frames: usize) -> Vec<f32> {
+  (0 .. frames).map(|_| generator.next_sample()).collect()
+}
+
+fn rms(samples: &[f32]) -> f32 {
+  (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+// Power at `frequency`, relative to a full scale sine.
+fn power_at(samples: &[f32], frequency: f64) -> f64 {
+  let w = 2.0 * PI * frequency / RATE as f64;
+  let (mut re, mut im) = (0.0, 0.0);
+  for (n, &s) in samples.iter().enumerate() {
+    re += s as f64 * (w * n as f64).cos();
+    im -= s as f64 * (w * n as f64).sin();
+  }
+  let half = samples.len() as f64 / 2.0;
+  (re * re + im * im) / (half * half)
+}
+
+#[test]
+fn looks_tones_up_by_country() {
+  let busy = ToneSpec::lookup("us", Tone::Busy).unwrap();
+  assert_eq!(busy.frequencies, &[480.0, 620.0]);
+  assert_eq!(busy.cadence, &[500, 500]);
+  assert_eq!(ToneSpec::lookup("JP", Tone::Ringback).unwrap().modulation, Some(16.0));
+  assert!(ToneSpec::lookup("GB", Tone::Dial).unwrap().cadence.is_empty());
+  assert!(ToneSpec::lookup("XX", Tone::Dial).is_none());
+  assert!(countries().contains(&"DE"));
+  assert_eq!(dtmf_frequencies('5'), Some((770.0, 1336.0)));
+  assert_eq!(dtmf_frequencies('d'), Some((941.0, 1633.0)));
+  assert_eq!(dtmf_frequencies('x'), None);
+}
+
+#[test]
+fn cadences_are_timed_and_click_free() {
+  let mut busy = ToneGenerator::for_country("US", Tone::Busy, RATE, 0.5).unwrap();
+  let samples = take(&mut busy, 2 * RATE as usize);
+  let ms = |ms: usize| ms * RATE as usize / 1000;
+  for &start in &[0, 1000] {
+    // two sines of 0.25
+    let on = rms(&samples[ms(start + 100) .. ms(start + 400)]);
+    assert!((on - 0.25).abs() < 0.01, "{}", on);
+    assert!(samples[ms(start + 510) .. ms(start + 1000)].iter().all(|&s| s == 0.0));
+  }
+  let max_step = samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+  assert!(max_step < 0.5 * (2.0 * PI * 620.0 / RATE as f64) as f32 + 0.01, "{}", max_step);
+
+  // 1s on of 400Hz, modulated by 16Hz, then 2s off
+  let mut ring = ToneGenerator::for_country("JP", Tone::Ringback, RATE, 1.0).unwrap();
+  let samples = take(&mut ring, 3 * RATE as usize);
+  assert!(power_at(&samples[.. ms(1000)], 400.0) > 0.2);
+  assert!(power_at(&samples[.. ms(1000)], 416.0) > 0.04);
+  assert!(rms(&samples[ms(1010) ..]) == 0.0);
+  assert!(!ring.is_finished());
+}
+
+#[test]
+fn dtmf_keys_play_once_then_drain() {
+  let (on, off) = (Duration::from_millis(50), Duration::from_millis(50));
+  assert!(ToneGenerator::dtmf("12x", on, off, RATE, 0.5).is_none());
+  let keys = ToneGenerator::dtmf("1#", on, off, RATE, 0.5).unwrap();
+  let mut cb = keys.into_callback::<f32>(2);
+  let mut out = vec![0_f32; 2 * RATE as usize];
+  // two keys of 50ms on and 50ms off
+  assert_eq!(cb(&[], &mut out), 4800 * 2);
+  assert!(out.chunks(2).all(|f| f[0] == f[1]));
+
+  let mono: Vec<f32> = out.iter().step_by(2).cloned().collect();
+  let first = &mono[.. 2400];
+  assert!(power_at(first, 697.0) > 0.05 && power_at(first, 1209.0) > 0.05);
+  assert!(power_at(first, 941.0) < 0.001);
+  let second = &mono[4800 .. 7200];
+  assert!(power_at(second, 941.0) > 0.05 && power_at(second, 1477.0) > 0.05);
+  assert!(power_at(second, 697.0) < 0.001);
+}
@@ -0,0 +1,42 @@
+extern crate cult;
+
+use cult::triple_buffer::triple_buffer;
+use std::thread;
+
+#[test]
+fn reads_the_latest_value() {
+  let (mut w, mut r) = triple_buffer(0_u32);
+  assert!(!r.has_update());
+  assert_eq!(*r.read(), 0);
+  w.write(1);
+  w.write(2);
+  assert!(r.has_update());
+  assert_eq!(*r.read(), 2);
+  assert!(!r.has_update());
+  assert_eq!(*r.read(), 2);
+  *w.back() = 3;
+  assert_eq!(*r.read(), 2);
+  w.publish();
+  assert_eq!(*r.read(), 3);
+}
+
+#[test]
+fn values_are_never_torn() {
+  let (mut w, mut r) = triple_buffer(vec![0_u64; 16]);
+  let writer = thread::spawn(move || {
+    for i in 1 ..= 100_000 {
+      for v in w.back().iter_mut() {
+        *v = i;
+      }
+      w.publish();
+    }
+  });
+  let mut last = 0;
+  while last < 100_000 {
+    let values = r.read();
+    assert!(values.iter().all(|&v| v == values[0]));
+    assert!(values[0] >= last);
+    last = values[0];
+  }
+  writer.join().unwrap();
+}
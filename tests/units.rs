@@ -0,0 +1,30 @@
+extern crate cult;
+
+use cult::units::{Frames, Latency, Samples};
+use std::time::Duration;
+
+#[test]
+fn frames_samples_and_durations() {
+  assert_eq!(Frames(480).to_samples(2), Samples(960));
+  assert_eq!(Samples(961).to_frames(2), Frames(480));
+  assert_eq!(Frames(480).to_duration(48000), Duration::from_millis(10));
+  assert_eq!(Frames::from_duration(Duration::from_millis(10), 44100), Frames(441));
+  // 1/3 ms at 48kHz is 16 frames, rounded
+  assert_eq!(Frames::from_duration(Duration::from_nanos(333_333), 48000), Frames(16));
+  assert_eq!(Frames(441).at_rate(44100, 48000), Frames(480));
+  assert_eq!(Frames(1).at_rate(48000, 44100), Frames(1));
+}
+
+#[test]
+fn arithmetic_saturates_at_zero() {
+  assert_eq!(Frames(480) + Frames(32) - Frames(12), Frames(500));
+  assert_eq!(Frames(12) - Frames(480), Frames(0));
+  assert_eq!(Samples(2) - Samples(3), Samples(0));
+}
+
+#[test]
+fn latencies() {
+  assert_eq!(Latency::from(256_u32).to_frames(48000), Frames(256));
+  assert_eq!(Latency::from(Duration::from_millis(20)).to_frames(48000), Frames(960));
+  assert_eq!(Latency::from(Frames(64)).to_frames(8000), Frames(64));
+}
@@ -0,0 +1,100 @@
+extern crate cult;
+
+use std::fs;
+use std::io::Cursor;
+
+use cult::ChannelLayout;
+use cult::wav::{self, Encoding, Spec, WavReader, WavWriter};
+
+fn ramp(samples: usize) -> Vec<f32> {
+  (0 .. samples).map(|i| (i as f32 / samples as f32) * 1.5 - 0.75).collect()
+}
+
+#[test]
+fn every_encoding_round_trips() {
+  let input = ramp(2 * 10_000);
+  for &(encoding, tolerance) in [(Encoding::Pcm16, 1.0 / 32768.0),
+                                 (Encoding::Pcm24, 1.0 / 8388608.0),
+                                 (Encoding::Pcm32, 1e-7),
+                                 (Encoding::Float32, 0.0)].iter() {
+    let spec = Spec::new(44100, 2, encoding);
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+    writer.write(&input[.. 2 * 3]).unwrap();
+    writer.write(&input[2 * 3 ..]).unwrap();
+    let file = writer.finalize().unwrap().into_inner();
+
+    let mut reader = WavReader::new(Cursor::new(file)).unwrap();
+    assert_eq!(reader.spec(), spec);
+    assert_eq!(reader.frames(), 10_000);
+    let output: Vec<f32> = reader.read_to_end().unwrap();
+    assert_eq!(output.len(), input.len());
+    for (a, b) in input.iter().zip(output.iter()) {
+      assert!((a - b).abs() <= tolerance, "{:?}: {} != {}", encoding, a, b);
+    }
+
+    reader.seek(9_998).unwrap();
+    let mut tail = [0_i16; 8];
+    assert_eq!(reader.read(&mut tail).unwrap(), 2);
+    assert_eq!(reader.read(&mut tail).unwrap(), 0);
+  }
+}
+
+#[test]
+fn channel_masks_map_to_layouts() {
+  let spec = Spec::new(48000, 6, Encoding::Pcm16).with_layout(ChannelLayout::F3_2_LFE);
+  let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+  writer.write(&[0_i16; 6 * 4]).unwrap();
+  let file = writer.finalize().unwrap().into_inner();
+  assert_eq!(WavReader::new(Cursor::new(file)).unwrap().spec().layout, ChannelLayout::F3_2_LFE);
+
+  assert_eq!(wav::mask_from_layout(ChannelLayout::F3_2_LFE), 0x60F);
+  // 5.1 on the back speakers
+  assert_eq!(wav::layout_from_mask(0x3F, 6), ChannelLayout::F3_2_LFE);
+  assert_eq!(wav::layout_from_mask(0x3, 2), ChannelLayout::Stereo);
+  assert_eq!(wav::layout_from_mask(0x3, 3), ChannelLayout::Undefined);
+}
+
+#[test]
+fn cut_off_recordings_are_readable_and_repaired() {
+  let path = std::env::temp_dir().join(format!("cult-wav-test-{}.wav", std::process::id()));
+  let spec = Spec::new(8000, 1, Encoding::Pcm24);
+  let mut writer = WavWriter::create(&path, spec).unwrap();
+  writer.write(&ramp(1001)).unwrap();
+  writer.flush().unwrap();
+  assert_eq!(WavReader::open(&path).unwrap().frames(), 1001);
+  writer.write(&ramp(500)).unwrap();
+  drop(writer);
+
+  // as if the process died before patching anything, mid-frame
+  let mut bytes = fs::read(&path).unwrap();
+  bytes.truncate(bytes.len() - 1);
+  bytes[4 .. 8].copy_from_slice(&[0xFF; 4]);
+  let data = bytes.len() - 3 * 1500 - 2;
+  bytes[data - 4 .. data].copy_from_slice(&[0xFF; 4]);
+  fs::write(&path, &bytes).unwrap();
+
+  assert_eq!(WavReader::open(&path).unwrap().frames(), 1500);
+  assert_eq!(wav::repair(&path).unwrap(), 1500);
+  let repaired = fs::read(&path).unwrap();
+  assert_eq!(repaired.len() % 2, 0);
+  assert_eq!(&repaired[data - 4 .. data], &(3_u32 * 1500).to_le_bytes());
+  assert_eq!(wav::repair(&path).unwrap(), 1500);
+  fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn bad_files_are_rejected() {
+  match WavReader::new(Cursor::new(b"RIFX\0\0\0\0WAVE".to_vec())) {
+    Err(wav::Error::Format(_)) => {}
+    _ => panic!("expected a format error"),
+  }
+  let mut file = WavWriter::new(Cursor::new(Vec::new()), Spec::new(8000, 1, Encoding::Pcm16))
+    .unwrap().finalize().unwrap().into_inner();
+  // 8 bit
+  file[34] = 8;
+  file[32] = 1;
+  match WavReader::new(Cursor::new(file)) {
+    Err(wav::Error::Unsupported(_)) => {}
+    _ => panic!("expected an unsupported error"),
+  }
+}